@@ -1,5 +1,9 @@
 fn main() {
     // println!("cargo:rerun-if-changed=src/proto/types.proto");
-    prost_build::compile_protos(&["src/proto/types.proto"], &["src/proto"])
+    tonic_build::configure()
+        .compile_protos(
+            &["src/proto/types.proto", "src/proto/network.proto", "src/proto/node.proto"],
+            &["src/proto"],
+        )
         .expect("Failed to compile proto");
-}
\ No newline at end of file
+}
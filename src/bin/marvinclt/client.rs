@@ -0,0 +1,180 @@
+use marvin_blockchain::error::{Result, MarvinError};
+
+use serde_json::{json, Value};
+
+use std::time::Duration;
+
+/// Default endpoint of the JSON-RPC API of a node
+pub const DEFAULT_RPC_URL: &str = "http://127.0.0.1:8545";
+/// Time to wait for the answer of the node
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// RpcClient calls the JSON-RPC API of a node over HTTP
+pub struct RpcClient {
+    url: String,
+    agent: ureq::Agent,
+    next_id: u64,
+}
+
+impl RpcClient {
+    pub fn new(url: &str) -> Self {
+        RpcClient {
+            url: url.to_string(),
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+            next_id: 1,
+        }
+    }
+
+    /// Call a method of the node and return its result.
+    /// Errors returned by the node are rebuilt from their stable code, see `MarvinError::from_code`.
+    pub fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let response: Value = self
+            .agent
+            .post(&self.url)
+            .send_json(request)
+            .map_err(|e| MarvinError::Network(format!("Request to {} failed: {}", self.url, e)))?
+            .into_json()
+            .map_err(|e| MarvinError::Network(format!("Invalid response from {}: {}", self.url, e)))?;
+
+        if let Some(error) = response.get("error") {
+            let code = error["code"].as_i64().unwrap_or_default();
+            let message = error["message"].as_str().unwrap_or_default();
+            return Err(MarvinError::from_code(code, message));
+        }
+
+        response
+            .get("result")
+            .cloned()
+            .ok_or_else(|| MarvinError::Network(format!("Response from {} has no result", self.url)))
+    }
+
+    pub fn height(&mut self) -> Result<u64> {
+        let height = self.call("marvin_getHeight", json!([]))?;
+        height.as_u64().ok_or_else(|| invalid_result("marvin_getHeight"))
+    }
+
+    /// Returns the block of the main chain at a height, `None` if there is no such block
+    pub fn block_by_height(&mut self, height: u64) -> Result<Option<Value>> {
+        self.call("marvin_getBlockByHeight", json!([height])).map(found)
+    }
+
+    /// Returns a block given its hex encoded hash, `None` if the node does not have it
+    pub fn block_by_hash(&mut self, hash: &str) -> Result<Option<Value>> {
+        self.call("marvin_getBlockByHash", json!([hash])).map(found)
+    }
+
+    /// Returns a pending or mined transaction given its hex encoded hash, `None` if the node does not know it
+    pub fn transaction(&mut self, hash: &str) -> Result<Option<Value>> {
+        self.call("marvin_getTransactionByHash", json!([hash])).map(found)
+    }
+
+    pub fn balance(&mut self, address: &str) -> Result<u64> {
+        let balance = self.call("marvin_getBalance", json!([address]))?;
+        balance.as_u64().ok_or_else(|| invalid_result("marvin_getBalance"))
+    }
+
+    /// Returns the nonce of the next transaction of an address, pending transactions included
+    pub fn nonce(&mut self, address: &str) -> Result<u64> {
+        let nonce = self.call("marvin_getNonce", json!([address]))?;
+        nonce.as_u64().ok_or_else(|| invalid_result("marvin_getNonce"))
+    }
+
+    /// Submit a hex encoded protobuf transaction, returning its hash
+    pub fn send_transaction(&mut self, data: &str) -> Result<String> {
+        let hash = self.call("marvin_sendTransaction", json!([data]))?;
+        hash.as_str().map(String::from).ok_or_else(|| invalid_result("marvin_sendTransaction"))
+    }
+}
+
+fn found(result: Value) -> Option<Value> {
+    Some(result).filter(|result| !result.is_null())
+}
+
+fn invalid_result(method: &str) -> MarvinError {
+    MarvinError::Network(format!("Invalid result of {}", method))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve the given JSON-RPC responses, one per connection, returning the requests received
+    fn serve(responses: Vec<Value>) -> (String, thread::JoinHandle<Vec<Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                requests.push(serde_json::from_slice(&body).unwrap());
+
+                let body = response.to_string();
+                let mut stream = reader.into_inner();
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).unwrap();
+            }
+            requests
+        });
+
+        (url, server)
+    }
+
+    #[test]
+    fn test_call() {
+        let (url, server) = serve(vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "result": 42 }),
+            json!({ "jsonrpc": "2.0", "id": 2, "result": null }),
+            json!({ "jsonrpc": "2.0", "id": 3, "error": { "code": -32012, "message": "Mempool rejected transaction: Nonce too low" } }),
+        ]);
+        let mut client = RpcClient::new(&url);
+
+        assert_eq!(client.height().unwrap(), 42);
+        assert_eq!(client.block_by_height(7).unwrap(), None);
+        let error = client.send_transaction("00").unwrap_err();
+        assert_eq!(error, MarvinError::MempoolRejected(String::from("Nonce too low")));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0]["method"], json!("marvin_getHeight"));
+        assert_eq!(requests[1]["params"], json!([7]));
+        assert_eq!(requests[2]["id"], json!(3));
+
+        // Nothing is listening anymore
+        assert!(matches!(client.height(), Err(MarvinError::Network(_))));
+    }
+
+    #[test]
+    fn test_https_url() {
+        // The listener closes the connection right away, but the client gets as far as starting a TLS handshake
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = RpcClient::new(&format!("https://{}", listener.local_addr().unwrap()));
+        let server = thread::spawn(move || drop(listener.accept().unwrap()));
+
+        match client.height() {
+            Err(MarvinError::Network(message)) => assert!(!message.contains("Unknown Scheme"), "{}", message),
+            result => panic!("unexpected result {:?}", result),
+        }
+        server.join().unwrap();
+    }
+}
@@ -1,15 +1,276 @@
-use clap::{arg, Command};
+mod client;
+mod output;
+mod tx_file;
+mod wallet;
+
+use client::{RpcClient, DEFAULT_RPC_URL};
+use output::{format_account, format_block, format_transaction, format_transaction_file, format_value, OutputFormat};
+use tx_file::TransactionFile;
+
+use marvin_blockchain::error::{Result, MarvinError};
+use marvin_blockchain::proto;
+use marvin_blockchain::types::transaction::verify_transaction;
+
+use clap::{Arg, ArgGroup, ArgMatches, Command};
+use prost::Message;
+use serde_json::json;
+
+use std::path::Path;
 
 pub fn start_cli() -> Command {
-    Command::new("marvinctl")
-        .about("marvinclt is a CLI tool for the Marvin Blockchain")
+    Command::new("marvinclt")
+        .about("marvinclt is a client for the JSON-RPC API of a Marvin node")
         .subcommand_required(true)
         .arg_required_else_help(true)
-        .allow_external_subcommands(true)
         .version("0.1.0")
+        .arg(
+            Arg::new("rpc-url")
+                .long("rpc-url")
+                .env("MARVIN_RPC_URL")
+                .default_value(DEFAULT_RPC_URL)
+                .global(true)
+                .help("URL of the JSON-RPC API of the node"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true)
+                .help("Format of the results"),
+        )
+        .subcommand(Command::new("height").about("Print the height of the chain"))
         .subcommand(
-            Command::new("test")
-                .about("Test the CLI")
-                .arg(arg!(-t --test ... "Test mode")),
+            Command::new("block")
+                .about("Print a block given its height or its hash")
+                .arg(Arg::new("block").required(true).help("Height or hex encoded hash of the block")),
         )
+        .subcommand(
+            Command::new("balance")
+                .about("Print the balance and the next nonce of an address")
+                .arg(Arg::new("address").required(true).help("Hex encoded address")),
+        )
+        .subcommand(
+            Command::new("tx")
+                .about("Query and submit transactions")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("get")
+                        .about("Print a pending or mined transaction")
+                        .arg(Arg::new("hash").required(true).help("Hex encoded hash of the transaction")),
+                )
+                .subcommand(
+                    Command::new("submit")
+                        .about("Submit a signed transaction, printing its hash")
+                        .arg(Arg::new("transaction").required(true).help("Hex encoded protobuf transaction")),
+                )
+                .subcommand(key_args(
+                    Command::new("send")
+                        .about("Sign a transaction with the next nonce of the sender and submit it, printing its hash")
+                        .arg(Arg::new("to").long("to").required(true).help("Hex encoded public key of the recipient"))
+                        .arg(value_arg())
+                        .arg(data_arg())
+                        .arg(valid_until_height_arg())
+                        .arg(valid_until_timestamp_arg()),
+                ))
+                .subcommand(
+                    Command::new("build")
+                        .about("Build an unsigned transaction file, to be signed offline with tx sign")
+                        .arg(Arg::new("to").long("to").required(true).help("Hex encoded public key of the recipient"))
+                        .arg(value_arg())
+                        .arg(data_arg())
+                        .arg(valid_until_height_arg())
+                        .arg(valid_until_timestamp_arg())
+                        .arg(
+                            Arg::new("from")
+                                .long("from")
+                                .help("Hex encoded public key of the sender, its next nonce is fetched from the node unless --nonce is given"),
+                        )
+                        .arg(
+                            Arg::new("nonce")
+                                .long("nonce")
+                                .value_parser(clap::value_parser!(u64))
+                                .help("Nonce of the transaction, the node is not contacted"),
+                        )
+                        .group(ArgGroup::new("sender").args(["from", "nonce"]).multiple(true).required(true))
+                        .arg(out_arg()),
+                )
+                .subcommand(key_args(
+                    Command::new("sign")
+                        .about("Sign a transaction file without contacting the node")
+                        .arg(Arg::new("file").required(true).help("Transaction file built with tx build"))
+                        .arg(out_arg()),
+                ))
+                .subcommand(
+                    Command::new("broadcast")
+                        .about("Submit a signed transaction file, printing its hash")
+                        .arg(Arg::new("file").required(true).help("Transaction file signed with tx sign")),
+                ),
+        )
+}
+
+/// Add the arguments selecting the key signing a transaction
+fn key_args(command: Command) -> Command {
+    command
+        .arg(Arg::new("from-mnemonic").long("from-mnemonic").help("Mnemonic of the sender"))
+        .arg(
+            Arg::new("keystore")
+                .long("keystore")
+                .help("Keystore of the sender, its password is read from MARVIN_KEYSTORE_PASSWORD or prompted for"),
+        )
+        .group(ArgGroup::new("key").args(["from-mnemonic", "keystore"]).required(true))
+}
+
+fn value_arg() -> Arg {
+    Arg::new("value")
+        .long("value")
+        .required(true)
+        .value_parser(clap::value_parser!(u64))
+        .help("Amount to send")
+}
+
+fn data_arg() -> Arg {
+    Arg::new("data").long("data").default_value("").help("Hex encoded data of the transaction")
+}
+
+fn valid_until_height_arg() -> Arg {
+    Arg::new("valid-until-height")
+        .long("valid-until-height")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .help("Last block height the transaction can be mined at, it does not expire by height if not given")
+}
+
+fn valid_until_timestamp_arg() -> Arg {
+    Arg::new("valid-until-timestamp")
+        .long("valid-until-timestamp")
+        .value_parser(clap::value_parser!(i64).range(1..))
+        .help("Last block timestamp the transaction can be mined at, as a Unix timestamp in nanoseconds")
+}
+
+fn out_arg() -> Arg {
+    Arg::new("out").long("out").help("File to write the transaction to, printed if not given")
+}
+
+fn main() {
+    let matches = start_cli().get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<()> {
+    let mut client = RpcClient::new(matches.get_one::<String>("rpc-url").unwrap());
+    let output = OutputFormat::from_name(matches.get_one::<String>("output").unwrap());
+
+    match matches.subcommand() {
+        Some(("height", _)) => output.print(&json!(client.height()?), format_value),
+        Some(("block", block_matches)) => {
+            let id = block_matches.get_one::<String>("block").unwrap();
+            let block = match id.parse::<u64>() {
+                Ok(height) => client.block_by_height(height)?,
+                Err(_) => client.block_by_hash(id)?,
+            };
+            let block = block.ok_or_else(|| MarvinError::NotFound(format!("Block {} not found", id)))?;
+            output.print(&block, format_block);
+        }
+        Some(("balance", balance_matches)) => {
+            let address = balance_matches.get_one::<String>("address").unwrap();
+            let account = json!({
+                "address": address,
+                "balance": client.balance(address)?,
+                "nonce": client.nonce(address)?,
+            });
+            output.print(&account, format_account);
+        }
+        Some(("tx", tx_matches)) => match tx_matches.subcommand() {
+            Some(("get", get_matches)) => {
+                let hash = get_matches.get_one::<String>("hash").unwrap();
+                let tx = client
+                    .transaction(hash)?
+                    .ok_or_else(|| MarvinError::NotFound(format!("Transaction {} not found", hash)))?;
+                output.print(&tx, format_transaction);
+            }
+            Some(("submit", submit_matches)) => {
+                let hash = client.send_transaction(submit_matches.get_one::<String>("transaction").unwrap())?;
+                output.print(&json!(hash), format_value);
+            }
+            Some(("send", send_matches)) => {
+                let mut private_key = wallet::signing_key(send_matches)?;
+                let sender = private_key.public_key().address().to_string();
+                let mut tx = build_transaction(send_matches, client.nonce(&sender)?)?;
+
+                wallet::sign(&mut private_key, &mut tx)?;
+                let hash = client.send_transaction(&hex::encode(tx.encode_to_vec()))?;
+                output.print(&json!(hash), format_value);
+            }
+            Some(("build", build_matches)) => {
+                let from = build_matches.get_one::<String>("from").map(|from| wallet::parse_public_key(from)).transpose()?;
+                let nonce = match (build_matches.get_one::<u64>("nonce"), &from) {
+                    (Some(nonce), _) => *nonce,
+                    (None, Some(from)) => client.nonce(&from.address().to_string())?,
+                    (None, None) => unreachable!("sender required"),
+                };
+
+                let mut tx = build_transaction(build_matches, nonce)?;
+                tx.from = from.map(|from| from.to_bytes().to_vec()).unwrap_or_default();
+                write_transaction_file(build_matches, &TransactionFile::new(&tx), output)?;
+            }
+            Some(("sign", sign_matches)) => {
+                let mut tx = TransactionFile::load(Path::new(sign_matches.get_one::<String>("file").unwrap()))?.transaction()?;
+                let mut private_key = wallet::signing_key(sign_matches)?;
+
+                let public_key = private_key.public_key();
+                if !tx.from.is_empty() && tx.from != public_key.to_bytes() {
+                    return Err(MarvinError::Validation(format!(
+                        "The transaction was built for the sender {}, not {}",
+                        hex::encode(&tx.from),
+                        public_key
+                    )));
+                }
+
+                wallet::sign(&mut private_key, &mut tx)?;
+                write_transaction_file(sign_matches, &TransactionFile::new(&tx), output)?;
+            }
+            Some(("broadcast", broadcast_matches)) => {
+                let file = TransactionFile::load(Path::new(broadcast_matches.get_one::<String>("file").unwrap()))?;
+                verify_transaction(&mut file.transaction()?)?;
+
+                let hash = client.send_transaction(&file.transaction)?;
+                output.print(&json!(hash), format_value);
+            }
+            _ => unreachable!("subcommand required"),
+        },
+        _ => unreachable!("subcommand required"),
+    }
+
+    Ok(())
+}
+
+/// Build an unsigned transaction from the `to`, `value`, `data` and expiry arguments
+fn build_transaction(matches: &ArgMatches, nonce: u64) -> Result<proto::Transaction> {
+    let to = wallet::parse_public_key(matches.get_one::<String>("to").unwrap())?;
+    let data = wallet::decode_hex(matches.get_one::<String>("data").unwrap())?;
+
+    let mut tx = wallet::new_transaction(&to, *matches.get_one::<u64>("value").unwrap(), data, nonce)?;
+    tx.valid_until_height = matches.get_one::<u64>("valid-until-height").copied().unwrap_or_default();
+    tx.valid_until_timestamp = matches.get_one::<i64>("valid-until-timestamp").copied().unwrap_or_default();
+
+    Ok(tx)
+}
+
+/// Write a transaction file to the `out` argument, printing a summary of it, or print the file itself
+fn write_transaction_file(matches: &ArgMatches, file: &TransactionFile, output: OutputFormat) -> Result<()> {
+    match matches.get_one::<String>("out") {
+        Some(path) => {
+            file.save(Path::new(path))?;
+            output.print(&json!(file), format_transaction_file);
+        }
+        None => println!("{}", file.to_json()),
+    }
+
+    Ok(())
 }
@@ -0,0 +1,155 @@
+use serde_json::Value;
+
+/// Format of the results printed by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable text
+    Text,
+    /// The JSON returned by the node, pretty printed
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> OutputFormat {
+        match name {
+            "json" => OutputFormat::Json,
+            _ => OutputFormat::Text,
+        }
+    }
+
+    /// Print a result, formatted as text with the given function
+    pub fn print(&self, result: &Value, text: fn(&Value) -> String) {
+        match self {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(result).unwrap_or_default()),
+            OutputFormat::Text => println!("{}", text(result)),
+        }
+    }
+}
+
+/// Format a value printed on its own, such as a height or a hash
+pub fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+pub fn format_block(block: &Value) -> String {
+    let transactions = block["transactions"].as_array().cloned().unwrap_or_default();
+
+    let mut lines = vec![
+        format!("hash:         {}", format_value(&block["hash"])),
+        format!("height:       {}", format_value(&block["height"])),
+        format!("prev block:   {}", format_value(&block["prevBlockHash"])),
+        format!("timestamp:    {}", format_timestamp(&block["timestamp"])),
+        format!("difficulty:   {}", format_value(&block["difficulty"])),
+        format!("nonce:        {}", format_value(&block["nonce"])),
+        format!("tx hash:      {}", format_value(&block["txHash"])),
+        format!("miner:        {}", format_value(&block["publicKey"])),
+        format!("transactions: {}", transactions.len()),
+    ];
+    lines.extend(transactions.iter().map(|tx| format!("  {}", format_value(&tx["hash"]))));
+
+    lines.join("\n")
+}
+
+pub fn format_transaction(tx: &Value) -> String {
+    let status = match tx["blockHeight"].as_u64() {
+        Some(height) => format!("mined in block {} at height {}", format_value(&tx["blockHash"]), height),
+        None => String::from("pending"),
+    };
+
+    [
+        format!("hash:   {}", format_value(&tx["hash"])),
+        format!("status: {}", status),
+        format!("from:   {}", format_value(&tx["from"])),
+        format!("to:     {}", format_value(&tx["to"])),
+        format!("value:  {}", format_value(&tx["value"])),
+        format!("nonce:  {}", format_value(&tx["nonce"])),
+        format!("data:   {}", format_value(&tx["data"])),
+    ]
+    .join("\n")
+}
+
+/// Format a transaction file, showing what is signed or broadcast
+pub fn format_transaction_file(file: &Value) -> String {
+    let hash = match file["signed"].as_bool() {
+        Some(true) => format_value(&file["hash"]),
+        _ => String::from("unsigned"),
+    };
+
+    let mut expiry = Vec::new();
+    if let Some(height) = file["valid_until_height"].as_u64().filter(|height| *height != 0) {
+        expiry.push(format!("height {}", height));
+    }
+    if file["valid_until_timestamp"].as_i64().is_some_and(|timestamp| timestamp != 0) {
+        expiry.push(format!("timestamp {}", format_timestamp(&file["valid_until_timestamp"])));
+    }
+    let expires = match expiry.is_empty() {
+        true => String::from("never"),
+        false => format!("after {}", expiry.join(" or ")),
+    };
+
+    [
+        format!("hash:    {}", hash),
+        format!("from:    {}", format_value(&file["from"])),
+        format!("to:      {}", format_value(&file["to"])),
+        format!("value:   {}", format_value(&file["value"])),
+        format!("nonce:   {}", format_value(&file["nonce"])),
+        format!("data:    {}", format_value(&file["data"])),
+        format!("expires: {}", expires),
+    ]
+    .join("\n")
+}
+
+pub fn format_account(account: &Value) -> String {
+    [
+        format!("address: {}", format_value(&account["address"])),
+        format!("balance: {}", format_value(&account["balance"])),
+        format!("nonce:   {}", format_value(&account["nonce"])),
+    ]
+    .join("\n")
+}
+
+/// Format a timestamp in nanoseconds as an RFC 3339 date
+fn format_timestamp(timestamp: &Value) -> String {
+    match timestamp.as_i64() {
+        Some(nanos) => chrono::DateTime::from_timestamp_nanos(nanos).to_rfc3339(),
+        None => format_value(timestamp),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_value(&json!("ab12")), "ab12");
+        assert_eq!(format_value(&json!(12)), "12");
+
+        let block = json!({
+            "hash": "aa", "height": 3, "prevBlockHash": "bb", "timestamp": 1722470400000000000i64, "difficulty": 8,
+            "nonce": 5, "txHash": "cc", "publicKey": "dd", "transactions": [{ "hash": "ee" }],
+        });
+        let text = format_block(&block);
+        assert!(text.contains("timestamp:    2024-08-01T00:00:00+00:00"));
+        assert!(text.ends_with("transactions: 1\n  ee"));
+
+        let tx = json!({ "hash": "ee", "from": "01", "to": "02", "value": 10, "nonce": 0, "data": "" });
+        assert!(format_transaction(&tx).contains("status: pending"));
+        let mined = json!({ "hash": "ee", "blockHash": "aa", "blockHeight": 3 });
+        assert!(format_transaction(&mined).contains("status: mined in block aa at height 3"));
+
+        let file = json!({ "signed": false, "hash": "", "from": "", "to": "02", "value": 10, "nonce": 0, "data": "" });
+        assert!(format_transaction_file(&file).starts_with("hash:    unsigned\nfrom:    \nto:      02"));
+        assert!(format_transaction_file(&file).ends_with("expires: never"));
+        let expiring = json!({ "valid_until_height": 120, "valid_until_timestamp": 1722470400000000000i64 });
+        assert!(format_transaction_file(&expiring).ends_with("expires: after height 120 or timestamp 2024-08-01T00:00:00+00:00"));
+
+        let account = json!({ "address": "ff", "balance": 100, "nonce": 2 });
+        assert_eq!(format_account(&account), "address: ff\nbalance: 100\nnonce:   2");
+        assert_eq!(OutputFormat::from_name("json"), OutputFormat::Json);
+    }
+}
@@ -0,0 +1,121 @@
+use marvin_blockchain::error::{Result, MarvinError};
+use marvin_blockchain::proto;
+use marvin_blockchain::types::transaction::deserialize_transaction;
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+
+/// TransactionFile is a transaction stored as JSON, moved between an online machine and an offline one signing it.
+/// `transaction` is the hex encoded protobuf transaction, the other fields are a readable copy of it which must match
+/// when the file is read, so what is shown before signing is what gets signed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionFile {
+    pub transaction: String,
+    pub signed: bool,
+    /// Hash of the transaction, empty until it is signed
+    pub hash: String,
+    /// Public key of the sender, empty if it is only known once signed
+    pub from: String,
+    pub to: String,
+    pub value: u64,
+    pub nonce: i64,
+    pub data: String,
+    /// Last height and Unix timestamp in nanoseconds of a block including the transaction, 0 if it does not expire
+    #[serde(default)]
+    pub valid_until_height: u64,
+    #[serde(default)]
+    pub valid_until_timestamp: i64,
+}
+
+impl TransactionFile {
+    pub fn new(tx: &proto::Transaction) -> TransactionFile {
+        TransactionFile {
+            transaction: hex::encode(tx.encode_to_vec()),
+            signed: !tx.signature.is_empty(),
+            hash: hex::encode(&tx.hash),
+            from: hex::encode(&tx.from),
+            to: hex::encode(&tx.to),
+            value: tx.value,
+            nonce: tx.nonce,
+            data: hex::encode(&tx.data),
+            valid_until_height: tx.valid_until_height,
+            valid_until_timestamp: tx.valid_until_timestamp,
+        }
+    }
+
+    /// Decode the transaction, failing if the readable fields do not match it
+    pub fn transaction(&self) -> Result<proto::Transaction> {
+        let data = hex::decode(&self.transaction).map_err(|e| MarvinError::Validation(format!("Invalid transaction: {}", e)))?;
+        let tx = deserialize_transaction(&data)?;
+
+        if TransactionFile::new(&tx) != *self {
+            return Err(MarvinError::Validation(String::from(
+                "The fields of the transaction file do not match its encoded transaction",
+            )));
+        }
+
+        Ok(tx)
+    }
+
+    pub fn load(path: &Path) -> Result<TransactionFile> {
+        let data = fs::read(path).map_err(|e| MarvinError::Storage(format!("Failed to read {}: {}", path.display(), e)))?;
+        serde_json::from_slice(&data).map_err(|e| MarvinError::Validation(format!("Invalid transaction file {}: {}", path.display(), e)))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json()).map_err(|e| MarvinError::Storage(format!("Failed to write {}: {}", path.display(), e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use marvin_blockchain::crypto::keys;
+    use crate::wallet;
+
+    #[test]
+    fn test_transaction_file() {
+        let recipient = keys::generate_private_key().public_key();
+        let mut tx = wallet::new_transaction(&recipient, 10, vec![7], 2).unwrap();
+        tx.valid_until_height = 120;
+
+        let unsigned = TransactionFile::new(&tx);
+        assert!(!unsigned.signed);
+        assert_eq!(unsigned.hash, "");
+        assert_eq!(unsigned.to, recipient.to_string());
+        assert_eq!((unsigned.valid_until_height, unsigned.valid_until_timestamp), (120, 0));
+        assert_eq!(unsigned.transaction().unwrap(), tx);
+
+        let path = std::env::temp_dir().join(format!("marvin-tx-{}.json", hex::encode(keys::new_entropy())));
+        unsigned.save(&path).unwrap();
+        assert_eq!(TransactionFile::load(&path).unwrap(), unsigned);
+        fs::remove_file(&path).unwrap();
+
+        let mut private_key = keys::generate_private_key();
+        let hash = wallet::sign(&mut private_key, &mut tx).unwrap();
+        let signed = TransactionFile::new(&tx);
+        assert!(signed.signed);
+        assert_eq!(signed.hash, hash);
+        assert_eq!(signed.from, private_key.public_key().to_string());
+
+        // Editing the readable copy does not change what is signed
+        let mut edited = unsigned.clone();
+        edited.value = 1000;
+        assert!(matches!(edited.transaction(), Err(MarvinError::Validation(_))));
+
+        edited = unsigned.clone();
+        edited.valid_until_height = 0;
+        assert!(matches!(edited.transaction(), Err(MarvinError::Validation(_))));
+
+        edited = unsigned.clone();
+        edited.transaction = String::from("zz");
+        assert!(edited.transaction().is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use marvin_blockchain::crypto::keys::{self, PrivateKey, PublicKey};
+use marvin_blockchain::crypto::keystore::{self, Keystore};
+use marvin_blockchain::error::{Result, MarvinError};
+use marvin_blockchain::proto;
+use marvin_blockchain::types::transaction::sign_transaction;
+
+use clap::ArgMatches;
+
+use std::path::Path;
+
+/// Returns the key selected by the `from-mnemonic` or the `keystore` argument.
+/// The password of a keystore is read from `MARVIN_KEYSTORE_PASSWORD` or prompted for.
+pub fn signing_key(matches: &ArgMatches) -> Result<PrivateKey> {
+    if let Some(mnemonic) = matches.get_one::<String>("from-mnemonic") {
+        return keys::get_private_key_from_mnemonic(mnemonic);
+    }
+
+    let path = matches
+        .get_one::<String>("keystore")
+        .ok_or_else(|| MarvinError::Validation(String::from("A mnemonic or a keystore is required to sign")))?;
+    let keystore = Keystore::load(Path::new(path))?;
+    let password = keystore::read_password(&format!("Password of {}: ", keystore.address))?;
+
+    keystore.decrypt(&password)
+}
+
+/// Parse the hex encoded public key of a recipient
+pub fn parse_public_key(value: &str) -> Result<PublicKey> {
+    let bytes = decode_hex(value).map_err(|_| MarvinError::Validation(format!("Invalid recipient public key {}", value)))?;
+    PublicKey::from_bytes(&bytes)
+}
+
+/// Decode a hex string, with or without a `0x` prefix
+pub fn decode_hex(value: &str) -> Result<Vec<u8>> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    hex::decode(value).map_err(|e| MarvinError::Validation(format!("Invalid hex data {}: {}", value, e)))
+}
+
+/// Build an unsigned transaction sending `value` to the owner of a public key
+pub fn new_transaction(to: &PublicKey, value: u64, data: Vec<u8>, nonce: u64) -> Result<proto::Transaction> {
+    let nonce = i64::try_from(nonce).map_err(|_| MarvinError::Validation(format!("Invalid nonce {}", nonce)))?;
+
+    Ok(proto::Transaction {
+        to: to.to_bytes().to_vec(),
+        value,
+        data,
+        nonce,
+        ..Default::default()
+    })
+}
+
+/// Sign a transaction, returning its hex encoded hash
+pub fn sign(private_key: &mut PrivateKey, tx: &mut proto::Transaction) -> Result<String> {
+    sign_transaction(private_key, tx)?;
+    Ok(hex::encode(&tx.hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use marvin_blockchain::types::transaction::verify_transaction;
+
+    #[test]
+    fn test_new_transaction() {
+        let recipient = keys::generate_private_key().public_key();
+        assert_eq!(parse_public_key(&format!("0x{}", recipient)).unwrap(), recipient);
+        assert!(parse_public_key("abcd").is_err());
+        assert!(parse_public_key("not hex").is_err());
+        assert_eq!(decode_hex("0x0102").unwrap(), vec![1, 2]);
+        assert!(new_transaction(&recipient, 1, vec![], u64::MAX).is_err());
+
+        let mut private_key = keys::generate_private_key();
+        let mut tx = new_transaction(&recipient, 10, vec![1, 2], 3).unwrap();
+        let hash = sign(&mut private_key, &mut tx).unwrap();
+
+        assert_eq!(tx.from, private_key.public_key().to_bytes().to_vec());
+        assert_eq!(tx.nonce, 3);
+        assert_eq!(hash, hex::encode(&tx.hash));
+        assert!(verify_transaction(&mut tx).unwrap());
+    }
+}
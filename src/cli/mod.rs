@@ -1,4 +1,4 @@
-use clap::{arg, Arg, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 
 pub fn start_cli() -> Command {
     Command::new("marvinctl")
@@ -12,7 +12,13 @@ pub fn start_cli() -> Command {
                 .about("Manage addresses")
                 .arg_required_else_help(true)
                 .subcommand_required(true)
-                .subcommand(Command::new("create").about("Create a new address"))
+                .subcommand(
+                    Command::new("create").about("Create a new address").arg(
+                        Arg::new("keystore")
+                            .long("keystore")
+                            .help("Also save the key to a new keystore file, encrypted with the password read from MARVIN_KEYSTORE_PASSWORD or prompted for"),
+                    ),
+                )
                 .subcommand(
                     Command::new("restore")
                         .about("Restore an address from a mnemonic")
@@ -25,4 +31,76 @@ pub fn start_cli() -> Command {
                         .arg_required_else_help(true),
                 ),
         )
+        .subcommand(
+            Command::new("node")
+                .about("Run a Marvin node")
+                .arg_required_else_help(true)
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("start")
+                        .about("Start a full node, stopped with Ctrl-C or SIGTERM")
+                        .after_help("Settings are read from the config file, then from the MARVIN_* environment variables, then from the flags.")
+                        .arg(
+                            Arg::new("config")
+                                .short('c')
+                                .long("config")
+                                .env("MARVIN_CONFIG")
+                                .help("Path of the TOML config file, the defaults are used if not set"),
+                        )
+                        .arg(Arg::new("data-dir").long("data-dir").help("Directory of the node data"))
+                        .arg(Arg::new("log-level").long("log-level").help("Minimum log level: critical, error, warning, info, debug or trace"))
+                        .arg(Arg::new("chain-id").long("chain-id").help("Identifier of the network"))
+                        .arg(Arg::new("difficulty").long("difficulty").help("Minimum proof of work difficulty of the blocks, the same on every node of the network"))
+                        .arg(Arg::new("listen-addr").long("listen-addr").help("Address to accept peer connections on"))
+                        .arg(
+                            Arg::new("bootnode")
+                                .long("bootnode")
+                                .action(ArgAction::Append)
+                                .help("Address of a node to connect to on startup, can be repeated"),
+                        )
+                        .arg(Arg::new("external-addr").long("external-addr").help("Address advertised to peers"))
+                        .arg(Arg::new("rpc-addr").long("rpc-addr").help("Address of the JSON-RPC API"))
+                        .arg(Arg::new("grpc-addr").long("grpc-addr").help("Address of the gRPC API"))
+                        .arg(Arg::new("mine").long("mine").action(ArgAction::SetTrue).help("Mine blocks"))
+                        .arg(Arg::new("mining-mnemonic").long("mining-mnemonic").help("Mnemonic of the key signing the mined blocks"))
+                        .arg(Arg::new("mining-interval").long("mining-interval").help("Seconds between two mined blocks"))
+                        .arg(
+                            Arg::new("mempool-ttl")
+                                .long("mempool-ttl")
+                                .help("Seconds a transaction stays in the mempool before it is dropped, 0 to keep it until mined"),
+                        ),
+                ),
+        )
+}
+
+/// Flags of `node start` overriding a setting of the node config, with the key of the setting
+const NODE_SETTING_FLAGS: &[(&str, &str)] = &[
+    ("data-dir", "data_dir"),
+    ("log-level", "log_level"),
+    ("chain-id", "chain.id"),
+    ("difficulty", "chain.difficulty"),
+    ("listen-addr", "network.listen_addr"),
+    ("external-addr", "network.external_addr"),
+    ("rpc-addr", "rpc.listen_addr"),
+    ("grpc-addr", "rpc.grpc_addr"),
+    ("mining-mnemonic", "mining.mnemonic"),
+    ("mining-interval", "mining.interval_secs"),
+    ("mempool-ttl", "mempool.ttl_secs"),
+];
+
+/// Returns the settings of the node config overridden by the flags of `node start`
+pub fn node_setting_overrides(matches: &ArgMatches) -> Vec<(&'static str, String)> {
+    let mut overrides: Vec<(&'static str, String)> = NODE_SETTING_FLAGS
+        .iter()
+        .filter_map(|(flag, key)| matches.get_one::<String>(flag).map(|value| (*key, value.clone())))
+        .collect();
+
+    if let Some(bootnodes) = matches.get_many::<String>("bootnode") {
+        overrides.push(("network.bootnodes", bootnodes.cloned().collect::<Vec<String>>().join(",")));
+    }
+    if matches.get_flag("mine") {
+        overrides.push(("mining.enabled", String::from("true")));
+    }
+
+    overrides
 }
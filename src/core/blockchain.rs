@@ -1,16 +1,49 @@
 const CHAIN_MNEMONIC: &str = "velvet echo quill jungle nimbus crescent whisk anchor harbor tangle mosaic horizon";
+// Timestamp of the genesis block as a Unix timestamp in nanoseconds, fixed so every node has the same genesis block
+const GENESIS_TIMESTAMP: i64 = 1722470400000000000;
+// Number of events buffered for every subscriber before the slowest ones start missing events
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+// Default number of leading zero bits required in the hash of a block header
+pub const DEFAULT_DIFFICULTY: u32 = 8;
+// Lowest minimum difficulty a node can require, below it blocks cost next to nothing to mine
+pub const MIN_DIFFICULTY: u32 = 8;
+// Maximum time in nanoseconds the timestamp of a block can be ahead of the local clock
+pub const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 1_000_000_000;
+// Maximum number of blocks that can be disconnected from the tip to switch to another branch
+pub const MAX_REORG_DEPTH: usize = 100;
 
 use crate::core::header_list::HeaderList;
+use crate::core::mempool::Mempool;
+use crate::core::state::{ChainState, PendingState};
 use crate::core::storage::Storage;
 use crate::error::{Result, MarvinError};
 use crate::proto;
 use crate::utils::log::make_json_logger;
-use crate::types;
+use crate::types::hash::Hash;
+
+use std::collections::{HashMap, HashSet};
+
+use tokio::sync::broadcast;
+
+/// ChainEvent is emitted to the subscribers of the blockchain every time the tip changes
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    /// A block was connected at the tip of the blockchain
+    BlockConnected { hash: Hash, height: u64, block: proto::Block },
+    /// The block at the tip of the blockchain was disconnected
+    BlockDisconnected { hash: Hash, height: u64 },
+}
 
 pub struct Blockchain {
     pub headers: HeaderList,
     pub store: Box<dyn Storage>,
+    pub mempool: Mempool,
+    // Accounts and transaction index of the main chain
+    pub state: ChainState,
     pub logger: slog::Logger,
+    // Minimum proof of work difficulty of the blocks added to the blockchain
+    pub difficulty: u32,
+    events: broadcast::Sender<ChainEvent>,
 }
 
 impl Blockchain {
@@ -18,7 +51,11 @@ impl Blockchain {
         let mut bc = Blockchain {
             headers: HeaderList::new(),
             store,
+            mempool: Mempool::new(),
+            state: ChainState::new(),
             logger: make_json_logger(),
+            difficulty: DEFAULT_DIFFICULTY,
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
         };
 
         // Create the genesis block
@@ -30,6 +67,125 @@ impl Blockchain {
         bc
     }
 
+    // Opens a blockchain over a store that may already hold blocks, such as a file store reopened after a restart.
+    // The stored chain linked to the genesis block with the most work that is valid against the given minimum
+    // difficulty is connected again, rebuilding the state. Chains with an invalid block are only kept up to the
+    // block before it, and only if no other stored chain has more work.
+    pub fn open(store: Box<dyn Storage>, difficulty: u32) -> Result<Self> {
+        let stored = store.blocks()?;
+        let mut bc = Blockchain::new(store);
+        bc.difficulty = difficulty;
+
+        let blocks: HashMap<Vec<u8>, proto::Block> = stored
+            .into_iter()
+            .filter(|block| block.header.as_ref().is_some_and(|header| header.height > 0))
+            .map(|block| (crate::types::block::hash_block(&block), block))
+            .collect();
+        let mut tips: Vec<(Vec<u8>, u128)> = bc.stored_chain_work(&blocks).into_iter().collect();
+        tips.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let mut invalid: HashSet<Vec<u8>> = HashSet::new();
+        for (tip, _) in tips {
+            let Some(mut chain) = bc.chain_to_genesis(&blocks[&tip], &blocks) else {
+                continue;
+            };
+            if !invalid.is_empty() && chain.iter().any(|block| invalid.contains(&crate::types::block::hash_block(block))) {
+                continue;
+            }
+            chain.reverse();
+
+            // Only the blocks following the ones this chain has in common with the current chain are connected
+            let common = chain
+                .iter()
+                .take_while(|block| {
+                    let header = block.header.as_ref().unwrap();
+                    bc.headers.get(header.height as usize) == Some(header)
+                })
+                .count();
+            while bc.height() > common {
+                bc.disconnect_tip(false)?;
+            }
+
+            let mut connected = true;
+            for block in chain[common..].iter() {
+                if let Err(e) = bc.add_block((*block).clone()) {
+                    warn!(bc.logger, "Stored block is invalid, trying the stored chain with the next most work";
+                        "height" => bc.height() + 1,
+                        "error" => e.to_string()
+                    );
+                    invalid.insert(crate::types::block::hash_block(block));
+                    connected = false;
+                    break;
+                }
+            }
+            if connected {
+                break;
+            }
+        }
+
+        Ok(bc)
+    }
+
+    // Returns the cumulative work of the chain ending at every stored block linked to the genesis block
+    fn stored_chain_work(&self, blocks: &HashMap<Vec<u8>, proto::Block>) -> HashMap<Vec<u8>, u128> {
+        let genesis_hash = self.genesis_hash();
+        // None for the blocks that are not linked to the genesis block
+        let mut work: HashMap<&Vec<u8>, Option<u128>> = HashMap::new();
+
+        for hash in blocks.keys() {
+            // Walk back to the genesis block or to a block whose work is already known
+            let mut path = Vec::new();
+            let mut current = hash;
+            let mut total = loop {
+                if let Some(known) = work.get(current) {
+                    break *known;
+                }
+                path.push(current);
+
+                let header = blocks[current].header.as_ref().unwrap();
+                if header.height == 1 {
+                    break (header.prev_block_hash == genesis_hash).then_some(0);
+                }
+                match blocks.get(&header.prev_block_hash) {
+                    Some(prev) if prev.header.as_ref().unwrap().height + 1 == header.height => current = &header.prev_block_hash,
+                    _ => break None,
+                }
+            };
+
+            for hash in path.into_iter().rev() {
+                total = total.map(|total| total.saturating_add(Blockchain::block_work(blocks[hash].header.as_ref().unwrap().difficulty)));
+                work.insert(hash, total);
+            }
+        }
+
+        work.into_iter().filter_map(|(hash, work)| work.map(|work| (hash.clone(), work))).collect()
+    }
+
+    // Returns the blocks from the given tip back to the block following the genesis block,
+    // or None if one of them is missing from the given blocks
+    fn chain_to_genesis<'a>(&self, tip: &'a proto::Block, blocks: &'a HashMap<Vec<u8>, proto::Block>) -> Option<Vec<&'a proto::Block>> {
+        let genesis_hash = self.genesis_hash();
+        let mut chain = vec![tip];
+
+        loop {
+            let header = chain.last().unwrap().header.as_ref()?;
+            if header.height == 1 {
+                return (header.prev_block_hash == genesis_hash).then_some(chain);
+            }
+
+            let prev = blocks.get(&header.prev_block_hash)?;
+            if prev.header.as_ref()?.height + 1 != header.height {
+                return None;
+            }
+            chain.push(prev);
+        }
+    }
+
+    // Subscribes to the blocks connected to and disconnected from the blockchain
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
     // Adds a block to the blockchain
     pub fn add_block(&mut self, block: proto::Block) -> Result<()> {
         // Validate the block before adding to the blockchain
@@ -44,7 +200,7 @@ impl Blockchain {
             // Add the header to the header list
             self.headers.add(header.clone());
         } else {
-            return Err(MarvinError::General(String::from("Block header is missing")));
+            return Err(MarvinError::Validation(String::from("Block header is missing")));
         }
 
         // TODO: Log block added to the blockchain
@@ -55,6 +211,164 @@ impl Blockchain {
 
         // Store the block in the storage
         self.store.put(&block)?;
+        self.state.connect_block(&block);
+
+        // Evict the transactions included in the block, and the ones it invalidated, from the mempool
+        let evicted = self.mempool.remove_block_transactions(&block) + self.mempool.sweep_expired();
+        if evicted > 0 {
+            info!(self.logger, "Transactions evicted from the mempool"; "count" => evicted);
+        }
+
+        let _ = self.events.send(ChainEvent::BlockConnected {
+            hash: crate::types::block::block_hash(&block),
+            height: self.height() as u64,
+            block,
+        });
+
+        Ok(())
+    }
+
+    // Adds a transaction received from a client or a peer to the mempool, returning its hash.
+    pub fn add_transaction(&mut self, tx: &proto::Transaction) -> Result<Hash> {
+        self.check_transaction(tx)?;
+        self.mempool.add(tx)
+    }
+
+    // Loads the transactions saved by `Mempool::save` into the mempool, returning the number of transactions added.
+    // Every transaction goes through the checks of `add_transaction`, the ones failing them are dropped.
+    pub fn load_mempool(&mut self, path: &std::path::Path) -> Result<usize> {
+        let mut added = 0;
+        for entry in Mempool::read(path)? {
+            if self.check_transaction(&entry.tx).is_ok() && self.mempool.restore(entry).is_ok() {
+                added += 1;
+            }
+        }
+
+        Ok(added)
+    }
+
+    // Checks that a transaction can enter the mempool. The transaction must be signed, still valid for the next block,
+    // its nonce must not be consumed yet, and its sender must hold its value on top of what its other transactions
+    // in the mempool already spend.
+    fn check_transaction(&self, tx: &proto::Transaction) -> Result<()> {
+        crate::types::transaction::verify_transaction(&mut tx.clone())?;
+
+        if let Some(sender) = crate::types::transaction::sender_address(tx) {
+            let account = self.state.account(&sender);
+            if tx.nonce < 0 || (tx.nonce as u64) < account.nonce {
+                return Err(MarvinError::MempoolRejected(
+                    format!("Nonce too low, expected at least {} got {}", account.nonce, tx.nonce))
+                );
+            }
+
+            let available = account.balance().saturating_sub(self.mempool.pending_spend(&tx.from, tx.nonce));
+            if available < tx.value {
+                return Err(MarvinError::MempoolRejected(
+                    format!("Insufficient funds, sending {} with {} available", tx.value, available))
+                );
+            }
+        }
+
+        let now = Blockchain::get_current_timestamp_as_unix_nano() as i64;
+        if crate::types::transaction::is_expired(tx, self.height() as u64 + 1, now) {
+            return Err(MarvinError::MempoolRejected(String::from("Transaction has expired")));
+        }
+
+        Ok(())
+    }
+
+    // Disconnects the block at the tip of the blockchain and returns it.
+    // The transactions of the disconnected block are revalidated and re-injected into the mempool.
+    pub fn disconnect_block(&mut self) -> Result<proto::Block> {
+        self.disconnect_tip(true)
+    }
+
+    // Disconnects the block at the tip of the blockchain, re-injecting its transactions into the mempool if asked to
+    fn disconnect_tip(&mut self, reinject: bool) -> Result<proto::Block> {
+        if self.height() == 0 {
+            return Err(MarvinError::General(String::from("Cannot disconnect the genesis block")));
+        }
+
+        let header = self.headers.last().unwrap();
+        let hash = hex::encode(crate::types::block::hash_header(header));
+        let block = self.store.get(hash.clone())?;
+
+        self.headers.pop();
+        self.state.disconnect_block(&block);
+
+        let reinjected = if reinject { self.mempool.reinject_block_transactions(&block) } else { 0 };
+        info!(self.logger, "Block disconnected from the blockchain";
+            "height" => self.height() + 1,
+            "hash" => hash,
+            "reinjected" => reinjected
+        );
+
+        let _ = self.events.send(ChainEvent::BlockDisconnected {
+            hash: crate::types::block::block_hash(&block),
+            height: self.height() as u64 + 1,
+        });
+
+        Ok(block)
+    }
+
+    // Returns the proof of work of a block of the given difficulty, the expected number of hashes to mine it.
+    // The work saturates for difficulties that do not fit in 128 bits.
+    pub fn block_work(difficulty: u32) -> u128 {
+        1u128.checked_shl(difficulty).unwrap_or(u128::MAX)
+    }
+
+    // Returns the cumulative proof of work of the blocks of the current chain above the given height
+    pub fn work_above(&self, height: usize) -> u128 {
+        self.headers.headers.iter().skip(height + 1).fold(0, |work: u128, header| {
+            work.saturating_add(Blockchain::block_work(header.difficulty))
+        })
+    }
+
+    // Replaces the blocks above `fork_height` with the given blocks.
+    // The new blocks must have more cumulative work than the blocks they replace, and no more than
+    // MAX_REORG_DEPTH blocks can be disconnected.
+    // If any of the new blocks is invalid the original chain is restored and the error is returned.
+    pub fn reorganize(&mut self, fork_height: usize, blocks: Vec<proto::Block>) -> Result<()> {
+        if fork_height > self.height() {
+            return Err(MarvinError::Validation(
+                format!("Fork height {} is above the current height {}", fork_height, self.height()))
+            );
+        }
+        if self.height() - fork_height > MAX_REORG_DEPTH {
+            return Err(MarvinError::Validation(format!(
+                "Fork at height {} is deeper than the maximum reorganization depth of {} blocks", fork_height, MAX_REORG_DEPTH
+            )));
+        }
+        let work = blocks.iter().fold(0, |work: u128, block| {
+            work.saturating_add(Blockchain::block_work(block.header.as_ref().map(|header| header.difficulty).unwrap_or_default()))
+        });
+        if work <= self.work_above(fork_height) {
+            return Err(MarvinError::Validation(String::from("Fork does not have more work than the current chain")));
+        }
+
+        let mut disconnected = Vec::new();
+        while self.height() > fork_height {
+            disconnected.push(self.disconnect_block()?);
+        }
+
+        for (connected, block) in blocks.into_iter().enumerate() {
+            if let Err(e) = self.add_block(block) {
+                warn!(self.logger, "Reorganization failed, restoring the original chain";
+                    "fork_height" => fork_height,
+                    "error" => e.to_string()
+                );
+
+                // The transactions of the rejected branch are not re-injected into the mempool
+                for _ in 0..connected {
+                    self.disconnect_tip(false)?;
+                }
+                for block in disconnected.into_iter().rev() {
+                    self.add_block_without_validation(block)?;
+                }
+
+                return Err(e);
+            }
+        }
 
         Ok(())
     }
@@ -64,14 +378,18 @@ impl Blockchain {
         let mut private_key = crate::crypto::keys::get_private_key_from_mnemonic(CHAIN_MNEMONIC)?;
 
         // Create the genesis block
-        let mut block = proto::Block::default();
-        let mut header = proto::Header::default();
-        header.height = 0;
-        header.version = 1;
-        header.timestamp = Blockchain::get_current_timestamp_as_unix_nano() as i64;
-        header.prev_block_hash = vec![0; 32];
+        let header = proto::Header {
+            height: 0,
+            version: 1,
+            timestamp: GENESIS_TIMESTAMP,
+            prev_block_hash: vec![0; 32],
+            ..Default::default()
+        };
 
-        block.header = Some(header);
+        let mut block = proto::Block {
+            header: Some(header),
+            ..Default::default()
+        };
 
         // Signs the block
         crate::types::block::sign_block(&mut private_key, &mut block)?;
@@ -79,8 +397,13 @@ impl Blockchain {
         Ok(block)
     }
 
+    // Returns the hash of the genesis block
+    pub fn genesis_hash(&self) -> Vec<u8> {
+        crate::types::block::hash_header(self.headers.get(0).unwrap())
+    }
+
     // Returns the current timestamp as a Unix timestamp in nanoseconds
-    fn get_current_timestamp_as_unix_nano() -> u64 {
+    pub fn get_current_timestamp_as_unix_nano() -> u64 {
         use std::time::{SystemTime, UNIX_EPOCH};
         let start = SystemTime::now();
         let since_the_epoch = start.duration_since(UNIX_EPOCH).expect("Time went backwards");
@@ -96,38 +419,176 @@ impl Blockchain {
 
     // Checks if the blockchain has a block at a given height
     pub fn has_block(&self, height: usize) -> bool {
-        return height <= self.height();
+        height <= self.height()
+    }
+
+    // Returns a stored block given its hash, the block is not necessarily part of the current chain
+    pub fn get_block(&self, hash: &Hash) -> Result<proto::Block> {
+        self.store.get(hash.to_string())
+    }
+
+    // Returns the block of the current chain at a given height
+    pub fn get_block_by_height(&self, height: usize) -> Result<proto::Block> {
+        match self.headers.get(height) {
+            Some(header) => self.get_block(&Hash::from_bytes(&crate::types::block::hash_header(header))?),
+            None => Err(MarvinError::NotFound(format!("No block at height {}", height))),
+        }
+    }
+
+    // Checks if the block with the given hash is part of the current chain
+    pub fn contains_block(&self, hash: &Hash) -> bool {
+        match self.get_block(hash) {
+            Ok(block) => {
+                let height = block.header.as_ref().map(|header| header.height).unwrap_or_default();
+                self.headers.get(height as usize) == block.header.as_ref()
+            }
+            Err(_) => false,
+        }
+    }
+
+    // Returns a block locator: the hashes of the blocks of the current chain from the tip back to the genesis block,
+    // dense near the tip and exponentially sparser further back, so a peer can find the last block we have in common
+    pub fn block_locator(&self) -> Vec<Hash> {
+        let mut locator = Vec::new();
+        let mut height = self.height();
+        let mut step = 1;
+
+        loop {
+            locator.push(Hash::from_bytes(&crate::types::block::hash_header(self.headers.get(height).unwrap())).unwrap());
+            if height == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+
+        locator
+    }
+
+    // Returns up to `max` headers of the current chain following the first block of the locator found in the chain.
+    // If no block of the locator is found, the headers following the genesis block are returned.
+    pub fn headers_after(&self, locator: &[Hash], max: usize) -> Vec<proto::Header> {
+        let start = locator
+            .iter()
+            .find(|hash| self.contains_block(hash))
+            .and_then(|hash| self.get_block(hash).ok())
+            .and_then(|block| block.header.map(|header| header.height as usize))
+            .unwrap_or(0);
+
+        self.headers.headers.iter().skip(start + 1).take(max).cloned().collect()
+    }
+
+    // Checks that a header can follow the given previous header: height, linkage, proof of work and timestamp
+    pub fn validate_header(&self, prev: &proto::Header, header: &proto::Header) -> Result<()> {
+        if header.height != prev.height + 1 {
+            return Err(MarvinError::Validation(
+                format!("Header height is not the next height. Expected height: {}", prev.height + 1))
+            );
+        }
+
+        if header.prev_block_hash != crate::types::block::hash_header(prev) {
+            return Err(MarvinError::Validation(
+                String::from("Previous hash in the header is not the hash of the previous block"))
+            );
+        }
+
+        if header.difficulty < self.difficulty {
+            return Err(MarvinError::Validation(
+                format!("Header difficulty {} is below the minimum difficulty {}", header.difficulty, self.difficulty))
+            );
+        }
+
+        if !crate::types::block::meets_difficulty(header) {
+            return Err(MarvinError::Validation(String::from("Header hash does not meet its difficulty")));
+        }
+
+        if header.timestamp <= prev.timestamp {
+            return Err(MarvinError::Validation(String::from("Header timestamp is not after the previous block")));
+        }
+
+        let now = Blockchain::get_current_timestamp_as_unix_nano() as i64;
+        if header.timestamp > now + MAX_FUTURE_BLOCK_TIME {
+            return Err(MarvinError::Validation(String::from("Header timestamp is too far in the future")));
+        }
+
+        Ok(())
     }
 
     // Checks if a block is valid to be added to the blockchain
     pub fn validate_block(&self, block: &proto::Block) -> Result<()> {
+        if block.header.is_none() {
+            return Err(MarvinError::Validation(String::from("Block header is missing")));
+        }
+
         // Check if the block is already in the blockchain
         if self.has_block(block.header.as_ref().unwrap().height as usize) {
-            return Err(MarvinError::General(
-                String::from(format!("Block already exists at height {}", block.header.as_ref().unwrap().height)))
+            return Err(MarvinError::Validation(
+                format!("Block already exists at height {}", block.header.as_ref().unwrap().height))
             );
         }
 
-        // Check if the block height is the next height in the blockchain
-        if block.header.as_ref().unwrap().height as usize != self.height() + 1 {
-            return Err(MarvinError::General(
-                String::from(format!("Block height is not the next height in the blockchain. Expected height: {}", self.height() + 1)))
-            );
+        // Check the header against the last header in the blockchain
+        let header = block.header.as_ref().unwrap();
+        self.validate_header(self.headers.last().unwrap(), header)?;
+
+        self.check_block(block)?;
+
+        // Check that every transaction uses the next nonce of its sender, so mined transactions cannot be replayed
+        // and no nonce is skipped, and that every sender holds the value it sends when its transaction is applied
+        let mut pending = PendingState::new(&self.state);
+        for tx in block.transactions.iter() {
+            pending.apply(tx)?;
+        }
+
+        Ok(())
+    }
+
+    // Checks the validity of a block on its own, regardless of the current chain: proof of work,
+    // signature of the block and of its transactions, the commitment of the header to the transactions,
+    // and nonces strictly increasing for every sender so no transaction appears twice.
+    // A block failing these checks can never become valid, unlike a block that does not fit the current tip.
+    pub fn check_block(&self, block: &proto::Block) -> Result<()> {
+        let header = match block.header.as_ref() {
+            Some(header) => header,
+            None => return Err(MarvinError::Validation(String::from("Block header is missing"))),
+        };
+
+        if header.difficulty < self.difficulty || !crate::types::block::meets_difficulty(header) {
+            return Err(MarvinError::Validation(String::from("Block does not meet the proof of work difficulty")));
+        }
+
+        // Check that the header commits to the transactions of the block
+        if header.tx_hash != crate::types::block::calculate_tx_hash(&block.transactions) {
+            return Err(MarvinError::Validation(String::from("Transaction hash does not match the block transactions")));
         }
 
         // Check if the block is valid
-        crate::types::block::verify_block(block).unwrap();
-
-        // Retrieve the last header in the blockchain, calculate the hash of the last block and compare it with the previous hash in the new block
-        let last_header = self.headers.last().unwrap();
-        let last_hash = crate::types::block::hash_header(last_header);
-        // Check if the previous hash in the new block is the hash of the last block
-        if last_hash != block.header.as_ref().unwrap().prev_block_hash {
-            return Err(MarvinError::General(
-                String::from("Previous hash in the new block is not the hash of the last block"))
-            );
+        if !crate::types::block::verify_block(block)? {
+            return Err(MarvinError::Crypto(String::from("Invalid block signature")));
         }
-    
+
+        // Check that every transaction is signed, none of them has expired, and the nonces of every sender increase
+        let mut nonces: HashMap<crate::crypto::keys::Address, i64> = HashMap::new();
+        for tx in block.transactions.iter() {
+            crate::types::transaction::verify_transaction(&mut tx.clone())?;
+            if crate::types::transaction::is_expired(tx, header.height, header.timestamp) {
+                return Err(MarvinError::Validation(
+                    format!("Transaction {} has expired", hex::encode(crate::types::transaction::calculate_transaction_hash(tx))))
+                );
+            }
+
+            let sender = crate::types::transaction::sender_address(tx);
+            let previous = sender.and_then(|sender| nonces.insert(sender, tx.nonce));
+            if tx.nonce < 0 || previous.is_some_and(|previous| tx.nonce <= previous) {
+                return Err(MarvinError::Validation(format!(
+                    "Transaction {} repeats or reorders the nonce {} of its sender",
+                    crate::types::transaction::transaction_hash(tx), tx.nonce
+                )));
+            }
+        }
+
         Ok(())
     }
 }
@@ -141,10 +602,19 @@ mod tests {
     #[test]
     fn test_new_blockchain() {
         let store = Box::new(MemoryStore::new());
-        let mut blockchain = Blockchain::new(store);
+        let blockchain = Blockchain::new(store);
 
         assert_eq!(blockchain.height(), 0);
-        assert_eq!(blockchain.has_block(0), true);
+        assert!(blockchain.has_block(0));
+    }
+
+    #[test]
+    fn test_genesis_block_is_deterministic() {
+        let blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let other = Blockchain::new(Box::new(MemoryStore::new()));
+
+        assert_eq!(blockchain.genesis_hash(), other.genesis_hash());
+        assert_eq!(Blockchain::create_genesis_block().unwrap(), Blockchain::create_genesis_block().unwrap());
     }
 
     #[test]
@@ -155,38 +625,450 @@ mod tests {
         let num_blocks = 10;
         for i in 0..num_blocks {
             let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
-            let block = generate_random_block((i+1) as i64, prev_block_hash);
+            let block = generate_random_block(i + 1, prev_block_hash);
             let result = blockchain.add_block(block);
 
-            assert_eq!(result.is_ok(), true);
+            assert!(result.is_ok());
         }
 
         assert_eq!(blockchain.height() as i64, num_blocks);
 
         let existing_block = generate_random_block(1, vec![0; 32]);
         let result = blockchain.add_block(existing_block);
-        assert_eq!(result.is_err(), true);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_check_block() {
+        let blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let mut private_key = crate::crypto::keys::generate_private_key();
+        let tx = generate_signed_transaction(&mut private_key, 1);
+
+        let block = generate_block_with_transactions(5, vec![0; 32], vec![tx.clone()]);
+        assert!(blockchain.check_block(&block).is_ok());
+
+        // A block not committing to its transactions
+        let mut tampered = block.clone();
+        tampered.transactions[0].value += 1;
+        assert!(blockchain.check_block(&tampered).is_err());
+
+        // A block with a modified header
+        let mut tampered = block.clone();
+        tampered.header.as_mut().unwrap().timestamp += 1;
+        assert!(blockchain.check_block(&tampered).is_err());
+
+        // The nonces of a sender must increase within a block
+        let next = generate_signed_transaction(&mut private_key, 2);
+        let ordered = generate_block_with_transactions(5, vec![0; 32], vec![tx.clone(), next.clone()]);
+        assert!(blockchain.check_block(&ordered).is_ok());
+        let duplicated = generate_block_with_transactions(5, vec![0; 32], vec![tx.clone(), tx.clone()]);
+        assert!(matches!(blockchain.check_block(&duplicated), Err(MarvinError::Validation(_))));
+        let reordered = generate_block_with_transactions(5, vec![0; 32], vec![next, tx]);
+        assert!(matches!(blockchain.check_block(&reordered), Err(MarvinError::Validation(_))));
+    }
+
+    #[test]
+    fn test_replayed_transaction_is_rejected() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let mut private_key = crate::crypto::keys::generate_private_key();
+        let tx = generate_signed_transaction(&mut private_key, 0);
+        let hash = crate::types::transaction::transaction_hash(&tx);
+        let sender = private_key.public_key().address();
+
+        let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+        blockchain.add_block(generate_block_with_transactions(1, prev_block_hash, vec![tx.clone()])).unwrap();
+        assert_eq!(blockchain.state.account(&sender).nonce, 1);
+
+        // The same signed transaction included again in the next block
+        let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+        let replay = generate_block_with_transactions(2, prev_block_hash, vec![tx]);
+        assert!(blockchain.check_block(&replay).is_ok());
+        assert!(matches!(blockchain.add_block(replay), Err(MarvinError::Validation(_))));
+
+        assert_eq!(blockchain.height(), 1);
+        assert_eq!(blockchain.state.account(&sender).nonce, 1);
+        assert_eq!(blockchain.state.transaction(&hash).unwrap().height, 1);
+    }
+
+    #[test]
+    fn test_block_transactions_follow_the_state() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let mut private_key = crate::crypto::keys::generate_private_key();
+        let first = generate_signed_transaction(&mut private_key, 0);
+        let second = generate_signed_transaction(&mut private_key, 1);
+        let gapped = generate_signed_transaction(&mut private_key, 2);
+        let mut unfunded = generate_signed_transaction(&mut private_key, 1);
+        unfunded.value = 1;
+        crate::types::transaction::sign_transaction(&mut private_key, &mut unfunded).unwrap();
+
+        // The first transaction of a sender must use its next nonce, and later ones must not skip a nonce
+        let genesis_hash = blockchain.genesis_hash();
+        let block = generate_block_with_transactions(1, genesis_hash.clone(), vec![second.clone()]);
+        assert!(matches!(blockchain.add_block(block), Err(MarvinError::Validation(_))));
+        let block = generate_block_with_transactions(1, genesis_hash.clone(), vec![first.clone(), gapped]);
+        assert!(matches!(blockchain.add_block(block), Err(MarvinError::Validation(_))));
+
+        // A sender cannot send value it does not hold
+        let block = generate_block_with_transactions(1, genesis_hash.clone(), vec![first.clone(), unfunded]);
+        assert!(matches!(blockchain.add_block(block), Err(MarvinError::Validation(_))));
+        assert_eq!(blockchain.height(), 0);
+
+        let block = generate_block_with_transactions(1, genesis_hash, vec![first, second]);
+        blockchain.add_block(block).unwrap();
+        assert_eq!(blockchain.state.account(&private_key.public_key().address()).nonce, 2);
+    }
+
+    #[test]
+    fn test_add_block_with_expired_transaction() {
+        let store = Box::new(MemoryStore::new());
+        let mut blockchain = Blockchain::new(store);
+
+        let mut private_key = crate::crypto::keys::generate_private_key();
+        let mut first = generate_signed_transaction(&mut private_key, 0);
+        first.valid_until_height = 1;
+        crate::types::transaction::sign_transaction(&mut private_key, &mut first).unwrap();
+        let mut tx = generate_signed_transaction(&mut private_key, 1);
+        tx.valid_until_height = 1;
+        crate::types::transaction::sign_transaction(&mut private_key, &mut tx).unwrap();
+
+        let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+        let block = generate_block_with_transactions(1, prev_block_hash, vec![first]);
+        blockchain.add_block(block).unwrap();
+
+        let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+        let block = generate_block_with_transactions(2, prev_block_hash, vec![tx]);
+        assert!(blockchain.add_block(block).is_err());
+        assert_eq!(blockchain.height(), 1);
+    }
+
+    #[test]
+    fn test_add_block_evicts_mempool_transactions() {
+        let store = Box::new(MemoryStore::new());
+        let mut blockchain = Blockchain::new(store);
+
+        let mut private_key = crate::crypto::keys::generate_private_key();
+        let first = generate_signed_transaction(&mut private_key, 0);
+        let mined = generate_signed_transaction(&mut private_key, 1);
+        let stale = generate_signed_transaction(&mut private_key, 0);
+        let pending = generate_signed_transaction(&mut private_key, 2);
+        blockchain.mempool.add(&mined).unwrap();
+        blockchain.mempool.add(&stale).unwrap();
+        blockchain.mempool.add(&pending).unwrap();
+
+        let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+        let block = generate_block_with_transactions(1, prev_block_hash, vec![first, mined.clone()]);
+        blockchain.add_block(block).unwrap();
+
+        assert_eq!(blockchain.mempool.len(), 1);
+        assert!(blockchain.mempool.contains(&pending));
+    }
+
+    #[test]
+    fn test_disconnect_block_reinjects_transactions() {
+        let store = Box::new(MemoryStore::new());
+        let mut blockchain = Blockchain::new(store);
+
+        let mut private_key = crate::crypto::keys::generate_private_key();
+        let tx = generate_signed_transaction(&mut private_key, 0);
+
+        let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+        let block = generate_block_with_transactions(1, prev_block_hash, vec![tx.clone()]);
+        blockchain.add_block(block.clone()).unwrap();
+        assert_eq!(blockchain.mempool.len(), 0);
+
+        let disconnected = blockchain.disconnect_block().unwrap();
+        assert_eq!(disconnected, block);
+        assert_eq!(blockchain.height(), 0);
+        assert!(blockchain.mempool.contains(&tx));
+
+        // The genesis block can never be disconnected
+        assert!(blockchain.disconnect_block().is_err());
+    }
+
+    #[test]
+    fn test_reorganize() {
+        let store = Box::new(MemoryStore::new());
+        let mut blockchain = Blockchain::new(store);
+
+        let mut private_key = crate::crypto::keys::generate_private_key();
+        let tx_a = generate_signed_transaction(&mut private_key, 0);
+        let tx_b = generate_signed_transaction(&mut private_key, 0);
+
+        let genesis_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+        let block_a = generate_block_with_transactions(1, genesis_hash.clone(), vec![tx_a.clone()]);
+        blockchain.add_block(block_a).unwrap();
+
+        // Competing branch from genesis with two blocks
+        let fork_1 = generate_block_with_transactions(1, genesis_hash.clone(), vec![]);
+        let fork_1_hash = crate::types::block::hash_block(&fork_1);
+        let fork_2 = generate_block_with_transactions(2, fork_1_hash, vec![tx_b.clone()]);
+
+        blockchain.reorganize(0, vec![fork_1.clone(), fork_2]).unwrap();
+        assert_eq!(blockchain.height(), 2);
+        assert!(!blockchain.mempool.contains(&tx_b));
+        // tx_a was re-injected, but tx_b consumed its nonce
+        assert!(!blockchain.mempool.contains(&tx_a));
+
+        // The state follows the new branch
+        let tx_a_hash = crate::types::transaction::transaction_hash(&tx_a);
+        let tx_b_hash = crate::types::transaction::transaction_hash(&tx_b);
+        assert!(blockchain.state.transaction(&tx_a_hash).is_none());
+        assert_eq!(blockchain.state.transaction(&tx_b_hash).unwrap().height, 2);
+        assert_eq!(blockchain.state.account(&private_key.public_key().address()).nonce, 1);
+
+        // An invalid branch leaves the chain untouched
+        let tip = blockchain.headers.last().unwrap().clone();
+        let tx_c = generate_signed_transaction(&mut crate::crypto::keys::generate_private_key(), 0);
+        let rejected = generate_block_with_transactions(1, genesis_hash.clone(), vec![tx_c.clone()]);
+        let invalid = generate_block_with_transactions(5, genesis_hash, vec![]);
+        let last = generate_random_block(3, crate::types::block::hash_block(&invalid));
+        assert!(blockchain.reorganize(0, vec![rejected, invalid, last]).is_err());
+        assert_eq!(blockchain.height(), 2);
+        assert_eq!(blockchain.headers.last().unwrap(), &tip);
+        // The transactions of the rejected branch are not re-injected into the mempool
+        assert!(!blockchain.mempool.contains(&tx_c));
+    }
+
+    #[test]
+    fn test_reorganize_requires_more_work() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let genesis_hash = blockchain.genesis_hash();
+
+        let mut heavy = generate_random_block(1, genesis_hash.clone());
+        heavy.header.as_mut().unwrap().difficulty = DEFAULT_DIFFICULTY + 2;
+        crate::types::block::mine_header(heavy.header.as_mut().unwrap());
+        crate::types::block::sign_block(&mut crate::crypto::keys::generate_private_key(), &mut heavy).unwrap();
+        blockchain.add_block(heavy.clone()).unwrap();
+        assert_eq!(blockchain.work_above(0), Blockchain::block_work(DEFAULT_DIFFICULTY + 2));
+
+        // A longer branch with less cumulative work does not replace the chain
+        let fork_1 = generate_random_block(1, genesis_hash.clone());
+        let fork_2 = generate_random_block(2, crate::types::block::hash_block(&fork_1));
+        let fork_3 = generate_random_block(3, crate::types::block::hash_block(&fork_2));
+        assert!(blockchain.reorganize(0, vec![fork_1.clone(), fork_2.clone(), fork_3.clone()]).is_err());
+        assert_eq!(blockchain.headers.last(), heavy.header.as_ref());
+
+        // It does once it has more work
+        let fork_4 = generate_random_block(4, crate::types::block::hash_block(&fork_3));
+        let fork_5 = generate_random_block(5, crate::types::block::hash_block(&fork_4));
+        blockchain.reorganize(0, vec![fork_1, fork_2, fork_3, fork_4, fork_5]).unwrap();
+        assert_eq!(blockchain.height(), 5);
+    }
+
+    #[test]
+    fn test_reorganize_depth_is_limited() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let genesis_hash = blockchain.genesis_hash();
+        for height in 1..=MAX_REORG_DEPTH + 1 {
+            let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+            blockchain.add_block(generate_random_block(height as i64, prev_block_hash)).unwrap();
+        }
+
+        let mut fork = Vec::new();
+        let mut prev_block_hash = genesis_hash;
+        for height in 1..=MAX_REORG_DEPTH + 2 {
+            let block = generate_random_block(height as i64, prev_block_hash);
+            prev_block_hash = crate::types::block::hash_block(&block);
+            fork.push(block);
+        }
+        assert!(blockchain.reorganize(0, fork.clone()).is_err());
+        assert_eq!(blockchain.height(), MAX_REORG_DEPTH + 1);
+
+        // A branch disconnecting exactly the maximum number of blocks is accepted
+        let mut prev_block_hash = crate::types::block::hash_header(blockchain.headers.get(1).unwrap());
+        let mut fork = Vec::new();
+        for height in 2..=MAX_REORG_DEPTH + 2 {
+            let block = generate_random_block(height as i64, prev_block_hash);
+            prev_block_hash = crate::types::block::hash_block(&block);
+            fork.push(block);
+        }
+        blockchain.reorganize(1, fork).unwrap();
+        assert_eq!(blockchain.height(), MAX_REORG_DEPTH + 2);
+    }
+
+    #[test]
+    fn test_chain_events() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let mut events = blockchain.subscribe();
+
+        let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+        let block = generate_random_block(1, prev_block_hash);
+        let hash = crate::types::block::block_hash(&block);
+        blockchain.add_block(block.clone()).unwrap();
+        assert!(blockchain.contains_block(&hash));
+
+        blockchain.disconnect_block().unwrap();
+        assert!(!blockchain.contains_block(&hash));
+        assert_eq!(blockchain.get_block(&hash).unwrap(), block);
+
+        assert_eq!(events.try_recv().unwrap(), ChainEvent::BlockConnected { hash, height: 1, block });
+        assert_eq!(events.try_recv().unwrap(), ChainEvent::BlockDisconnected { hash, height: 1 });
+    }
+
+    #[test]
+    fn test_add_transaction() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let mut private_key = crate::crypto::keys::generate_private_key();
+
+        let tx = generate_signed_transaction(&mut private_key, 0);
+        assert_eq!(blockchain.add_transaction(&tx).unwrap(), crate::types::transaction::transaction_hash(&tx));
+
+        let mut tampered = generate_signed_transaction(&mut private_key, 1);
+        tampered.value += 1;
+        assert!(matches!(blockchain.add_transaction(&tampered), Err(MarvinError::Crypto(_))));
+
+        let mut unfunded = generate_signed_transaction(&mut private_key, 1);
+        unfunded.value = 1;
+        crate::types::transaction::sign_transaction(&mut private_key, &mut unfunded).unwrap();
+        let error = blockchain.add_transaction(&unfunded).unwrap_err();
+        assert_eq!(error, MarvinError::MempoolRejected(String::from("Insufficient funds, sending 1 with 0 available")));
+
+        let mut expired = generate_signed_transaction(&mut private_key, 2);
+        expired.valid_until_height = 0;
+        expired.valid_until_timestamp = 1;
+        crate::types::transaction::sign_transaction(&mut private_key, &mut expired).unwrap();
+        assert!(matches!(blockchain.add_transaction(&expired), Err(MarvinError::MempoolRejected(_))));
+        assert!(matches!(blockchain.add_transaction(&tx), Err(MarvinError::MempoolRejected(_))));
+        assert_eq!(blockchain.mempool.len(), 1);
+
+        // Once the transaction is mined its nonce is consumed
+        let block = generate_block_with_transactions(1, blockchain.genesis_hash(), vec![tx]);
+        blockchain.add_block(block).unwrap();
+        let stale = generate_signed_transaction(&mut private_key, 0);
+        let error = blockchain.add_transaction(&stale).unwrap_err();
+        assert_eq!(error, MarvinError::MempoolRejected(String::from("Nonce too low, expected at least 1 got 0")));
+    }
+
+    #[test]
+    fn test_load_mempool() {
+        let path = std::env::temp_dir().join(format!("marvin-mempool-{}.dat", hex::encode(crate::crypto::keys::new_entropy())));
+        let mut private_key = crate::crypto::keys::generate_private_key();
+        let valid = generate_signed_transaction(&mut private_key, 1);
+        let mined = generate_signed_transaction(&mut private_key, 0);
+        let mut other_key = crate::crypto::keys::generate_private_key();
+        let mut unfunded = generate_signed_transaction(&mut other_key, 0);
+        unfunded.value = 1;
+        crate::types::transaction::sign_transaction(&mut other_key, &mut unfunded).unwrap();
+        let mut expired = generate_signed_transaction(&mut private_key, 2);
+        expired.valid_until_height = 1;
+        crate::types::transaction::sign_transaction(&mut private_key, &mut expired).unwrap();
+
+        // The mempool file was saved before the node was stopped, the transactions are not checked on save
+        let mut mempool = Mempool::new();
+        for tx in [&valid, &mined, &unfunded, &expired] {
+            mempool.add(tx).unwrap();
+        }
+        mempool.save(&path).unwrap();
+
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let block = generate_block_with_transactions(1, blockchain.genesis_hash(), vec![mined]);
+        blockchain.add_block(block).unwrap();
+        assert_eq!(blockchain.load_mempool(&path).unwrap(), 1);
+        assert!(blockchain.mempool.contains(&valid));
+        assert_eq!(blockchain.mempool.len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(blockchain.load_mempool(&path).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_open() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let mut private_key = crate::crypto::keys::generate_private_key();
+        let tx = generate_signed_transaction(&mut private_key, 0);
+
+        let block = generate_block_with_transactions(1, blockchain.genesis_hash(), vec![tx.clone()]);
+        blockchain.add_block(block).unwrap();
+        for height in 2..=3 {
+            let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+            blockchain.add_block(generate_random_block(height, prev_block_hash)).unwrap();
+        }
+        // A stale branch and a block that is not linked to the genesis block are ignored
+        blockchain.store.put(&generate_random_block(1, blockchain.genesis_hash())).unwrap();
+        blockchain.store.put(&generate_random_block(7, vec![1; 32])).unwrap();
+
+        let store = std::mem::replace(&mut blockchain.store, Box::new(MemoryStore::new()));
+        let reopened = Blockchain::open(store, DEFAULT_DIFFICULTY).unwrap();
+        assert_eq!(reopened.height(), 3);
+        assert_eq!(reopened.headers.last(), blockchain.headers.last());
+        assert_eq!(reopened.state.account(&private_key.public_key().address()).nonce, 1);
+        assert!(reopened.state.transaction(&crate::types::transaction::transaction_hash(&tx)).is_some());
+    }
+
+    #[test]
+    fn test_open_skips_invalid_stored_chain() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        for height in 1..=3 {
+            let prev_block_hash = crate::types::block::hash_header(blockchain.headers.last().unwrap());
+            blockchain.add_block(generate_random_block(height, prev_block_hash)).unwrap();
+        }
+
+        // A stored branch with more work, whose second block does not match the transactions its header commits to
+        let mut prev_block_hash = blockchain.genesis_hash();
+        for height in 1..=5 {
+            let mut block = generate_random_block(height, prev_block_hash);
+            prev_block_hash = crate::types::block::hash_block(&block);
+            if height == 2 {
+                block.transactions.push(proto::Transaction::default());
+            }
+            blockchain.store.put(&block).unwrap();
+        }
+
+        let store = std::mem::replace(&mut blockchain.store, Box::new(MemoryStore::new()));
+        let reopened = Blockchain::open(store, DEFAULT_DIFFICULTY).unwrap();
+        assert_eq!(reopened.height(), 3);
+        assert_eq!(reopened.headers.last(), blockchain.headers.last());
     }
 
     fn generate_random_block(height: i64, prev_block_hash: Vec<u8>) -> proto::Block {
+        generate_block_with_transactions(height, prev_block_hash, vec![])
+    }
+
+    fn generate_block_with_transactions(height: i64, prev_block_hash: Vec<u8>, transactions: Vec<proto::Transaction>) -> proto::Block {
         let mnemonic = "all wild paddle pride wheat menu task funny sign profit blouse hockey";
         let mut private_key = crate::crypto::keys::get_private_key_from_mnemonic(mnemonic).unwrap();
 
-        let mut block = proto::Block::default();
-        let mut header = proto::Header::default();
-        header.height = height as u64;
-        header.version = 1;
-        header.timestamp = Blockchain::get_current_timestamp_as_unix_nano() as i64;
-        header.prev_block_hash = prev_block_hash;
-        header.nonce = 1;
-        header.difficulty = 1;
+        let header = proto::Header {
+            height: height as u64,
+            version: 1,
+            timestamp: Blockchain::get_current_timestamp_as_unix_nano() as i64,
+            prev_block_hash,
+            nonce: 1,
+            difficulty: DEFAULT_DIFFICULTY,
+            ..Default::default()
+        };
 
+        let mut block = proto::Block {
+            header: Some(header),
+            ..Default::default()
+        };
 
-        block.header = Some(header);
+        for tx in transactions {
+            crate::types::block::add_transaction(&mut block, tx);
+        }
+        block.header.as_mut().unwrap().tx_hash = crate::types::block::calculate_tx_hash(&block.transactions);
+        crate::types::block::mine_header(block.header.as_mut().unwrap());
 
         // Signs the block
         crate::types::block::sign_block(&mut private_key, &mut block).unwrap();
 
         block
     }
+
+    fn generate_signed_transaction(private_key: &mut crate::crypto::keys::PrivateKey, nonce: i64) -> proto::Transaction {
+        let mut tx = proto::Transaction {
+            from: private_key.public_key().to_bytes().to_vec(),
+            to: crate::crypto::keys::generate_private_key().public_key().to_bytes().to_vec(),
+            value: 0,
+            data: b"Transaction data".to_vec(),
+            signature: [0; 64].to_vec(),
+            nonce,
+            hash: [0; 32].to_vec(),
+            valid_until_height: 0,
+            valid_until_timestamp: 0,
+        };
+        crate::types::transaction::sign_transaction(private_key, &mut tx).unwrap();
+
+        tx
+    }
 }
\ No newline at end of file
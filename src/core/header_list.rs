@@ -4,6 +4,12 @@ pub struct HeaderList {
     pub headers: Vec<proto::Header>,
 }
 
+impl Default for HeaderList {
+    fn default() -> Self {
+        HeaderList::new()
+    }
+}
+
 impl HeaderList {
     pub fn new() -> Self {
         HeaderList {
@@ -16,6 +22,11 @@ impl HeaderList {
         self.headers.push(h);
     }
 
+    /// Remove the last header from the list and return it
+    pub fn pop(&mut self) -> Option<proto::Header> {
+        self.headers.pop()
+    }
+
     /// Get a header from the list given an index. The index is 0-based and is also the height of the header.
     pub fn get(&self, index: usize) -> Option<&proto::Header> {
         self.headers.get(index)
@@ -1,26 +1,167 @@
+use crate::crypto::keys::Address;
 use crate::error::{Result, MarvinError};
 use crate::proto;
 use crate::types;
+use crate::types::hash::Hash;
 
-use core::hash;
-use std::collections::HashMap;
+use prost::Message;
+use tokio::sync::broadcast;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default maximum age of a transaction in the mempool before it is dropped by the TTL sweep
+pub const DEFAULT_TRANSACTION_TTL: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// Default maximum number of transactions in the mempool
+pub const DEFAULT_MAX_TRANSACTIONS: usize = 50_000;
+/// Default maximum total size in bytes of the encoded transactions in the mempool
+pub const DEFAULT_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// Maximum number of times the pending transactions of a sender can be replaced while they wait to be mined
+pub const MAX_REPLACEMENTS_PER_SENDER: u32 = 16;
+
+/// Number of events buffered for every subscriber before the slowest ones start missing events
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Magic bytes and version at the start of a mempool dump file
+const MEMPOOL_FILE_MAGIC: &[u8; 4] = b"MVMP";
+const MEMPOOL_FILE_VERSION: u8 = 1;
+/// Size of the length prefix and of the checksum of every record in a mempool dump file
+const RECORD_LENGTH_SIZE: usize = 4;
+const RECORD_CHECKSUM_SIZE: usize = 4;
+
+/// MempoolEntry is a transaction in the mempool along with the time it was added
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolEntry {
+    pub tx: proto::Transaction,
+    pub added_at: SystemTime,
+    /// Number of transactions with the same sender and nonce this transaction replaced
+    pub replacements: u32,
+}
+
+/// EvictionReason describes why a transaction left the mempool without being mined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionReason {
+    /// The transaction was in the mempool for longer than the TTL
+    TtlExceeded,
+    /// The transaction expired at the height or timestamp of a connected block
+    Expired,
+    /// A transaction of the same sender with the same or a higher nonce was mined
+    NonceConsumed,
+    /// The transaction was removed by hash
+    Removed,
+    /// The mempool was flushed
+    Flushed,
+    /// The mempool was full and the transaction had the lowest priority
+    Full,
+}
+
+/// MempoolEvent is emitted to the subscribers of the mempool every time a transaction enters or leaves it
+#[derive(Debug, Clone, PartialEq)]
+pub enum MempoolEvent {
+    /// A new transaction was added to the mempool
+    Added { hash: Hash, tx: proto::Transaction },
+    /// A transaction replaced a pending transaction with the same sender and nonce
+    Replaced { old: Hash, new: Hash, tx: proto::Transaction },
+    /// A transaction was evicted from the mempool without being mined
+    Evicted { hash: Hash, reason: EvictionReason },
+    /// A transaction was included in the block at the given height
+    Mined { hash: Hash, height: u64 },
+}
 
 /// Mempool struct is a pool of transactions that are not yet included in a block
 pub struct Mempool {
-    pub transactions: HashMap<String, proto::Transaction>,
+    pub transactions: HashMap<Hash, MempoolEntry>,
+    /// Maximum age of a transaction in the mempool, `None` keeps transactions until they are mined
+    pub ttl: Option<Duration>,
+    /// Maximum number of transactions and total encoded size of the transactions in the mempool
+    pub max_transactions: usize,
+    pub max_size: usize,
+    /// Hashes of the pending transactions of every sender by nonce
+    senders: HashMap<Vec<u8>, BTreeMap<i64, Hash>>,
+    /// Total encoded size of the transactions in the mempool
+    size: usize,
+    events: broadcast::Sender<MempoolEvent>,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool::new()
+    }
 }
 
 impl Mempool {
-    /// Create a new Mempool
+    /// Create a new Mempool with the default transaction TTL
     pub fn new() -> Self {
+        Mempool::with_ttl(Some(DEFAULT_TRANSACTION_TTL))
+    }
+
+    /// Create a new Mempool with the given transaction TTL
+    pub fn with_ttl(ttl: Option<Duration>) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
         Mempool {
             transactions: HashMap::new(),
+            ttl,
+            max_transactions: DEFAULT_MAX_TRANSACTIONS,
+            max_size: DEFAULT_MAX_SIZE,
+            senders: HashMap::new(),
+            size: 0,
+            events,
         }
     }
 
+    /// Subscribe to the events of the mempool. Every subscriber receives all the events emitted after it subscribed,
+    /// a subscriber that falls more than 1024 events behind misses the oldest ones and gets a `Lagged` error.
+    pub fn subscribe(&self) -> broadcast::Receiver<MempoolEvent> {
+        self.events.subscribe()
+    }
+
+    /// Send an event to the subscribers, if there are any
+    fn emit(&self, event: MempoolEvent) {
+        let _ = self.events.send(event);
+    }
+
     /// Flush the mempool by removing all transactions
     pub fn flush(&mut self) {
-        self.transactions.clear();
+        self.senders.clear();
+        self.size = 0;
+        for (hash, _) in self.transactions.drain().collect::<Vec<_>>() {
+            self.emit(MempoolEvent::Evicted { hash, reason: EvictionReason::Flushed });
+        }
+    }
+
+    /// Store an entry in the mempool and index it by sender and nonce
+    fn put(&mut self, hash: Hash, entry: MempoolEntry) {
+        self.size += entry.tx.encoded_len();
+        self.senders.entry(entry.tx.from.clone()).or_default().insert(entry.tx.nonce, hash);
+        self.transactions.insert(hash, entry);
+    }
+
+    /// Remove an entry from the mempool and from the sender index
+    fn take(&mut self, hash: &Hash) -> Option<MempoolEntry> {
+        let entry = self.transactions.remove(hash)?;
+        self.size -= entry.tx.encoded_len();
+        if let Some(nonces) = self.senders.get_mut(entry.tx.from.as_slice()) {
+            nonces.remove(&entry.tx.nonce);
+            if nonces.is_empty() {
+                self.senders.remove(entry.tx.from.as_slice());
+            }
+        }
+
+        Some(entry)
+    }
+
+    /// Returns the total encoded size in bytes of the transactions in the mempool
+    pub fn size(&self) -> usize {
+        self.size
     }
 
     /// Get the number of transactions in the mempool
@@ -28,27 +169,392 @@ impl Mempool {
         self.transactions.len()
     }
 
+    /// Check if the mempool is empty
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Check if a transaction with the given hash is in the mempool
+    pub fn has(&self, hash: &Hash) -> bool {
+        self.transactions.contains_key(hash)
+    }
+
     /// Check if a transaction is in the mempool
-    pub fn has(&self, tx: &mut proto::Transaction) -> bool {
-        let hash = types::transaction::hash_transaction(tx);
-        let hash_str = hex::encode(hash);
+    pub fn contains(&self, tx: &proto::Transaction) -> bool {
+        self.has(&types::transaction::transaction_hash(tx))
+    }
+
+    /// Get a transaction from the mempool given its hash
+    pub fn get(&self, hash: &Hash) -> Option<&proto::Transaction> {
+        self.transactions.get(hash).map(|entry| &entry.tx)
+    }
+
+    /// Add a transaction to the mempool, returning its hash.
+    /// A pending transaction with the same sender and nonce is replaced by the new one if the new one sends
+    /// a higher value, as long as the sender did not reach `MAX_REPLACEMENTS_PER_SENDER`.
+    pub fn add(&mut self, tx: &proto::Transaction) -> Result<Hash> {
+        let hash = types::transaction::transaction_hash(tx);
+        if self.has(&hash) {
+            return Err(MarvinError::MempoolRejected(String::from("Transaction already exists in the mempool")));
+        }
+
+        self.insert(hash, MempoolEntry {
+            tx: tx.clone(),
+            added_at: SystemTime::now(),
+            replacements: 0,
+        })?;
 
-        self.transactions.contains_key(&hash_str)
+        Ok(hash)
     }
 
-    /// Add a transaction to the mempool
-    pub fn add(&mut self, tx: &mut proto::Transaction) -> Result<()> {
-        if self.has(tx) {
-            return Err(MarvinError::General(String::from("Transaction already exists in the mempool")));
+    /// Insert an entry in the mempool, replacing the pending transaction with the same sender and nonce
+    /// if the replacement rules allow it, and evicting lower priority transactions if the mempool is full
+    fn insert(&mut self, hash: Hash, mut entry: MempoolEntry) -> Result<()> {
+        let nonces = self.senders.get(entry.tx.from.as_slice());
+        let replaced = nonces.and_then(|nonces| nonces.get(&entry.tx.nonce)).copied();
+
+        if let Some(old) = replaced {
+            let existing = &self.transactions[&old];
+            if entry.tx.value <= existing.tx.value {
+                return Err(MarvinError::MempoolRejected(format!(
+                    "Replacement transaction must send more than the {} sent by the transaction it replaces", existing.tx.value
+                )));
+            }
+            let sender_replacements: u32 = nonces
+                .into_iter()
+                .flat_map(|nonces| nonces.values())
+                .map(|hash| self.transactions[hash].replacements)
+                .sum();
+            if sender_replacements >= MAX_REPLACEMENTS_PER_SENDER {
+                return Err(MarvinError::MempoolRejected(String::from("Too many replaced transactions for the sender")));
+            }
+            entry.replacements = existing.replacements + 1;
         }
 
-        let hash = types::transaction::hash_transaction(tx);
-        let hash_str = hex::encode(hash);
+        self.make_room(&entry, replaced)?;
 
-        self.transactions.insert(hash_str, tx.clone());
+        let tx = entry.tx.clone();
+        match replaced {
+            Some(old) => {
+                self.take(&old);
+                self.put(hash, entry);
+                self.emit(MempoolEvent::Replaced { old, new: hash, tx });
+            }
+            None => {
+                self.put(hash, entry);
+                self.emit(MempoolEvent::Added { hash, tx });
+            }
+        }
 
         Ok(())
     }
+
+    /// Evict the lowest priority transactions until the entry fits in the mempool in place of the transaction
+    /// it replaces, if any. The lowest priority transaction is the last pending transaction of the sender whose
+    /// last transaction arrived most recently, so evictions never leave a gap in the nonces of a sender.
+    /// Fails if the entry itself would be the lowest priority transaction of the mempool.
+    fn make_room(&mut self, entry: &MempoolEntry, replaced: Option<Hash>) -> Result<()> {
+        let freed = replaced.map(|hash| self.transactions[&hash].tx.encoded_len()).unwrap_or(0);
+        loop {
+            let count = self.transactions.len() + 1 - replaced.is_some() as usize;
+            if count <= self.max_transactions && self.size + entry.tx.encoded_len() - freed <= self.max_size {
+                return Ok(());
+            }
+
+            // The entry competes for the last place only when no later nonce of its sender is pending
+            let last = self
+                .senders
+                .get(entry.tx.from.as_slice())
+                .and_then(|nonces| nonces.keys().next_back().copied())
+                .is_none_or(|nonce| nonce <= entry.tx.nonce);
+            let lowest = self
+                .senders
+                .values()
+                .filter_map(|nonces| nonces.values().next_back())
+                .map(|hash| (self.transactions[hash].added_at, *hash))
+                .max();
+
+            match lowest {
+                Some((added_at, hash)) if Some(hash) != replaced && (!last || added_at > entry.added_at) => {
+                    self.take(&hash);
+                    self.emit(MempoolEvent::Evicted { hash, reason: EvictionReason::Full });
+                }
+                _ => return Err(MarvinError::MempoolRejected(String::from("Mempool is full"))),
+            }
+        }
+    }
+
+    /// Remove a transaction from the mempool given its hash, returning it if it was in the mempool
+    pub fn remove(&mut self, hash: &Hash) -> Option<proto::Transaction> {
+        let entry = self.take(hash)?;
+        self.emit(MempoolEvent::Evicted { hash: *hash, reason: EvictionReason::Removed });
+
+        Some(entry.tx)
+    }
+
+    /// Returns the pending transactions sent from or to the given address, in priority order
+    pub fn transactions_for_address(&self, address: &Address) -> Vec<&proto::Transaction> {
+        self.by_priority()
+            .into_iter()
+            .filter(|tx| {
+                types::transaction::sender_address(tx).as_ref() == Some(address)
+                    || types::transaction::recipient_address(tx).as_ref() == Some(address)
+            })
+            .collect()
+    }
+
+    /// Returns the total value sent by the pending transactions of a sender, given by its public key, leaving out
+    /// the transaction with the given nonce since a new transaction with that nonce would replace it
+    pub fn pending_spend(&self, from: &[u8], nonce: i64) -> u64 {
+        self.senders
+            .get(from)
+            .into_iter()
+            .flat_map(|nonces| nonces.iter())
+            .filter(|(pending, _)| **pending != nonce)
+            .fold(0u64, |total, (_, hash)| total.saturating_add(self.transactions[hash].tx.value))
+    }
+
+    /// Returns the transactions of the mempool in priority order, which is the order they should be mined in.
+    /// The transactions of every sender are ordered by nonce, and senders are interleaved by the time their
+    /// next transaction arrived in the mempool, so older transactions come first without breaking nonce order.
+    pub fn by_priority(&self) -> Vec<&proto::Transaction> {
+        // Group the entries by sender, highest nonce first so the next transaction can be popped from the end
+        let mut senders: HashMap<&[u8], Vec<(&Hash, &MempoolEntry)>> = HashMap::new();
+        for (hash, entry) in self.transactions.iter() {
+            senders.entry(entry.tx.from.as_slice()).or_default().push((hash, entry));
+        }
+        let mut queues: Vec<Vec<(&Hash, &MempoolEntry)>> = senders
+            .into_values()
+            .map(|mut queue| {
+                queue.sort_by_key(|(hash, entry)| Reverse((entry.tx.nonce, entry.added_at, **hash)));
+                queue
+            })
+            .collect();
+
+        // Pick the oldest transaction among the next transaction of every sender, ties are broken by hash
+        let mut heap = BinaryHeap::new();
+        for (index, queue) in queues.iter().enumerate() {
+            if let Some((hash, entry)) = queue.last() {
+                heap.push(Reverse((entry.added_at, **hash, index)));
+            }
+        }
+
+        let mut ordered = Vec::with_capacity(self.transactions.len());
+        while let Some(Reverse((_, _, index))) = heap.pop() {
+            let (_, entry) = queues[index].pop().unwrap();
+            ordered.push(&entry.tx);
+
+            if let Some((hash, entry)) = queues[index].last() {
+                heap.push(Reverse((entry.added_at, **hash, index)));
+            }
+        }
+
+        ordered
+    }
+
+    /// Drop the transactions that have been in the mempool for longer than the TTL.
+    /// Returns the number of transactions removed.
+    pub fn sweep_expired(&mut self) -> usize {
+        self.sweep_expired_at(SystemTime::now())
+    }
+
+    /// Drop the transactions that are older than the TTL at the given point in time
+    fn sweep_expired_at(&mut self, now: SystemTime) -> usize {
+        let ttl = match self.ttl {
+            Some(ttl) => ttl,
+            None => return 0,
+        };
+
+        let expired: Vec<Hash> = self
+            .transactions
+            .iter()
+            .filter(|(_, entry)| matches!(now.duration_since(entry.added_at), Ok(age) if age > ttl))
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        for hash in expired.iter() {
+            self.take(hash);
+            self.emit(MempoolEvent::Evicted { hash: *hash, reason: EvictionReason::TtlExceeded });
+        }
+
+        expired.len()
+    }
+
+    /// Dump the contents of the mempool to a file, returning the number of transactions written.
+    /// Every transaction is written as an independent record with its own length prefix and checksum,
+    /// so a partially written or corrupted file only loses the affected records.
+    /// The file is written to a temporary path first and then renamed over the destination.
+    pub fn save(&self, path: &Path) -> Result<usize> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MEMPOOL_FILE_MAGIC);
+        buf.push(MEMPOOL_FILE_VERSION);
+
+        for entry in self.transactions.values() {
+            let added_at = entry.added_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as i64;
+            let record = proto::MempoolEntry {
+                transaction: Some(entry.tx.clone()),
+                added_at,
+            };
+
+            let payload = record.encode_to_vec();
+            buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            buf.extend_from_slice(&record_checksum(&payload));
+            buf.extend_from_slice(&payload);
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, &buf).map_err(|e| MarvinError::Storage(e.to_string()))?;
+        fs::rename(&tmp_path, path).map_err(|e| MarvinError::Storage(e.to_string()))?;
+
+        Ok(self.transactions.len())
+    }
+
+    /// Read the transactions dumped by `save`, with the time they were added to the mempool.
+    /// Records with a bad checksum or that fail to decode are skipped, and a truncated record at the end of
+    /// the file stops the read. A missing file holds no transactions.
+    /// The transactions are not validated, see `Blockchain::load_mempool`.
+    pub fn read(path: &Path) -> Result<Vec<MempoolEntry>> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(MarvinError::Storage(e.to_string())),
+        };
+
+        let header_size = MEMPOOL_FILE_MAGIC.len() + 1;
+        if data.len() < header_size || &data[..MEMPOOL_FILE_MAGIC.len()] != MEMPOOL_FILE_MAGIC {
+            return Err(MarvinError::Storage(String::from("Invalid mempool file")));
+        }
+        if data[MEMPOOL_FILE_MAGIC.len()] != MEMPOOL_FILE_VERSION {
+            return Err(MarvinError::Storage(format!("Unsupported mempool file version {}", data[MEMPOOL_FILE_MAGIC.len()])));
+        }
+
+        let mut entries = Vec::new();
+        let mut offset = header_size;
+        while offset + RECORD_LENGTH_SIZE + RECORD_CHECKSUM_SIZE <= data.len() {
+            let length = u32::from_be_bytes(data[offset..offset + RECORD_LENGTH_SIZE].try_into().unwrap()) as usize;
+            let checksum = &data[offset + RECORD_LENGTH_SIZE..offset + RECORD_LENGTH_SIZE + RECORD_CHECKSUM_SIZE];
+            let start = offset + RECORD_LENGTH_SIZE + RECORD_CHECKSUM_SIZE;
+            if length > data.len() - start {
+                break;
+            }
+
+            let payload = &data[start..start + length];
+            offset = start + length;
+
+            if checksum != record_checksum(payload) {
+                continue;
+            }
+            let record = match proto::MempoolEntry::decode(payload) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            let tx = match record.transaction {
+                Some(tx) => tx,
+                None => continue,
+            };
+
+            let added_at = UNIX_EPOCH + Duration::from_nanos(record.added_at.max(0) as u64);
+            entries.push(MempoolEntry { tx, added_at, replacements: 0 });
+        }
+
+        Ok(entries)
+    }
+
+    /// Add a transaction read from a dump file, keeping the time it was first added to the mempool.
+    /// Transactions older than the TTL are rejected, otherwise the rules of `add` apply.
+    pub fn restore(&mut self, entry: MempoolEntry) -> Result<Hash> {
+        if let Some(ttl) = self.ttl {
+            if SystemTime::now().duration_since(entry.added_at).unwrap_or_default() > ttl {
+                return Err(MarvinError::MempoolRejected(String::from("Transaction is older than the mempool TTL")));
+            }
+        }
+        let hash = types::transaction::transaction_hash(&entry.tx);
+        if self.has(&hash) {
+            return Err(MarvinError::MempoolRejected(String::from("Transaction already exists in the mempool")));
+        }
+
+        self.insert(hash, entry)?;
+
+        Ok(hash)
+    }
+
+    /// Remove the transactions included in a connected block from the mempool.
+    /// Pending transactions that can no longer be mined, because their sender already has
+    /// a transaction with the same or a higher nonce in the block or because they expired
+    /// at the block height or timestamp, are evicted as well.
+    /// Returns the number of transactions removed.
+    pub fn remove_block_transactions(&mut self, block: &proto::Block) -> usize {
+        let before = self.transactions.len();
+
+        let (height, timestamp) = match block.header.as_ref() {
+            Some(header) => (header.height, header.timestamp),
+            None => (0, 0),
+        };
+
+        // Highest nonce included in the block for every sender
+        let mut included_nonces: HashMap<&[u8], i64> = HashMap::new();
+        for tx in block.transactions.iter() {
+            let hash = types::transaction::transaction_hash(tx);
+            if self.take(&hash).is_some() {
+                self.emit(MempoolEvent::Mined { hash, height });
+            }
+
+            let nonce = included_nonces.entry(tx.from.as_slice()).or_insert(tx.nonce);
+            *nonce = (*nonce).max(tx.nonce);
+        }
+
+        let evicted: Vec<(Hash, EvictionReason)> = self
+            .transactions
+            .iter()
+            .filter_map(|(hash, entry)| {
+                if types::transaction::is_expired(&entry.tx, height, timestamp) {
+                    return Some((*hash, EvictionReason::Expired));
+                }
+
+                match included_nonces.get(entry.tx.from.as_slice()) {
+                    Some(nonce) if entry.tx.nonce <= *nonce => Some((*hash, EvictionReason::NonceConsumed)),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        for (hash, reason) in evicted {
+            self.take(&hash);
+            self.emit(MempoolEvent::Evicted { hash, reason });
+        }
+
+        before - self.transactions.len()
+    }
+
+    /// Re-inject the transactions of a disconnected block into the mempool.
+    /// Every transaction is revalidated first, transactions that fail validation or are
+    /// already in the mempool are dropped. Returns the number of transactions added back.
+    pub fn reinject_block_transactions(&mut self, block: &proto::Block) -> usize {
+        let mut added = 0;
+
+        for tx in block.transactions.iter() {
+            if types::transaction::verify_transaction(&mut tx.clone()).is_err() {
+                continue;
+            }
+
+            if self.add(tx).is_ok() {
+                added += 1;
+            }
+        }
+
+        added
+    }
+}
+
+/// Calculate the checksum of a record in a mempool dump file
+fn record_checksum(payload: &[u8]) -> [u8; RECORD_CHECKSUM_SIZE] {
+    let mut hasher = Sha256::new();
+    hasher.input(payload);
+
+    let mut hash = [0; 32];
+    hasher.result(&mut hash);
+
+    hash[..RECORD_CHECKSUM_SIZE].try_into().unwrap()
 }
 
 #[cfg(test)]
@@ -69,7 +575,7 @@ mod tests {
         let mut mempool = Mempool::new();
 
         let mnemonic = "all wild paddle pride wheat menu task funny sign profit blouse hockey";
-        let mut private_key = keys::get_private_key_from_mnemonic(&mnemonic).unwrap();
+        let mut private_key = keys::get_private_key_from_mnemonic(mnemonic).unwrap();
         let public_key = private_key.public_key();
 
         let mut tx = proto::Transaction {
@@ -80,13 +586,435 @@ mod tests {
             signature: [0; 64].to_vec(),
             nonce: 123,
             hash: [0; 32].to_vec(),
+            valid_until_height: 0,
+            valid_until_timestamp: 0,
         };
         let _ = types::transaction::sign_transaction(&mut private_key, &mut tx).unwrap();
 
-        mempool.add(&mut tx).unwrap();
+        mempool.add(&tx).unwrap();
         assert_eq!(mempool.len(), 1);
 
+        // Adding the same transaction twice is rejected
+        assert!(mempool.add(&tx).is_err());
+
         mempool.flush();
         assert_eq!(mempool.len(), 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_replace_transaction() {
+        let mut mempool = Mempool::new();
+        let mut private_key = keys::generate_private_key();
+
+        let original = signed_transaction(&mut private_key, 1);
+        let mut replacement = unsigned_transaction(&private_key, 1);
+        replacement.value = 2000;
+        types::transaction::sign_transaction(&mut private_key, &mut replacement).unwrap();
+
+        mempool.add(&original).unwrap();
+        mempool.add(&replacement).unwrap();
+
+        assert_eq!(mempool.len(), 1);
+        assert!(!mempool.contains(&original));
+        assert!(mempool.contains(&replacement));
+
+        // A replacement must send more than the transaction it replaces
+        let mut lower = unsigned_transaction(&private_key, 1);
+        lower.value = 1500;
+        types::transaction::sign_transaction(&mut private_key, &mut lower).unwrap();
+        assert!(matches!(mempool.add(&lower), Err(MarvinError::MempoolRejected(_))));
+        assert!(mempool.contains(&replacement));
+    }
+
+    #[test]
+    fn test_replacement_limit() {
+        let mut mempool = Mempool::new();
+        let mut private_key = keys::generate_private_key();
+
+        let mut replace = |mempool: &mut Mempool, nonce: i64, value: u64| {
+            let mut tx = unsigned_transaction(&private_key, nonce);
+            tx.value = value;
+            types::transaction::sign_transaction(&mut private_key, &mut tx).unwrap();
+            mempool.add(&tx)
+        };
+
+        // The limit is shared by all the transactions of the sender
+        replace(&mut mempool, 1, 0).unwrap();
+        replace(&mut mempool, 2, 0).unwrap();
+        for value in 1..=MAX_REPLACEMENTS_PER_SENDER as u64 {
+            replace(&mut mempool, 1 + value as i64 % 2, value).unwrap();
+        }
+        let error = replace(&mut mempool, 1, 1000).unwrap_err();
+        assert_eq!(error, MarvinError::MempoolRejected(String::from("Too many replaced transactions for the sender")));
+        assert_eq!(mempool.len(), 2);
+
+        // Other senders can still replace their transactions
+        let mut other = keys::generate_private_key();
+        let mut tx = unsigned_transaction(&other, 1);
+        types::transaction::sign_transaction(&mut other, &mut tx).unwrap();
+        mempool.add(&tx).unwrap();
+        tx.value += 1;
+        types::transaction::sign_transaction(&mut other, &mut tx).unwrap();
+        mempool.add(&tx).unwrap();
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut mempool = Mempool::new();
+        let mut events = mempool.subscribe();
+        let mut private_key = keys::generate_private_key();
+
+        let tx1 = signed_transaction(&mut private_key, 1);
+        let mut tx1_replacement = unsigned_transaction(&private_key, 1);
+        tx1_replacement.value = 2000;
+        types::transaction::sign_transaction(&mut private_key, &mut tx1_replacement).unwrap();
+        let tx2 = signed_transaction(&mut private_key, 2);
+        let tx3 = signed_transaction(&mut private_key, 3);
+
+        let hash1 = mempool.add(&tx1).unwrap();
+        let hash1_replacement = mempool.add(&tx1_replacement).unwrap();
+        let hash2 = mempool.add(&tx2).unwrap();
+        let hash3 = mempool.add(&tx3).unwrap();
+
+        let block = proto::Block {
+            header: Some(proto::Header {
+                height: 7,
+                ..Default::default()
+            }),
+            transactions: vec![tx2.clone()],
+            ..Default::default()
+        };
+        mempool.remove_block_transactions(&block);
+        mempool.remove(&hash3);
+
+        assert_eq!(events.try_recv().unwrap(), MempoolEvent::Added { hash: hash1, tx: tx1 });
+        assert_eq!(
+            events.try_recv().unwrap(),
+            MempoolEvent::Replaced { old: hash1, new: hash1_replacement, tx: tx1_replacement }
+        );
+        assert_eq!(events.try_recv().unwrap(), MempoolEvent::Added { hash: hash2, tx: tx2 });
+        assert_eq!(events.try_recv().unwrap(), MempoolEvent::Added { hash: hash3, tx: tx3 });
+        assert_eq!(events.try_recv().unwrap(), MempoolEvent::Mined { hash: hash2, height: 7 });
+        assert_eq!(
+            events.try_recv().unwrap(),
+            MempoolEvent::Evicted { hash: hash1_replacement, reason: EvictionReason::NonceConsumed }
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            MempoolEvent::Evicted { hash: hash3, reason: EvictionReason::Removed }
+        );
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_get_and_remove_by_hash() {
+        let mut mempool = Mempool::new();
+        let mut private_key = keys::generate_private_key();
+
+        let tx = signed_transaction(&mut private_key, 1);
+        let before = tx.clone();
+        let hash = mempool.add(&tx).unwrap();
+
+        // Queries never mutate the transaction
+        assert_eq!(tx, before);
+        assert_eq!(hash, types::transaction::transaction_hash(&tx));
+        assert!(mempool.has(&hash));
+        assert_eq!(mempool.get(&hash), Some(&tx));
+
+        assert_eq!(mempool.remove(&hash), Some(tx));
+        assert!(!mempool.has(&hash));
+        assert_eq!(mempool.get(&hash), None);
+        assert_eq!(mempool.remove(&hash), None);
+    }
+
+    #[test]
+    fn test_by_priority() {
+        let mut mempool = Mempool::new();
+        let mut alice = keys::generate_private_key();
+        let mut bob = keys::generate_private_key();
+
+        let alice_2 = signed_transaction(&mut alice, 2);
+        let bob_1 = signed_transaction(&mut bob, 1);
+        let alice_1 = signed_transaction(&mut alice, 1);
+        let bob_2 = signed_transaction(&mut bob, 2);
+
+        let now = SystemTime::now();
+        for (i, tx) in [&alice_2, &bob_1, &alice_1, &bob_2].iter().enumerate() {
+            let hash = mempool.add(tx).unwrap();
+            mempool.transactions.get_mut(&hash).unwrap().added_at = now + Duration::from_secs(i as u64);
+        }
+
+        // alice_2 arrived first but has to wait for alice_1, which arrived after bob_1
+        let ordered = mempool.by_priority();
+        assert_eq!(ordered, vec![&bob_1, &alice_1, &alice_2, &bob_2]);
+    }
+
+    #[test]
+    fn test_mempool_limits() {
+        let mut mempool = Mempool::new();
+        let mut events = mempool.subscribe();
+        let (mut alice, mut bob, mut carol) = (keys::generate_private_key(), keys::generate_private_key(), keys::generate_private_key());
+
+        let pending = signed_transaction(&mut alice, 1);
+        mempool.add(&pending).unwrap();
+        mempool.add(&signed_transaction(&mut bob, 0)).unwrap();
+        let newest = signed_transaction(&mut carol, 0);
+        mempool.add(&newest).unwrap();
+        mempool.max_transactions = 3;
+
+        // A transaction arriving last has the lowest priority
+        let error = mempool.add(&signed_transaction(&mut keys::generate_private_key(), 0)).unwrap_err();
+        assert_eq!(error, MarvinError::MempoolRejected(String::from("Mempool is full")));
+        assert!(matches!(mempool.add(&signed_transaction(&mut carol, 1)), Err(MarvinError::MempoolRejected(_))));
+
+        // A transaction mined before a pending transaction of its sender evicts the most recent last transaction
+        let first = signed_transaction(&mut alice, 0);
+        mempool.add(&first).unwrap();
+        assert_eq!(mempool.len(), 3);
+        assert!(mempool.contains(&first));
+        assert!(!mempool.contains(&newest));
+        let evicted = MempoolEvent::Evicted { hash: types::transaction::transaction_hash(&newest), reason: EvictionReason::Full };
+        assert!(std::iter::from_fn(|| events.try_recv().ok()).any(|event| event == evicted));
+
+        // The size of the mempool is limited as well
+        mempool.max_transactions = 10;
+        mempool.max_size = mempool.size();
+        assert!(matches!(mempool.add(&signed_transaction(&mut bob, 1)), Err(MarvinError::MempoolRejected(_))));
+        mempool.remove(&types::transaction::transaction_hash(&pending));
+        assert_eq!(mempool.size(), mempool.transactions.values().map(|entry| entry.tx.encoded_len()).sum::<usize>());
+        mempool.add(&signed_transaction(&mut bob, 1)).unwrap();
+    }
+
+    #[test]
+    fn test_pending_spend() {
+        let mut mempool = Mempool::new();
+        let mut private_key = keys::generate_private_key();
+        let from = private_key.public_key().to_bytes().to_vec();
+
+        mempool.add(&signed_transaction(&mut private_key, 0)).unwrap();
+        mempool.add(&signed_transaction(&mut private_key, 1)).unwrap();
+        mempool.add(&signed_transaction(&mut keys::generate_private_key(), 0)).unwrap();
+
+        assert_eq!(mempool.pending_spend(&from, 2), 2000);
+        assert_eq!(mempool.pending_spend(&from, 1), 1000);
+        mempool.flush();
+        assert_eq!(mempool.pending_spend(&from, 2), 0);
+    }
+
+    #[test]
+    fn test_transactions_for_address() {
+        let mut mempool = Mempool::new();
+        let mut alice = keys::generate_private_key();
+        let mut bob = keys::generate_private_key();
+
+        let from_alice = signed_transaction(&mut alice, 1);
+        let mut to_alice = unsigned_transaction(&bob, 1);
+        to_alice.to = alice.public_key().to_bytes().to_vec();
+        types::transaction::sign_transaction(&mut bob, &mut to_alice).unwrap();
+        let unrelated = signed_transaction(&mut bob, 2);
+
+        mempool.add(&from_alice).unwrap();
+        mempool.add(&to_alice).unwrap();
+        mempool.add(&unrelated).unwrap();
+
+        let alice_transactions = mempool.transactions_for_address(&alice.public_key().address());
+        assert_eq!(alice_transactions.len(), 2);
+        assert!(alice_transactions.contains(&&from_alice));
+        assert!(alice_transactions.contains(&&to_alice));
+
+        let other = keys::generate_private_key().public_key().address();
+        assert!(mempool.transactions_for_address(&other).is_empty());
+    }
+
+    #[test]
+    fn test_remove_block_transactions() {
+        let mut mempool = Mempool::new();
+        let mut private_key = keys::generate_private_key();
+        let mut other_private_key = keys::generate_private_key();
+
+        let mined = signed_transaction(&mut private_key, 2);
+        let stale = signed_transaction(&mut private_key, 1);
+        let pending = signed_transaction(&mut private_key, 3);
+        let other = signed_transaction(&mut other_private_key, 1);
+
+        mempool.add(&mined).unwrap();
+        mempool.add(&stale).unwrap();
+        mempool.add(&pending).unwrap();
+        mempool.add(&other).unwrap();
+
+        let block = proto::Block {
+            transactions: vec![mined.clone()],
+            ..Default::default()
+        };
+
+        let removed = mempool.remove_block_transactions(&block);
+        assert_eq!(removed, 2);
+        assert_eq!(mempool.len(), 2);
+        assert!(!mempool.contains(&mined));
+        assert!(!mempool.contains(&stale));
+        assert!(mempool.contains(&pending));
+        assert!(mempool.contains(&other));
+    }
+
+    #[test]
+    fn test_reinject_block_transactions() {
+        let mut mempool = Mempool::new();
+        let mut private_key = keys::generate_private_key();
+
+        let valid = signed_transaction(&mut private_key, 1);
+        let mut tampered = signed_transaction(&mut private_key, 2);
+        tampered.value += 1;
+
+        let block = proto::Block {
+            transactions: vec![valid.clone(), tampered.clone()],
+            ..Default::default()
+        };
+
+        let added = mempool.reinject_block_transactions(&block);
+        assert_eq!(added, 1);
+        assert!(mempool.contains(&valid));
+        assert!(!mempool.contains(&tampered));
+
+        // Re-injecting again does not duplicate transactions
+        assert_eq!(mempool.reinject_block_transactions(&block), 0);
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_block_transactions_expired() {
+        let mut mempool = Mempool::new();
+        let mut private_key = keys::generate_private_key();
+
+        let mut expiring = unsigned_transaction(&private_key, 1);
+        expiring.valid_until_height = 1;
+        types::transaction::sign_transaction(&mut private_key, &mut expiring).unwrap();
+        let lasting = signed_transaction(&mut private_key, 2);
+
+        mempool.add(&expiring).unwrap();
+        mempool.add(&lasting).unwrap();
+
+        let mut block = proto::Block {
+            header: Some(proto::Header {
+                height: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(mempool.remove_block_transactions(&block), 0);
+
+        block.header.as_mut().unwrap().height = 2;
+        assert_eq!(mempool.remove_block_transactions(&block), 1);
+        assert!(!mempool.contains(&expiring));
+        assert!(mempool.contains(&lasting));
+    }
+
+    #[test]
+    fn test_sweep_expired() {
+        let mut mempool = Mempool::with_ttl(Some(Duration::from_secs(60)));
+        let mut private_key = keys::generate_private_key();
+
+        let old = signed_transaction(&mut private_key, 1);
+        let fresh = signed_transaction(&mut private_key, 2);
+        mempool.add(&old).unwrap();
+        mempool.add(&fresh).unwrap();
+
+        let now = SystemTime::now();
+        let old_hash = types::transaction::transaction_hash(&old);
+        mempool.transactions.get_mut(&old_hash).unwrap().added_at = now - Duration::from_secs(120);
+
+        assert_eq!(mempool.sweep_expired_at(now), 1);
+        assert!(!mempool.contains(&old));
+        assert!(mempool.contains(&fresh));
+
+        // Without a TTL nothing is ever swept
+        mempool.ttl = None;
+        assert_eq!(mempool.sweep_expired_at(now + Duration::from_secs(3600)), 0);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("marvin-mempool-{}.dat", hex::encode(crate::crypto::keys::new_entropy())));
+        let mut private_key = keys::generate_private_key();
+
+        let mut mempool = Mempool::new();
+        let tx1 = signed_transaction(&mut private_key, 1);
+        let tx2 = signed_transaction(&mut private_key, 2);
+        mempool.add(&tx1).unwrap();
+        mempool.add(&tx2).unwrap();
+        assert_eq!(mempool.save(&path).unwrap(), 2);
+
+        let mut restored = Mempool::new();
+        let entries = Mempool::read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        for entry in entries {
+            restored.restore(entry).unwrap();
+        }
+        assert!(restored.contains(&tx1));
+        assert!(restored.contains(&tx2));
+        assert_eq!(restored.transactions, mempool.transactions);
+
+        // Restoring twice does not duplicate transactions
+        let entry = Mempool::read(&path).unwrap().remove(0);
+        assert!(matches!(restored.restore(entry), Err(MarvinError::MempoolRejected(_))));
+
+        // Transactions older than the TTL are dropped
+        let mut entry = Mempool::read(&path).unwrap().remove(0);
+        entry.added_at -= DEFAULT_TRANSACTION_TTL * 2;
+        assert!(matches!(Mempool::new().restore(entry), Err(MarvinError::MempoolRejected(_))));
+
+        fs::remove_file(&path).unwrap();
+        assert!(Mempool::read(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_load_corrupted_file() {
+        let path = std::env::temp_dir().join(format!("marvin-mempool-{}.dat", hex::encode(crate::crypto::keys::new_entropy())));
+        let mut private_key = keys::generate_private_key();
+
+        let mut mempool = Mempool::new();
+        let tx = signed_transaction(&mut private_key, 1);
+        mempool.add(&tx).unwrap();
+        mempool.save(&path).unwrap();
+        let valid_record = fs::read(&path).unwrap()[5..].to_vec();
+
+        // A corrupted record followed by a valid one and a truncated one
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        data.extend_from_slice(&valid_record);
+        data.extend_from_slice(&valid_record[..valid_record.len() / 2]);
+        fs::write(&path, &data).unwrap();
+
+        let entries = Mempool::read(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].tx, tx);
+
+        fs::write(&path, b"not a mempool").unwrap();
+        assert!(Mempool::read(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn signed_transaction(private_key: &mut keys::PrivateKey, nonce: i64) -> proto::Transaction {
+        let mut tx = unsigned_transaction(private_key, nonce);
+        types::transaction::sign_transaction(private_key, &mut tx).unwrap();
+
+        tx
+    }
+
+    fn unsigned_transaction(private_key: &keys::PrivateKey, nonce: i64) -> proto::Transaction {
+        let public_key = private_key.public_key();
+
+        proto::Transaction {
+            from: public_key.to_bytes().to_vec(),
+            to: keys::generate_private_key().public_key().to_bytes().to_vec(),
+            value: 1000,
+            data: b"Transaction data".to_vec(),
+            signature: [0; 64].to_vec(),
+            nonce,
+            hash: [0; 32].to_vec(),
+            valid_until_height: 0,
+            valid_until_timestamp: 0,
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::core::blockchain::Blockchain;
+use crate::core::state::PendingState;
+use crate::crypto::keys::PrivateKey;
+use crate::error::{Result, MarvinError};
+use crate::proto;
+use crate::types;
+use crate::types::limits::DEFAULT_MAX_BLOCK_SIZE;
+use crate::utils::log::make_json_logger;
+
+use prost::Message;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Default time between two blocks mined by the node
+pub const DEFAULT_BLOCK_INTERVAL: Duration = Duration::from_secs(10);
+/// Room left in a block template for the header, the public key and the signature of the block
+const BLOCK_OVERHEAD: usize = 1024;
+
+/// Miner builds blocks from the mempool on top of the tip of the blockchain, mines and signs them.
+/// Mined blocks are added to the blockchain, and the node gossips them to its peers.
+pub struct Miner {
+    blockchain: Arc<Mutex<Blockchain>>,
+    private_key: PrivateKey,
+    interval: Duration,
+    logger: slog::Logger,
+}
+
+impl Miner {
+    pub fn new(blockchain: Arc<Mutex<Blockchain>>, private_key: PrivateKey, interval: Duration) -> Self {
+        Miner {
+            blockchain,
+            private_key,
+            interval,
+            logger: make_json_logger(),
+        }
+    }
+
+    /// Mine a block every interval until the task is aborted
+    pub async fn run(mut self) {
+        let mut tick = tokio::time::interval(self.interval);
+        tick.tick().await;
+
+        loop {
+            tick.tick().await;
+            match self.mine_block().await {
+                Ok(block) => info!(self.logger, "Block mined";
+                    "height" => block.header.as_ref().map(|header| header.height).unwrap_or_default(),
+                    "hash" => types::block::block_hash(&block).to_string(),
+                    "transactions" => block.transactions.len()
+                ),
+                Err(e) => warn!(self.logger, "Failed to mine a block"; "error" => e.to_string()),
+            }
+        }
+    }
+
+    /// Mine a block on top of the current tip and add it to the blockchain.
+    /// The proof of work runs on a blocking thread, if the tip changed in the meantime the block is rejected.
+    pub async fn mine_block(&mut self) -> Result<proto::Block> {
+        let mut block = block_template(&self.blockchain.lock().unwrap());
+
+        let mut header = block.header.take().unwrap_or_default();
+        let header = tokio::task::spawn_blocking(move || {
+            types::block::mine_header(&mut header);
+            header
+        })
+        .await
+        .map_err(|e| MarvinError::Internal(e.to_string()))?;
+        block.header = Some(header);
+
+        types::block::sign_block(&mut self.private_key, &mut block)?;
+        self.blockchain.lock().unwrap().add_block(block.clone())?;
+
+        Ok(block)
+    }
+}
+
+/// Returns an unmined and unsigned block following the tip of the blockchain, with the transactions of the
+/// mempool in priority order. Expired transactions, transactions that do not use the next nonce of their sender
+/// and transactions sending more than their sender holds are left out, and transactions stop being added once the block reaches the maximum block size.
+pub fn block_template(blockchain: &Blockchain) -> proto::Block {
+    let prev = blockchain.headers.last().unwrap();
+    let height = prev.height + 1;
+    let now = Blockchain::get_current_timestamp_as_unix_nano() as i64;
+    let timestamp = now.max(prev.timestamp + 1);
+
+    let mut block = proto::Block {
+        header: Some(proto::Header {
+            height,
+            version: 1,
+            timestamp,
+            prev_block_hash: types::block::hash_header(prev),
+            difficulty: blockchain.difficulty,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let mut size = BLOCK_OVERHEAD;
+    let mut pending = PendingState::new(&blockchain.state);
+    for tx in blockchain.mempool.by_priority() {
+        if types::transaction::is_expired(tx, height, timestamp) {
+            continue;
+        }
+
+        size += tx.encoded_len() + 4;
+        if size > DEFAULT_MAX_BLOCK_SIZE {
+            break;
+        }
+        if pending.apply(tx).is_err() {
+            size -= tx.encoded_len() + 4;
+            continue;
+        }
+        types::block::add_transaction(&mut block, tx.clone());
+    }
+    block.header.as_mut().unwrap().tx_hash = types::block::calculate_tx_hash(&block.transactions);
+
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::storage::MemoryStore;
+    use crate::crypto::keys;
+    use crate::rpc::methods::tests::transfer;
+
+    #[tokio::test]
+    async fn test_mine_block() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let mut alice = keys::generate_private_key();
+        let bob = keys::generate_private_key();
+
+        let first = transfer(&mut alice, &bob, 0, 0);
+        let second = transfer(&mut alice, &bob, 0, 1);
+        blockchain.add_transaction(&second).unwrap();
+        blockchain.add_transaction(&first).unwrap();
+
+        let template = block_template(&blockchain);
+        assert_eq!(template.header.as_ref().unwrap().height, 1);
+        assert_eq!(template.transactions, vec![first, second]);
+
+        let blockchain = Arc::new(Mutex::new(blockchain));
+        let mut miner = Miner::new(blockchain.clone(), keys::generate_private_key(), DEFAULT_BLOCK_INTERVAL);
+        let block = miner.mine_block().await.unwrap();
+
+        let blockchain = blockchain.lock().unwrap();
+        assert_eq!(blockchain.height(), 1);
+        assert_eq!(blockchain.state.account(&alice.public_key().address()).nonce, 2);
+        assert!(blockchain.mempool.is_empty());
+        assert!(block_template(&blockchain).transactions.is_empty());
+        assert!(block.header.unwrap().timestamp > blockchain.headers.get(0).unwrap().timestamp);
+    }
+
+    #[test]
+    fn test_block_template_skips_unminable_transactions() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let mut alice = keys::generate_private_key();
+        let mut carol = keys::generate_private_key();
+        let bob = keys::generate_private_key();
+
+        // A gap in the nonces of alice, and a transfer of value carol does not hold
+        let first = transfer(&mut alice, &bob, 0, 0);
+        let gapped = transfer(&mut alice, &bob, 0, 2);
+        let unfunded = transfer(&mut carol, &bob, 5, 0);
+        blockchain.mempool.add(&first).unwrap();
+        blockchain.mempool.add(&gapped).unwrap();
+        blockchain.mempool.add(&unfunded).unwrap();
+
+        let template = block_template(&blockchain);
+        assert_eq!(template.transactions, vec![first]);
+    }
+}
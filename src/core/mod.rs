@@ -1,4 +1,6 @@
 pub mod blockchain;
 pub mod header_list;
 pub mod storage;
-pub mod mempool;
\ No newline at end of file
+pub mod mempool;
+pub mod miner;
+pub mod state;
\ No newline at end of file
@@ -0,0 +1,241 @@
+use crate::crypto::keys::Address;
+use crate::error::{Result, MarvinError};
+use crate::proto;
+use crate::types;
+use crate::types::hash::Hash;
+
+use std::collections::HashMap;
+
+/// Account is the state of an address derived from the transactions of the main chain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Account {
+    /// Total value received by the address
+    pub received: u64,
+    /// Total value sent by the address
+    pub sent: u64,
+    /// Nonce expected in the next transaction of the address, one more than the highest mined nonce
+    pub nonce: u64,
+}
+
+impl Account {
+    /// Returns the value held by the address. Marvin has no issuance yet: value only moves with transfers,
+    /// and a transfer is only valid if its sender holds the value it sends.
+    pub fn balance(&self) -> u64 {
+        self.received.saturating_sub(self.sent)
+    }
+}
+
+/// PendingState applies transactions on top of the chain state without modifying it, tracking the next nonce
+/// and the balance of every account they touch. It checks the transactions of a block, or of a block template,
+/// in the order they would be connected.
+pub struct PendingState<'a> {
+    state: &'a ChainState,
+    accounts: HashMap<Address, Account>,
+}
+
+impl<'a> PendingState<'a> {
+    pub fn new(state: &'a ChainState) -> Self {
+        PendingState { state, accounts: HashMap::new() }
+    }
+
+    /// Returns the account of an address with the transactions applied so far
+    pub fn account(&self, address: &Address) -> Account {
+        self.accounts.get(address).copied().unwrap_or_else(|| self.state.account(address))
+    }
+
+    /// Apply a transaction if it uses the next nonce of its sender and the sender holds the value it sends
+    pub fn apply(&mut self, tx: &proto::Transaction) -> Result<()> {
+        if let Some(sender) = types::transaction::sender_address(tx) {
+            let mut account = self.account(&sender);
+            if tx.nonce < 0 || tx.nonce as u64 != account.nonce {
+                return Err(MarvinError::Validation(format!(
+                    "Transaction {} has nonce {}, expected the next nonce {} of its sender",
+                    types::transaction::transaction_hash(tx), tx.nonce, account.nonce
+                )));
+            }
+            if account.balance() < tx.value {
+                return Err(MarvinError::Validation(format!(
+                    "Transaction {} sends {} but its sender only holds {}",
+                    types::transaction::transaction_hash(tx), tx.value, account.balance()
+                )));
+            }
+
+            account.sent += tx.value;
+            account.nonce += 1;
+            self.accounts.insert(sender, account);
+        }
+        if let Some(recipient) = types::transaction::recipient_address(tx) {
+            let mut account = self.account(&recipient);
+            account.received = account.received.saturating_add(tx.value);
+            self.accounts.insert(recipient, account);
+        }
+
+        Ok(())
+    }
+}
+
+/// TransactionLocation is the position of a mined transaction in the main chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionLocation {
+    pub block_hash: Hash,
+    pub height: u64,
+    pub index: usize,
+}
+
+/// ChainState keeps the accounts and the transaction index of the main chain.
+/// It is updated every time a block is connected or disconnected, and can be rolled back block by block.
+#[derive(Debug, Default)]
+pub struct ChainState {
+    accounts: HashMap<Address, Account>,
+    transactions: HashMap<Hash, TransactionLocation>,
+    /// Nonces of the senders of every connected block before the block was applied, used to disconnect blocks
+    undo: Vec<Vec<(Address, u64)>>,
+}
+
+impl ChainState {
+    pub fn new() -> Self {
+        ChainState::default()
+    }
+
+    /// Returns the account of an address, addresses that never appeared in a transaction have an empty account
+    pub fn account(&self, address: &Address) -> Account {
+        self.accounts.get(address).copied().unwrap_or_default()
+    }
+
+    /// Returns the location of a mined transaction given its hash
+    pub fn transaction(&self, hash: &Hash) -> Option<TransactionLocation> {
+        self.transactions.get(hash).copied()
+    }
+
+    /// Apply the transactions of a block connected at the tip of the main chain
+    pub fn connect_block(&mut self, block: &proto::Block) {
+        let block_hash = types::block::block_hash(block);
+        let height = block.header.as_ref().map(|header| header.height).unwrap_or_default();
+
+        let mut undo = Vec::new();
+        for (index, tx) in block.transactions.iter().enumerate() {
+            self.transactions.insert(
+                types::transaction::transaction_hash(tx),
+                TransactionLocation { block_hash, height, index },
+            );
+
+            if let Some(sender) = types::transaction::sender_address(tx) {
+                let account = self.accounts.entry(sender).or_default();
+                undo.push((sender, account.nonce));
+                account.sent = account.sent.saturating_add(tx.value);
+                account.nonce = tx.nonce.max(0) as u64 + 1;
+            }
+            if let Some(recipient) = types::transaction::recipient_address(tx) {
+                let account = self.accounts.entry(recipient).or_default();
+                account.received = account.received.saturating_add(tx.value);
+            }
+        }
+
+        self.undo.push(undo);
+    }
+
+    /// Revert the transactions of the block disconnected from the tip of the main chain
+    pub fn disconnect_block(&mut self, block: &proto::Block) {
+        for tx in block.transactions.iter() {
+            self.transactions.remove(&types::transaction::transaction_hash(tx));
+
+            if let Some(sender) = types::transaction::sender_address(tx) {
+                let account = self.accounts.entry(sender).or_default();
+                account.sent = account.sent.saturating_sub(tx.value);
+            }
+            if let Some(recipient) = types::transaction::recipient_address(tx) {
+                let account = self.accounts.entry(recipient).or_default();
+                account.received = account.received.saturating_sub(tx.value);
+            }
+        }
+
+        // Restore the nonces in reverse order, so a sender with several transactions gets its oldest nonce back
+        for (sender, nonce) in self.undo.pop().unwrap_or_default().into_iter().rev() {
+            self.accounts.entry(sender).or_default().nonce = nonce;
+        }
+        self.accounts.retain(|_, account| *account != Account::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys;
+
+    fn transfer(from: &mut keys::PrivateKey, to: &keys::PrivateKey, value: u64, nonce: i64) -> proto::Transaction {
+        let mut tx = proto::Transaction {
+            to: to.public_key().to_bytes().to_vec(),
+            value,
+            nonce,
+            ..Default::default()
+        };
+        types::transaction::sign_transaction(from, &mut tx).unwrap();
+
+        tx
+    }
+
+    fn block(height: u64, transactions: Vec<proto::Transaction>) -> proto::Block {
+        proto::Block {
+            header: Some(proto::Header { height, ..Default::default() }),
+            transactions,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_connect_and_disconnect_blocks() {
+        let mut alice = keys::generate_private_key();
+        let mut bob = keys::generate_private_key();
+        let (alice_address, bob_address) = (alice.public_key().address(), bob.public_key().address());
+        let mut state = ChainState::new();
+
+        let first = block(1, vec![transfer(&mut bob, &alice, 100, 0)]);
+        let second = block(2, vec![transfer(&mut alice, &bob, 30, 0), transfer(&mut alice, &bob, 20, 1)]);
+        state.connect_block(&first);
+        state.connect_block(&second);
+
+        assert_eq!(state.account(&alice_address), Account { received: 100, sent: 50, nonce: 2 });
+        assert_eq!(state.account(&alice_address).balance(), 50);
+        assert_eq!(state.account(&bob_address).balance(), 0);
+        assert_eq!(state.account(&bob_address).nonce, 1);
+
+        let tx_hash = types::transaction::transaction_hash(&second.transactions[1]);
+        assert_eq!(
+            state.transaction(&tx_hash),
+            Some(TransactionLocation { block_hash: types::block::block_hash(&second), height: 2, index: 1 })
+        );
+
+        state.disconnect_block(&second);
+        assert_eq!(state.account(&alice_address), Account { received: 100, sent: 0, nonce: 0 });
+        assert_eq!(state.transaction(&tx_hash), None);
+
+        state.disconnect_block(&first);
+        assert_eq!(state.account(&bob_address), Account::default());
+        assert!(state.accounts.is_empty());
+    }
+
+    #[test]
+    fn test_pending_state() {
+        let mut alice = keys::generate_private_key();
+        let mut bob = keys::generate_private_key();
+        let (alice_address, bob_address) = (alice.public_key().address(), bob.public_key().address());
+        let mut state = ChainState::new();
+        state.accounts.insert(alice_address, Account { received: 50, sent: 0, nonce: 3 });
+
+        let mut pending = PendingState::new(&state);
+        pending.apply(&transfer(&mut alice, &bob, 30, 3)).unwrap();
+        // The nonce of the sender must not be reused or skipped
+        assert!(pending.apply(&transfer(&mut alice, &bob, 0, 3)).is_err());
+        assert!(pending.apply(&transfer(&mut alice, &bob, 0, 5)).is_err());
+        // Earlier transfers are spent, and the value received by earlier transfers can be sent
+        assert!(pending.apply(&transfer(&mut alice, &bob, 30, 4)).is_err());
+        pending.apply(&transfer(&mut bob, &alice, 10, 0)).unwrap();
+        assert!(pending.apply(&transfer(&mut bob, &alice, 21, 1)).is_err());
+        pending.apply(&transfer(&mut alice, &bob, 30, 4)).unwrap();
+
+        assert_eq!(pending.account(&alice_address), Account { received: 60, sent: 60, nonce: 5 });
+        assert_eq!(pending.account(&bob_address), Account { received: 60, sent: 10, nonce: 1 });
+        // The chain state is left untouched
+        assert_eq!(state.account(&bob_address), Account::default());
+    }
+}
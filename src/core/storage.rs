@@ -2,32 +2,274 @@ use crate::error::{Result, MarvinError};
 
 use crate::proto;
 
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use prost::Message;
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Magic bytes and version at the start of a block file
+const BLOCK_FILE_MAGIC: &[u8; 4] = b"MVBK";
+const BLOCK_FILE_VERSION: u8 = 1;
+/// Size of the length prefix and of the checksum of every record in a block file
+const RECORD_LENGTH_SIZE: usize = 4;
+const RECORD_CHECKSUM_SIZE: usize = 4;
 
 // Store is a trait that defines the methods that a store must implement.
-pub trait Storage {
+pub trait Storage: Send {
     fn put(&mut self, block: &proto::Block) -> Result<()>;
-    fn get(&self, hash: String) -> Result<proto::Block>; 
+    fn get(&self, hash: String) -> Result<proto::Block>;
+    // Returns every stored block, in no particular order
+    fn blocks(&self) -> Result<Vec<proto::Block>>;
 }
 
-pub struct MemoryStore {}
+/// MemoryStore keeps blocks in memory, indexed by the hex encoded block hash
+pub struct MemoryStore {
+    blocks: HashMap<String, proto::Block>,
+}
 
 impl MemoryStore {
     pub fn new() -> Self {
-        MemoryStore {}
+        MemoryStore {
+            blocks: HashMap::new(),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
     }
 }
 
 impl Storage for MemoryStore {
     fn put(&mut self, block: &proto::Block) -> Result<()> {
+        let hash = hex::encode(crate::types::block::hash_block(block));
+        self.blocks.insert(hash, block.clone());
+
+        Ok(())
+    }
+
+    fn get(&self, hash: String) -> Result<proto::Block> {
+        self.blocks
+            .get(&hash)
+            .cloned()
+            .ok_or_else(|| MarvinError::NotFound(format!("Block not found in the store: {}", hash)))
+    }
+
+    fn blocks(&self) -> Result<Vec<proto::Block>> {
+        Ok(self.blocks.values().cloned().collect())
+    }
+}
+
+/// FileStore keeps blocks in an append-only file, indexed in memory by the hex encoded block hash.
+/// Every block is written as a record with its own length prefix and checksum. When the file is opened,
+/// records with a bad checksum are skipped and a truncated record at the end of the file, left by a crash
+/// in the middle of a write, is cut off so the next blocks are appended after the last complete record.
+pub struct FileStore {
+    file: Mutex<File>,
+    /// Offset and length of the payload of every block in the file
+    index: HashMap<String, (u64, usize)>,
+    /// Offset at which the next record is written
+    end: u64,
+}
+
+impl FileStore {
+    /// Open the block file at the given path, creating it and its directory if they do not exist
+    pub fn open(path: &Path) -> Result<FileStore> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| MarvinError::Storage(e.to_string()))?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| MarvinError::Storage(e.to_string()))?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(|e| MarvinError::Storage(e.to_string()))?;
+
+        let header_size = BLOCK_FILE_MAGIC.len() + 1;
+        if data.is_empty() {
+            file.write_all(BLOCK_FILE_MAGIC).map_err(|e| MarvinError::Storage(e.to_string()))?;
+            file.write_all(&[BLOCK_FILE_VERSION]).map_err(|e| MarvinError::Storage(e.to_string()))?;
+            file.sync_data().map_err(|e| MarvinError::Storage(e.to_string()))?;
+            data.extend_from_slice(BLOCK_FILE_MAGIC);
+            data.push(BLOCK_FILE_VERSION);
+        }
+        if data.len() < header_size || &data[..BLOCK_FILE_MAGIC.len()] != BLOCK_FILE_MAGIC {
+            return Err(MarvinError::Storage(String::from("Invalid block file")));
+        }
+        if data[BLOCK_FILE_MAGIC.len()] != BLOCK_FILE_VERSION {
+            return Err(MarvinError::Storage(format!("Unsupported block file version {}", data[BLOCK_FILE_MAGIC.len()])));
+        }
+
+        let mut index = HashMap::new();
+        let mut offset = header_size;
+        while offset + RECORD_LENGTH_SIZE + RECORD_CHECKSUM_SIZE <= data.len() {
+            let length = u32::from_be_bytes(data[offset..offset + RECORD_LENGTH_SIZE].try_into().unwrap()) as usize;
+            let checksum = &data[offset + RECORD_LENGTH_SIZE..offset + RECORD_LENGTH_SIZE + RECORD_CHECKSUM_SIZE];
+            let start = offset + RECORD_LENGTH_SIZE + RECORD_CHECKSUM_SIZE;
+            if length > data.len() - start {
+                break;
+            }
+
+            let payload = &data[start..start + length];
+            offset = start + length;
+
+            if checksum != record_checksum(payload) {
+                continue;
+            }
+            if let Ok(block) = proto::Block::decode(payload) {
+                index.insert(hex::encode(crate::types::block::hash_block(&block)), (start as u64, length));
+            }
+        }
+
+        // Cut off the truncated record at the end of the file, if any
+        if offset < data.len() {
+            file.set_len(offset as u64).map_err(|e| MarvinError::Storage(e.to_string()))?;
+        }
+
+        Ok(FileStore {
+            file: Mutex::new(file),
+            index,
+            end: offset as u64,
+        })
+    }
+
+    fn read(&self, offset: u64, length: usize) -> Result<proto::Block> {
+        let mut file = self.file.lock().unwrap();
+        let mut payload = vec![0; length];
+        file.seek(SeekFrom::Start(offset)).map_err(|e| MarvinError::Storage(e.to_string()))?;
+        file.read_exact(&mut payload).map_err(|e| MarvinError::Storage(e.to_string()))?;
+
+        proto::Block::decode(payload.as_slice()).map_err(|e| MarvinError::Storage(e.to_string()))
+    }
+}
+
+impl Storage for FileStore {
+    fn put(&mut self, block: &proto::Block) -> Result<()> {
+        let hash = hex::encode(crate::types::block::hash_block(block));
+        if self.index.contains_key(&hash) {
+            return Ok(());
+        }
+
+        let payload = block.encode_to_vec();
+        let mut record = Vec::with_capacity(RECORD_LENGTH_SIZE + RECORD_CHECKSUM_SIZE + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(&record_checksum(&payload));
+        record.extend_from_slice(&payload);
+
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(self.end)).map_err(|e| MarvinError::Storage(e.to_string()))?;
+            file.write_all(&record).map_err(|e| MarvinError::Storage(e.to_string()))?;
+            file.sync_data().map_err(|e| MarvinError::Storage(e.to_string()))?;
+        }
+
+        let start = self.end + (RECORD_LENGTH_SIZE + RECORD_CHECKSUM_SIZE) as u64;
+        self.index.insert(hash, (start, payload.len()));
+        self.end += record.len() as u64;
+
         Ok(())
     }
 
     fn get(&self, hash: String) -> Result<proto::Block> {
-        println!("MemoryStore::get: {}", hash);
+        match self.index.get(&hash) {
+            Some((offset, length)) => self.read(*offset, *length),
+            None => Err(MarvinError::NotFound(format!("Block not found in the store: {}", hash))),
+        }
+    }
 
-        // empty block
-        Ok(proto::Block::default())
+    fn blocks(&self) -> Result<Vec<proto::Block>> {
+        let mut records: Vec<&(u64, usize)> = self.index.values().collect();
+        records.sort();
 
-        // Err(MarvinError::NotImplemented("MemoryStore::get".to_string()))
+        records.into_iter().map(|(offset, length)| self.read(*offset, *length)).collect()
     }
-}
\ No newline at end of file
+}
+
+/// Returns the checksum of a record, the first bytes of its sha256
+fn record_checksum(payload: &[u8]) -> [u8; RECORD_CHECKSUM_SIZE] {
+    let mut hasher = Sha256::new();
+    hasher.input(payload);
+
+    let mut hash = [0; 32];
+    hasher.result(&mut hash);
+
+    hash[..RECORD_CHECKSUM_SIZE].try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_store_put_get() {
+        let mut store = MemoryStore::new();
+
+        let block = proto::Block {
+            header: Some(proto::Header {
+                height: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let hash = hex::encode(crate::types::block::hash_block(&block));
+
+        assert!(store.get(hash.clone()).is_err());
+
+        store.put(&block).unwrap();
+        assert_eq!(store.get(hash).unwrap(), block);
+    }
+
+    fn block(height: u64) -> proto::Block {
+        proto::Block {
+            header: Some(proto::Header { height, ..Default::default() }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_file_store() {
+        let path = std::env::temp_dir()
+            .join(format!("marvin-store-{}", hex::encode(crate::crypto::keys::new_entropy())))
+            .join("blocks.dat");
+        let (first, second) = (block(1), block(2));
+        let first_hash = hex::encode(crate::types::block::hash_block(&first));
+
+        let mut store = FileStore::open(&path).unwrap();
+        store.put(&first).unwrap();
+        store.put(&second).unwrap();
+        // Blocks already stored are not written twice
+        store.put(&first).unwrap();
+        assert_eq!(store.get(first_hash.clone()).unwrap(), first);
+        drop(store);
+
+        // A record cut in the middle of a write is dropped when the file is reopened
+        let size = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 1, 0, 1, 2]).unwrap();
+        drop(file);
+
+        let mut store = FileStore::open(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+        assert_eq!(store.blocks().unwrap(), vec![first.clone(), second.clone()]);
+        assert!(matches!(store.get(String::from("00")), Err(MarvinError::NotFound(_))));
+
+        let third = block(3);
+        store.put(&third).unwrap();
+        drop(store);
+        assert_eq!(FileStore::open(&path).unwrap().blocks().unwrap(), vec![first, second, third]);
+
+        fs::write(&path, b"not a block file").unwrap();
+        assert!(FileStore::open(&path).is_err());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
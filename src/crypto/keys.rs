@@ -60,7 +60,7 @@ pub fn new_private_key_from_string(private_key: &str) -> Result<PrivateKey> {
 /// new_private_key_from_seed generates a new private key from the given seed
 pub fn new_private_key_from_seed(seed: &[u8; 32]) -> Result<PrivateKey> {
     if seed.len() != SEED_SIZE {
-        return Err(MarvinError::Internal(String::from("Invalid seed size, expected 32 bytes.")));
+        return Err(MarvinError::Crypto(String::from("Invalid seed size, expected 32 bytes.")));
     }
 
     let key = SigningKey::from_bytes(seed);
@@ -96,11 +96,15 @@ impl PrivateKey {
         }
     }
 
-    /// Convert the private key to a string in hex format
-    pub fn to_string(&self) -> String {
-        hex::encode(self.key.to_bytes())
+}
+
+impl std::fmt::Display for PrivateKey {
+    /// Formats the private key as a string in hex format
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.key.to_bytes()))
     }
 }
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
 pub struct PublicKey {
     pub key: VerifyingKey,
@@ -109,15 +113,16 @@ pub struct PublicKey {
 impl PublicKey {
     pub fn from_bytes(bytes: &[u8]) -> Result<PublicKey> {
         if bytes.len() != PUBLIC_KEY_SIZE {
-            return Err(MarvinError::Internal(String::from("Invalid public key size, expected 32 bytes.")));
+            return Err(MarvinError::Crypto(String::from("Invalid public key size, expected 32 bytes.")));
         }
 
-        Ok(PublicKey {
-            key: VerifyingKey::from_bytes(bytes.try_into().unwrap()).unwrap(),
-        })
+        let key = VerifyingKey::from_bytes(bytes.try_into().unwrap())
+            .map_err(|e| MarvinError::Crypto(e.to_string()))?;
+
+        Ok(PublicKey { key })
     }
 
-    pub fn to_bytes(&self) -> [u8; PUBLIC_KEY_SIZE] {
+    pub fn to_bytes(self) -> [u8; PUBLIC_KEY_SIZE] {
         self.key.to_bytes()
     }
 
@@ -126,9 +131,12 @@ impl PublicKey {
             value: self.key.to_bytes()[..ADDRESS_SIZE].try_into().unwrap(),
         }
     }
-    
-    pub fn to_string(&self) -> String {
-        hex::encode(self.key.to_bytes())
+}
+
+impl std::fmt::Display for PublicKey {
+    /// Formats the public key as a string in hex format
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.key.to_bytes()))
     }
 }
 
@@ -140,7 +148,7 @@ pub struct SignatureWrapper {
 impl SignatureWrapper {
     pub fn from_bytes(bytes: &[u8]) -> Result<SignatureWrapper> {
         if bytes.len() != SIGNATURE_SIZE {
-            return Err(MarvinError::Internal(String::from("Invalid signature size, expected 64 bytes.")));
+            return Err(MarvinError::Crypto(String::from("Invalid signature size, expected 64 bytes.")));
         }
 
         Ok(SignatureWrapper {
@@ -150,7 +158,7 @@ impl SignatureWrapper {
 
 
     /// Convert the signature to bytes
-    pub fn to_bytes(&self) -> [u8; SIGNATURE_SIZE] {
+    pub fn to_bytes(self) -> [u8; SIGNATURE_SIZE] {
         self.signature.to_bytes()
     }
 
@@ -158,26 +166,41 @@ impl SignatureWrapper {
     pub fn verify(&self, data: &[u8], public_key: &PublicKey) -> bool {
         public_key.key.verify(data, &self.signature).is_ok()
     }
+}
 
-    /// Convert the signature to a string in hex format
-    pub fn to_string(&self) -> String {
-        hex::encode(self.signature.to_bytes())
+impl std::fmt::Display for SignatureWrapper {
+    /// Formats the signature as a string in hex format
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.signature.to_bytes()))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct Address {
     pub value: [u8; ADDRESS_SIZE],
 }
 
+impl std::fmt::Display for Address {
+    /// Formats the address as a string in hex format
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.value))
+    }
+}
+
 impl Address {
-    /// Convert the address to a string in hex format
-    pub fn to_string(&self) -> String {
-        hex::encode(self.value)
+    /// Derive the address from the bytes of a public key, without decoding the key itself
+    pub fn from_public_key_bytes(bytes: &[u8]) -> Result<Address> {
+        if bytes.len() != PUBLIC_KEY_SIZE {
+            return Err(MarvinError::Crypto(String::from("Invalid public key size, expected 32 bytes.")));
+        }
+
+        Ok(Address {
+            value: bytes[..ADDRESS_SIZE].try_into().unwrap(),
+        })
     }
 
     /// Convert the address to bytes
-    pub fn to_bytes(&self) -> [u8; ADDRESS_SIZE] {
+    pub fn to_bytes(self) -> [u8; ADDRESS_SIZE] {
         self.value
     }
 }
@@ -203,7 +226,7 @@ mod tests {
     fn test_new_private_key_from_mnemonic() {
         let mnemonic = "all wild paddle pride wheat menu task funny sign profit blouse hockey";
         let address_string = "e15af3cd7d9c09ebaf20d1f97ea396c218b66037";
-        let private_key = get_private_key_from_mnemonic(&mnemonic).unwrap();
+        let private_key = get_private_key_from_mnemonic(mnemonic).unwrap();
 
         assert_eq!(
             private_key.to_bytes().len(),
@@ -281,7 +304,7 @@ mod tests {
     fn test_new_private_key_from_string() {
         let seed = "753bfa924576a230736e83589933ccb7aad8fd3934d7e9637df4912b58ac95d6";
         let address_string = "339f9690596b35d909a8c47fe26c5e8697af034c";
-        let private_key = new_private_key_from_string(&seed).unwrap();
+        let private_key = new_private_key_from_string(seed).unwrap();
 
         assert_eq!(PRIVATE_KEY_SIZE, private_key.to_bytes().len());
 
@@ -312,13 +335,13 @@ mod tests {
         assert_eq!(SIGNATURE_SIZE, signature.signature.to_bytes().len());
 
         // Verify the signature
-        assert_eq!(true, signature.verify(data, &public_key));
+        assert!(signature.verify(data, &public_key));
 
         // Verify the signature
-        assert_eq!(true, signature.verify(data, &public_key));
+        assert!(signature.verify(data, &public_key));
 
         // Verify the signature is invalid
-        assert_eq!(false, signature.verify(data, &invalid_public_key));
+        assert!(!signature.verify(data, &invalid_public_key));
     }
 
     #[test]
@@ -327,5 +350,9 @@ mod tests {
         let public_key = private_key.public_key();
         let address = public_key.address();
         assert_eq!(ADDRESS_SIZE, address.value.len());
+
+        let from_bytes = Address::from_public_key_bytes(&public_key.to_bytes()).unwrap();
+        assert_eq!(address, from_bytes);
+        assert!(Address::from_public_key_bytes(&[0; ADDRESS_SIZE]).is_err());
     }
 }
@@ -0,0 +1,179 @@
+use crate::crypto::keys::{self, PrivateKey, SEED_SIZE};
+use crate::error::{Result, MarvinError};
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use crypto::symmetriccipher::SynchronousStreamCipher;
+use crypto::util::fixed_time_eq;
+use pbkdf2::pbkdf2_hmac;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+
+/// Version of the keystore format
+pub const KEYSTORE_VERSION: u32 = 1;
+/// Default number of PBKDF2 iterations deriving the encryption key from the password
+pub const DEFAULT_KEYSTORE_ITERATIONS: u32 = 262_144;
+/// Environment variable holding the password of the keystores, the password is prompted for if it is not set
+pub const KEYSTORE_PASSWORD_ENV: &str = "MARVIN_KEYSTORE_PASSWORD";
+const CIPHER: &str = "aes-128-ctr";
+const KDF: &str = "pbkdf2-sha256";
+
+/// Keystore is a private key encrypted with a password, stored as JSON.
+/// The encryption key is derived from the password with PBKDF2-SHA256 and the seed of the key is encrypted with
+/// AES-128-CTR. The MAC, the sha256 of the second half of the derived key and the ciphertext, detects a wrong password.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    /// Address of the key, readable without the password
+    pub address: String,
+    pub cipher: String,
+    pub ciphertext: String,
+    pub iv: String,
+    pub kdf: String,
+    pub salt: String,
+    pub iterations: u32,
+    pub mac: String,
+}
+
+impl Keystore {
+    /// Encrypt a private key with a password
+    pub fn encrypt(private_key: &PrivateKey, password: &str, iterations: u32) -> Keystore {
+        let mut salt = [0u8; 32];
+        let mut iv = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut iv);
+
+        let derived_key = derive_key(password, &salt, iterations);
+        let ciphertext = apply_cipher(&derived_key, &iv, &private_key.to_bytes());
+
+        Keystore {
+            version: KEYSTORE_VERSION,
+            address: private_key.public_key().address().to_string(),
+            cipher: CIPHER.to_string(),
+            ciphertext: hex::encode(&ciphertext),
+            iv: hex::encode(iv),
+            kdf: KDF.to_string(),
+            salt: hex::encode(salt),
+            iterations,
+            mac: hex::encode(mac(&derived_key, &ciphertext)),
+        }
+    }
+
+    /// Decrypt the private key, failing if the password is wrong
+    pub fn decrypt(&self, password: &str) -> Result<PrivateKey> {
+        if self.version != KEYSTORE_VERSION || self.cipher != CIPHER || self.kdf != KDF {
+            return Err(MarvinError::Crypto(format!(
+                "Unsupported keystore version {} with cipher {} and kdf {}", self.version, self.cipher, self.kdf
+            )));
+        }
+
+        let decode = |value: &str| hex::decode(value).map_err(|e| MarvinError::Crypto(format!("Invalid keystore: {}", e)));
+        let (ciphertext, iv, salt, expected_mac) = (decode(&self.ciphertext)?, decode(&self.iv)?, decode(&self.salt)?, decode(&self.mac)?);
+
+        let derived_key = derive_key(password, &salt, self.iterations);
+        if !fixed_time_eq(&mac(&derived_key, &ciphertext), &expected_mac) {
+            return Err(MarvinError::Crypto(String::from("Wrong keystore password")));
+        }
+
+        let seed: [u8; SEED_SIZE] = apply_cipher(&derived_key, &iv, &ciphertext)
+            .try_into()
+            .map_err(|_| MarvinError::Crypto(String::from("Invalid keystore key size")))?;
+
+        keys::new_private_key_from_seed(&seed)
+    }
+
+    /// Load a keystore from a JSON file
+    pub fn load(path: &Path) -> Result<Keystore> {
+        let data = fs::read(path).map_err(|e| MarvinError::Storage(format!("Failed to read the keystore {}: {}", path.display(), e)))?;
+        serde_json::from_slice(&data).map_err(|e| MarvinError::Storage(format!("Invalid keystore {}: {}", path.display(), e)))
+    }
+
+    /// Save the keystore to a JSON file only readable by its owner, an existing file is never overwritten
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(|e| MarvinError::Storage(e.to_string()))?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options
+            .open(path)
+            .map_err(|e| MarvinError::Storage(format!("Failed to create the keystore {}: {}", path.display(), e)))?;
+
+        file.write_all(&data).map_err(|e| MarvinError::Storage(e.to_string()))
+    }
+}
+
+/// Returns the password of the keystores from `KEYSTORE_PASSWORD_ENV`, or prompts for it on the terminal with echo off.
+/// When stdin is not a terminal, such as a pipe, the password is read from its first line.
+pub fn read_password(prompt: &str) -> Result<String> {
+    if let Ok(password) = std::env::var(KEYSTORE_PASSWORD_ENV) {
+        return Ok(password);
+    }
+
+    let read_error = |e: std::io::Error| MarvinError::General(format!("Failed to read the password: {}", e));
+    if std::io::stdin().is_terminal() {
+        return rpassword::prompt_password(prompt).map_err(read_error);
+    }
+
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password).map_err(read_error)?;
+
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn derive_key(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<sha2::Sha256>(password.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+fn apply_cipher(derived_key: &[u8; 32], iv: &[u8], input: &[u8]) -> Vec<u8> {
+    let mut output = vec![0u8; input.len()];
+    crypto::aes::ctr(crypto::aes::KeySize::KeySize128, &derived_key[..16], iv).process(input, &mut output);
+    output
+}
+
+fn mac(derived_key: &[u8; 32], ciphertext: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.input(&derived_key[16..]);
+    hasher.input(ciphertext);
+
+    let mut mac = [0u8; 32];
+    hasher.result(&mut mac);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keystore() {
+        let private_key = keys::generate_private_key();
+        let keystore = Keystore::encrypt(&private_key, "correct horse", 16);
+        assert_eq!(keystore.address, private_key.public_key().address().to_string());
+        assert_eq!(keystore.decrypt("correct horse").unwrap(), private_key);
+        assert_eq!(keystore.decrypt("wrong").unwrap_err(), MarvinError::Crypto(String::from("Wrong keystore password")));
+
+        let path = std::env::temp_dir().join(format!("marvin-keystore-{}.json", hex::encode(keys::new_entropy())));
+        keystore.save(&path).unwrap();
+        assert_eq!(Keystore::load(&path).unwrap(), keystore);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        // Keystores are never overwritten
+        assert!(keystore.save(&path).is_err());
+        fs::remove_file(&path).unwrap();
+
+        let mut tampered = keystore.clone();
+        tampered.ciphertext = hex::encode([0u8; SEED_SIZE]);
+        assert!(tampered.decrypt("correct horse").is_err());
+    }
+}
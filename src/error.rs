@@ -7,6 +7,18 @@ use std::result;
 pub type Result<T> = result::Result<T, MarvinError>;
 
 
+/// Stable numeric codes of the errors, returned to the RPC clients along with the error message.
+/// The codes are in the range reserved for server errors by JSON-RPC 2.0 and never change once released.
+pub const GENERAL_ERROR_CODE: i64 = -32000;
+pub const INTERNAL_ERROR_CODE: i64 = -32001;
+pub const NOT_IMPLEMENTED_ERROR_CODE: i64 = -32002;
+pub const VALIDATION_ERROR_CODE: i64 = -32010;
+pub const NOT_FOUND_ERROR_CODE: i64 = -32011;
+pub const MEMPOOL_REJECTED_ERROR_CODE: i64 = -32012;
+pub const STORAGE_ERROR_CODE: i64 = -32013;
+pub const CRYPTO_ERROR_CODE: i64 = -32014;
+pub const NETWORK_ERROR_CODE: i64 = -32015;
+
 /// MarvinError is an enum with all the standardized errors available for returning
 ///
 #[derive(Error, Debug, PartialEq)]
@@ -17,17 +29,70 @@ pub enum MarvinError {
     General(String),
     #[error("Internal error: {0}")]
     Internal(String),
+    /// A block, header, transaction or message that is malformed or breaks the consensus rules
+    #[error("Validation error: {0}")]
+    Validation(String),
+    /// A block, transaction or any other requested item that does not exist
+    #[error("Not found: {0}")]
+    NotFound(String),
+    /// A transaction that is well formed but cannot enter the mempool, such as a duplicate or a nonce too low
+    #[error("Mempool rejected transaction: {0}")]
+    MempoolRejected(String),
+    /// A failure reading or writing data on disk
+    #[error("Storage error: {0}")]
+    Storage(String),
+    /// An invalid key or signature
+    #[error("Crypto error: {0}")]
+    Crypto(String),
+    /// A failure of a connection or of the peer-to-peer protocol
+    #[error("Network error: {0}")]
+    Network(String),
+}
+
+impl MarvinError {
+    /// Returns the stable numeric code of the error
+    pub fn code(&self) -> i64 {
+        match self {
+            MarvinError::General(_) => GENERAL_ERROR_CODE,
+            MarvinError::Internal(_) => INTERNAL_ERROR_CODE,
+            MarvinError::NotImplemented(_) => NOT_IMPLEMENTED_ERROR_CODE,
+            MarvinError::Validation(_) => VALIDATION_ERROR_CODE,
+            MarvinError::NotFound(_) => NOT_FOUND_ERROR_CODE,
+            MarvinError::MempoolRejected(_) => MEMPOOL_REJECTED_ERROR_CODE,
+            MarvinError::Storage(_) => STORAGE_ERROR_CODE,
+            MarvinError::Crypto(_) => CRYPTO_ERROR_CODE,
+            MarvinError::Network(_) => NETWORK_ERROR_CODE,
+        }
+    }
+
+    /// Rebuild an error from its stable code and the message returned by an RPC API, the inverse of `code`.
+    /// The JSON-RPC 2.0 protocol errors are mapped to the closest error, any other code is a general error.
+    pub fn from_code(code: i64, message: &str) -> MarvinError {
+        let error: fn(String) -> MarvinError = match code {
+            INTERNAL_ERROR_CODE | -32603 => MarvinError::Internal,
+            NOT_IMPLEMENTED_ERROR_CODE | -32601 => MarvinError::NotImplemented,
+            VALIDATION_ERROR_CODE | -32700 | -32600 | -32602 => MarvinError::Validation,
+            NOT_FOUND_ERROR_CODE => MarvinError::NotFound,
+            MEMPOOL_REJECTED_ERROR_CODE => MarvinError::MempoolRejected,
+            STORAGE_ERROR_CODE => MarvinError::Storage,
+            CRYPTO_ERROR_CODE => MarvinError::Crypto,
+            NETWORK_ERROR_CODE => MarvinError::Network,
+            _ => MarvinError::General,
+        };
+
+        // Messages built from an error start with the description of its kind, which is added back by `Display`
+        let prefix = error(String::new()).to_string();
+        error(message.strip_prefix(&prefix).unwrap_or(message).to_string())
+    }
 }
 
-/// Returns SQLRiteError::General error from String
+/// Returns a MarvinError::General error from a string
 pub fn marvin_error(message: &str) -> MarvinError {
     MarvinError::General(message.to_owned())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::fmt::format;
-
     use super::*;
 
     #[test]
@@ -59,4 +124,26 @@ mod tests {
         let result = format!("{}", input);
         assert_eq!(result, expected);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_error_codes() {
+        // The codes are part of the RPC API, they must stay stable
+        assert_eq!(MarvinError::General(String::new()).code(), -32000);
+        assert_eq!(MarvinError::Validation(String::new()).code(), -32010);
+        assert_eq!(MarvinError::NotFound(String::new()).code(), -32011);
+        assert_eq!(MarvinError::MempoolRejected(String::new()).code(), -32012);
+        assert_eq!(MarvinError::Storage(String::new()).code(), -32013);
+        assert_eq!(MarvinError::Crypto(String::new()).code(), -32014);
+        assert_eq!(MarvinError::Network(String::new()).code(), -32015);
+
+        let input = MarvinError::MempoolRejected(String::from("Nonce too low"));
+        assert_eq!(format!("{}", input), "Mempool rejected transaction: Nonce too low");
+
+        assert_eq!(MarvinError::from_code(input.code(), &input.to_string()), input);
+        assert_eq!(
+            MarvinError::from_code(-32602, "Validation error: Invalid hash size"),
+            MarvinError::Validation(String::from("Invalid hash size"))
+        );
+        assert_eq!(MarvinError::from_code(-1, "Unknown"), MarvinError::General(String::from("Unknown")));
+    }
+}
@@ -0,0 +1,14 @@
+#[macro_use]
+extern crate slog;
+
+#[macro_use]
+pub mod utils;
+
+pub mod proto;
+pub mod core;
+pub mod crypto;
+pub mod error;
+pub mod network;
+pub mod node;
+pub mod rpc;
+pub mod types;
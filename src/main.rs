@@ -1,41 +1,16 @@
-#![allow(unused_macros)]
-#![allow(unused_imports)]
-#![allow(dead_code)]
-
-#[macro_use]
-extern crate slog;
-
-#[macro_use]
-pub mod utils;
-
-mod proto;
 mod cli;
-mod core;
-mod crypto;
-mod error;
-mod types;
 
 use cli::start_cli;
-use crypto::keys::{self, get_private_key_from_mnemonic};
-
-use slog::*;
-use slog::Drain;
-use std::sync::Mutex;
+use marvin_blockchain::{crypto, error, node};
+use crypto::keys::get_private_key_from_mnemonic;
+use crypto::keystore::{self, Keystore};
 
 fn main() {
-    let logger = utils::log::make_json_logger();
-
-    info!(logger, "info json log"; o!("key" => "value", "key2" => "value2"));
-    // warn!(logger, "warn json log"; "key" => "value");
-    // debug!(logger, "debug json log"; "key" => "value");
-
-    block_serialization().unwrap();
-
     let matches = start_cli().get_matches();
 
     match matches.subcommand() {
         Some(("address", sub_matches)) => match sub_matches.subcommand() {
-            Some(("create", _)) => {
+            Some(("create", create_matches)) => {
                 println!("Generating new address...");
                 let entropy = crypto::keys::new_entropy();
                 let mnemonic = crypto::keys::get_mnemonic_from_entropy(&entropy).unwrap();
@@ -44,78 +19,58 @@ fn main() {
                 let address = public_key.address();
 
                 println!("mnemonic: {}", mnemonic);
-                println!("address: {}", address.to_string());
+                println!("address: {}", address);
+                println!("public key: {}", public_key);
+
+                if let Some(path) = create_matches.get_one::<String>("keystore") {
+                    let result = keystore::read_password("Keystore password: ").and_then(|password| {
+                        Keystore::encrypt(&private_key, &password, keystore::DEFAULT_KEYSTORE_ITERATIONS)
+                            .save(std::path::Path::new(path))
+                    });
+
+                    match result {
+                        Ok(()) => println!("keystore: {}", path),
+                        Err(e) => {
+                            eprintln!("error: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
             }
             Some(("restore", restore_matches)) => {
                 let mnemonic = restore_matches.get_one::<String>("mnemonic").unwrap();
                 let private_key = get_private_key_from_mnemonic(mnemonic).unwrap();
                 let public_key = private_key.public_key();
                 let address = public_key.address();
-                println!("address: {}", address.to_string());
+                println!("address: {}", address);
+                println!("public key: {}", public_key);
             }
             _ => {
                 println!("No address subcommand was used");
             }
         },
+        Some(("node", sub_matches)) => match sub_matches.subcommand() {
+            Some(("start", start_matches)) => {
+                let path = start_matches.get_one::<String>("config").map(std::path::Path::new);
+                let overrides = cli::node_setting_overrides(start_matches);
+                let config = node::config::NodeConfig::resolve(path, std::env::vars(), &overrides);
+                let result = config.and_then(|config| {
+                    tokio::runtime::Runtime::new()
+                        .map_err(|e| error::MarvinError::Internal(e.to_string()))?
+                        .block_on(node::runner::run(config))
+                });
+
+                if let Err(e) = result {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            _ => {
+                println!("No node subcommand was used");
+            }
+        },
         _ => {
             println!("No subcommand was used");
         }
     }
-
-    
 }
-
-
-fn block_serialization() -> Result<()> {
-    println!("Block Serialization");
-
-    let mnemonic_to = "all wild paddle pride wheat menu task funny sign profit blouse hockey";
-    let private_key_to = crypto::keys::get_private_key_from_mnemonic(&mnemonic_to).unwrap();
-    let public_key_to = private_key_to.public_key();
-    let address_to = public_key_to.address();
-    println!("address to: {}", address_to.to_string());
-
-    let mnemonic_from = "hello wild paddle pride wheat menu task funny sign profit blouse hockey";
-    let mut private_key_from = crypto::keys::get_private_key_from_mnemonic(&mnemonic_from).unwrap();
-    let public_key_from = private_key_from.public_key();
-    let address_from = public_key_from.address();
-    println!("address from: {}", address_from.to_string());
-
-    // Create an instance of Header
-    let header = proto::Header {
-        prev_block_hash: [0; 32].to_vec(),
-        tx_hash: [0; 32].to_vec(),
-        version: 1,
-        height: 1,
-        timestamp: 1627483623,
-        nonce: 12345,
-        difficulty: 10,
-    };
-
-    // Create an instance of Block
-    let mut block = proto::Block {
-        header: Some(header),
-        transactions: vec![],
-        public_key: public_key_from.to_bytes().to_vec(),
-        signature: vec![],
-        hash: vec![],
-    };
-
-    let mut tx = proto::Transaction {
-        from: public_key_from.to_bytes().to_vec(),
-        to: public_key_to.to_bytes().to_vec(),
-        value: 1000,
-        data: b"Transaction data".to_vec(),
-        signature: [0; 64].to_vec(),
-        nonce: 123,
-        hash: [0; 32].to_vec(),
-    };
-    let _ = types::transaction::sign_transaction(&mut private_key_from, &mut tx).unwrap();
-    types::block::add_transaction(&mut block, tx);
-
-    types::block::sign_block(&mut private_key_from, &mut block).unwrap();
-
-    println!("RUST: Block WITH TRANSACTIONS hex: {:?}", hex::encode(types::block::serialize_block(block.clone()).unwrap()));
-    
-    Ok(())
-}
\ No newline at end of file
@@ -0,0 +1,349 @@
+use crate::error::{Result, MarvinError};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Delay in seconds before dialing an address again, doubled for every consecutive failure
+pub const RETRY_BACKOFF: u64 = 30;
+/// Maximum number of doublings of the retry delay
+const MAX_BACKOFF_EXPONENT: u32 = 6;
+/// Number of consecutive failures after which an address is dropped from the address book
+pub const MAX_FAILURES: u32 = 10;
+/// Maximum number of addresses in the address book
+pub const MAX_ADDRESSES: usize = 2048;
+
+/// AddressEntry is what the node knows about the address of another node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressEntry {
+    pub addr: String,
+    /// Unix timestamp in seconds of the last successful connection to the address
+    pub last_seen: Option<u64>,
+    /// Unix timestamp in seconds of the last connection attempt to the address
+    pub last_attempt: Option<u64>,
+    /// Number of consecutive failed connection attempts
+    pub failures: u32,
+}
+
+/// AddressBook keeps the addresses of the known nodes, used to choose the outbound connections of the node.
+/// It is persisted to disk as JSON so the node can reconnect to the network without the bootstrap nodes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AddressBook {
+    pub entries: HashMap<String, AddressEntry>,
+    /// Banned hosts, with the Unix timestamp in seconds at which the ban ends
+    #[serde(default)]
+    pub bans: HashMap<String, u64>,
+}
+
+impl AddressBook {
+    pub fn new() -> Self {
+        AddressBook::default()
+    }
+
+    /// Load an address book from a file, a missing file is an empty address book
+    pub fn load(path: &Path) -> Result<AddressBook> {
+        if !path.exists() {
+            return Ok(AddressBook::new());
+        }
+
+        let data = fs::read(path).map_err(|e| MarvinError::Storage(e.to_string()))?;
+        serde_json::from_slice(&data).map_err(|e| MarvinError::Storage(e.to_string()))
+    }
+
+    /// Save the address book to a file. The file is written next to the destination first and then renamed,
+    /// so a crash while saving never leaves a truncated address book behind.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(|e| MarvinError::Storage(e.to_string()))?;
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data).map_err(|e| MarvinError::Storage(e.to_string()))?;
+        fs::rename(&tmp_path, path).map_err(|e| MarvinError::Storage(e.to_string()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add an address to the address book if it is not known yet and is a valid host:port address.
+    /// When the address book is full, the address replaces the one that failed the most, if any.
+    /// Returns true if the address was added.
+    pub fn add(&mut self, addr: &str) -> bool {
+        if !is_host_port(addr) || self.entries.contains_key(addr) {
+            return false;
+        }
+        if self.entries.len() >= MAX_ADDRESSES && !self.evict() {
+            return false;
+        }
+
+        self.entries.insert(addr.to_string(), AddressEntry {
+            addr: addr.to_string(),
+            last_seen: None,
+            last_attempt: None,
+            failures: 0,
+        });
+
+        true
+    }
+
+    /// Remove the address that failed the most, the least recently tried first.
+    /// Returns false if no address of the address book failed.
+    fn evict(&mut self) -> bool {
+        let evicted = self
+            .entries
+            .values()
+            .filter(|entry| entry.failures > 0)
+            .max_by(|a, b| a.failures.cmp(&b.failures).then(b.last_attempt.cmp(&a.last_attempt)).then(b.addr.cmp(&a.addr)))
+            .map(|entry| entry.addr.clone());
+
+        match evicted {
+            Some(addr) => self.entries.remove(&addr).is_some(),
+            None => false,
+        }
+    }
+
+    /// Record a connection attempt to an address
+    pub fn mark_attempt(&mut self, addr: &str, now: u64) {
+        self.add(addr);
+        if let Some(entry) = self.entries.get_mut(addr) {
+            entry.last_attempt = Some(now);
+        }
+    }
+
+    /// Record a successful connection to an address
+    pub fn mark_seen(&mut self, addr: &str, now: u64) {
+        self.add(addr);
+        if let Some(entry) = self.entries.get_mut(addr) {
+            entry.last_seen = Some(now);
+            entry.failures = 0;
+        }
+    }
+
+    /// Record a failed connection attempt to an address, dropping the address after too many failures
+    pub fn mark_failed(&mut self, addr: &str, now: u64) {
+        if let Some(entry) = self.entries.get_mut(addr) {
+            entry.last_attempt = Some(now);
+            entry.failures += 1;
+            if entry.failures >= MAX_FAILURES {
+                self.entries.remove(addr);
+            }
+        }
+    }
+
+    /// Ban the host of an address until the given Unix timestamp in seconds
+    pub fn ban(&mut self, addr: &str, until: u64) {
+        self.bans.insert(host(addr), until);
+    }
+
+    /// Check if the host of an address is banned
+    pub fn is_banned(&self, addr: &str, now: u64) -> bool {
+        self.bans.get(&host(addr)).is_some_and(|until| *until > now)
+    }
+
+    /// Remove the bans that are over
+    pub fn remove_expired_bans(&mut self, now: u64) {
+        self.bans.retain(|_, until| *until > now);
+    }
+
+    /// Choose up to `count` addresses to dial, skipping the excluded ones and the ones that failed recently.
+    /// Addresses with fewer failures come first, then the most recently seen ones.
+    pub fn select_outbound(&self, exclude: &HashSet<String>, count: usize, now: u64) -> Vec<String> {
+        let mut candidates: Vec<&AddressEntry> = self
+            .entries
+            .values()
+            .filter(|entry| !exclude.contains(&entry.addr) && !self.is_banned(&entry.addr, now))
+            .filter(|entry| match entry.last_attempt {
+                Some(last_attempt) => now >= last_attempt + retry_delay(entry.failures),
+                None => true,
+            })
+            .collect();
+
+        candidates.sort_by(|a, b| {
+            a.failures
+                .cmp(&b.failures)
+                .then(b.last_seen.cmp(&a.last_seen))
+                .then(a.addr.cmp(&b.addr))
+        });
+
+        candidates.into_iter().take(count).map(|entry| entry.addr.clone()).collect()
+    }
+
+    /// Returns up to `count` addresses to share with a peer, the most recently seen first.
+    /// Only the addresses the node connected to are shared, not the ones that are currently failing or banned.
+    pub fn addresses_to_share(&self, count: usize, now: u64) -> Vec<String> {
+        let mut shared: Vec<&AddressEntry> = self
+            .entries
+            .values()
+            .filter(|entry| entry.last_seen.is_some() && entry.failures == 0 && !self.is_banned(&entry.addr, now))
+            .collect();
+        shared.sort_by(|a, b| b.last_seen.cmp(&a.last_seen).then(a.addr.cmp(&b.addr)));
+
+        shared.into_iter().take(count).map(|entry| entry.addr.clone()).collect()
+    }
+}
+
+/// Check that an address is a host, name or IP, followed by a port
+pub fn is_host_port(addr: &str) -> bool {
+    match addr.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+        None => false,
+    }
+}
+
+/// Returns the host of an address, bans apply to all the ports of a host.
+/// Addresses that are not socket addresses are their own host.
+pub fn host(addr: &str) -> String {
+    match addr.parse::<SocketAddr>() {
+        Ok(socket_addr) => socket_addr.ip().to_string(),
+        Err(_) => addr.to_string(),
+    }
+}
+
+/// Returns the delay in seconds before dialing again an address with the given number of consecutive failures
+fn retry_delay(failures: u32) -> u64 {
+    RETRY_BACKOFF << failures.min(MAX_BACKOFF_EXPONENT)
+}
+
+/// Returns the current time as a Unix timestamp in seconds
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1722470400;
+
+    #[test]
+    fn test_select_outbound() {
+        let mut book = AddressBook::new();
+        assert!(book.add("10.0.0.1:7000"));
+        assert!(!book.add("10.0.0.1:7000"));
+        book.add("10.0.0.2:7000");
+        book.add("10.0.0.3:7000");
+        book.mark_seen("10.0.0.2:7000", NOW - 10);
+        book.mark_failed("10.0.0.3:7000", NOW);
+
+        // The address that failed is retried once its backoff elapsed
+        let selected = book.select_outbound(&HashSet::new(), 10, NOW);
+        assert_eq!(selected, vec!["10.0.0.2:7000", "10.0.0.1:7000"]);
+        let selected = book.select_outbound(&HashSet::new(), 10, NOW + RETRY_BACKOFF * 2);
+        assert_eq!(selected, vec!["10.0.0.2:7000", "10.0.0.1:7000", "10.0.0.3:7000"]);
+
+        let exclude: HashSet<String> = [String::from("10.0.0.2:7000")].into_iter().collect();
+        assert_eq!(book.select_outbound(&exclude, 1, NOW), vec!["10.0.0.1:7000"]);
+    }
+
+    #[test]
+    fn test_failures() {
+        let mut book = AddressBook::new();
+        book.mark_attempt("10.0.0.1:7000", NOW);
+
+        for i in 0..MAX_FAILURES - 1 {
+            book.mark_failed("10.0.0.1:7000", NOW + i as u64);
+        }
+        assert!(book.addresses_to_share(10, NOW).is_empty());
+
+        // A successful connection resets the failures
+        book.mark_seen("10.0.0.1:7000", NOW);
+        assert_eq!(book.entries["10.0.0.1:7000"].failures, 0);
+        assert_eq!(book.addresses_to_share(10, NOW), vec!["10.0.0.1:7000"]);
+
+        for _ in 0..MAX_FAILURES {
+            book.mark_failed("10.0.0.1:7000", NOW);
+        }
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn test_bans() {
+        let mut book = AddressBook::new();
+        book.mark_seen("10.0.0.1:7000", NOW - 10);
+        book.mark_seen("10.0.0.2:7000", NOW - 10);
+        book.ban("10.0.0.1:51234", NOW + 60);
+
+        // The ban applies to every port of the host
+        assert!(book.is_banned("10.0.0.1:7000", NOW));
+        assert!(!book.is_banned("10.0.0.2:7000", NOW));
+        assert_eq!(book.select_outbound(&HashSet::new(), 10, NOW), vec!["10.0.0.2:7000"]);
+        assert_eq!(book.addresses_to_share(10, NOW), vec!["10.0.0.2:7000"]);
+
+        assert!(!book.is_banned("10.0.0.1:7000", NOW + 60));
+        book.remove_expired_bans(NOW + 60);
+        assert!(book.bans.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_addresses_are_not_added() {
+        let mut book = AddressBook::new();
+
+        for addr in ["", "10.0.0.1", ":7000", "10.0.0.1:", "10.0.0.1:70000", "10.0.0.1:port"] {
+            assert!(!book.add(addr), "{}", addr);
+        }
+        assert!(book.add("10.0.0.1:7000"));
+        assert!(book.add("seed.example.com:7000"));
+        assert!(book.add("[::1]:7000"));
+    }
+
+    #[test]
+    fn test_failed_addresses_are_evicted_when_full() {
+        let mut book = AddressBook::new();
+        for i in 0..MAX_ADDRESSES {
+            assert!(book.add(&format!("10.0.{}.{}:7000", i / 256, i % 256)));
+        }
+
+        // No address failed, there is no room for new addresses
+        assert!(!book.add("10.1.0.1:7000"));
+        assert_eq!(book.len(), MAX_ADDRESSES);
+
+        book.mark_failed("10.0.0.1:7000", NOW);
+        book.mark_failed("10.0.0.2:7000", NOW);
+        book.mark_failed("10.0.0.2:7000", NOW + 1);
+        book.mark_failed("10.0.0.3:7000", NOW + 1);
+
+        // The address that failed the most goes first, then the one tried the longest ago
+        assert!(book.add("10.1.0.1:7000"));
+        assert!(!book.entries.contains_key("10.0.0.2:7000"));
+        assert!(book.add("10.1.0.2:7000"));
+        assert!(!book.entries.contains_key("10.0.0.1:7000"));
+        assert!(book.entries.contains_key("10.0.0.3:7000"));
+        assert_eq!(book.len(), MAX_ADDRESSES);
+    }
+
+    #[test]
+    fn test_only_seen_addresses_are_shared() {
+        let mut book = AddressBook::new();
+        book.add("10.0.0.1:7000");
+        book.mark_attempt("10.0.0.2:7000", NOW);
+        book.mark_seen("10.0.0.3:7000", NOW);
+
+        assert_eq!(book.addresses_to_share(10, NOW), vec!["10.0.0.3:7000"]);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("marvin-address-book-{}.json", hex::encode(crate::crypto::keys::new_entropy())));
+        assert!(AddressBook::load(&path).unwrap().is_empty());
+
+        let mut book = AddressBook::new();
+        book.mark_seen("10.0.0.1:7000", NOW);
+        book.mark_failed("10.0.0.1:7000", NOW + 1);
+        book.add("10.0.0.2:7000");
+        book.ban("10.0.0.3:7000", NOW + 60);
+        book.save(&path).unwrap();
+
+        assert_eq!(AddressBook::load(&path).unwrap(), book);
+
+        fs::write(&path, b"not json").unwrap();
+        assert!(AddressBook::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+use crate::core::mempool::Mempool;
+use crate::error::{Result, MarvinError};
+use crate::proto;
+use crate::proto::message::Payload;
+use crate::types::hash::Hash;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use std::collections::{HashMap, HashSet};
+
+/// Returns the short id of a transaction in a compact block, the first 8 bytes of sha256(block hash || transaction hash).
+/// Short ids are salted with the block hash, so a collision in one block does not repeat in the next ones.
+pub fn short_id(block_hash: &Hash, tx_hash: &Hash) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.input(block_hash.as_bytes());
+    hasher.input(tx_hash.as_bytes());
+
+    let mut hash = [0; 32];
+    hasher.result(&mut hash);
+
+    u64::from_be_bytes(hash[..8].try_into().unwrap())
+}
+
+/// Create the compact form of a block
+pub fn compact_block(block: &proto::Block) -> proto::CompactBlock {
+    let hash = crate::types::block::block_hash(block);
+
+    proto::CompactBlock {
+        header: block.header.clone(),
+        public_key: block.public_key.clone(),
+        signature: block.signature.clone(),
+        short_ids: block
+            .transactions
+            .iter()
+            .map(|tx| short_id(&hash, &crate::types::transaction::transaction_hash(tx)))
+            .collect(),
+    }
+}
+
+/// Check the header of a compact block on its own: the proof of work, the minimum difficulty of the chain and the
+/// signature of the block. A compact block failing these checks can never become a valid block.
+pub fn check_header(compact: &proto::CompactBlock, min_difficulty: u32) -> Result<()> {
+    let header = compact
+        .header
+        .as_ref()
+        .ok_or_else(|| MarvinError::Validation(String::from("Compact block header is missing")))?;
+
+    if header.difficulty < min_difficulty || !crate::types::block::meets_difficulty(header) {
+        return Err(MarvinError::Validation(String::from("Block does not meet the proof of work difficulty")));
+    }
+
+    // The signature of a block covers its header only
+    let block = proto::Block {
+        header: compact.header.clone(),
+        public_key: compact.public_key.clone(),
+        signature: compact.signature.clone(),
+        ..Default::default()
+    };
+    if !crate::types::block::verify_block(&block)? {
+        return Err(MarvinError::Crypto(String::from("Invalid block signature")));
+    }
+
+    Ok(())
+}
+
+/// PartialBlock is a compact block being reconstructed from the mempool and the transactions requested from the peer
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+    pub hash: Hash,
+    compact: proto::CompactBlock,
+    transactions: Vec<Option<proto::Transaction>>,
+}
+
+impl PartialBlock {
+    /// Start the reconstruction of a compact block with the transactions of the mempool.
+    /// Short ids matching several transactions of the mempool are left missing and requested from the peer.
+    pub fn new(compact: proto::CompactBlock, mempool: &Mempool) -> Result<PartialBlock> {
+        let header = compact
+            .header
+            .as_ref()
+            .ok_or_else(|| MarvinError::Validation(String::from("Compact block header is missing")))?;
+        let hash = Hash::from_bytes(&crate::types::block::hash_header(header))?;
+
+        let unique: HashSet<u64> = compact.short_ids.iter().copied().collect();
+        if unique.len() != compact.short_ids.len() {
+            return Err(MarvinError::Validation(String::from("Duplicate short ids in compact block")));
+        }
+
+        let mut candidates: HashMap<u64, Option<&proto::Transaction>> = HashMap::new();
+        for (tx_hash, entry) in mempool.transactions.iter() {
+            let id = short_id(&hash, tx_hash);
+            if unique.contains(&id) {
+                candidates.entry(id).and_modify(|tx| *tx = None).or_insert(Some(&entry.tx));
+            }
+        }
+
+        let transactions = compact
+            .short_ids
+            .iter()
+            .map(|id| candidates.get(id).copied().flatten().cloned())
+            .collect();
+
+        Ok(PartialBlock { hash, compact, transactions })
+    }
+
+    pub fn header(&self) -> &proto::Header {
+        self.compact.header.as_ref().unwrap()
+    }
+
+    /// Returns the indexes of the transactions that are still missing
+    pub fn missing(&self) -> Vec<u32> {
+        self.transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(index, _)| index as u32)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.transactions.iter().all(|tx| tx.is_some())
+    }
+
+    /// Fill the missing transactions with the ones sent by the peer, in the order they were requested
+    pub fn fill(&mut self, transactions: Vec<proto::Transaction>) -> Result<()> {
+        let missing = self.missing();
+        if transactions.len() != missing.len() {
+            return Err(MarvinError::Validation(format!(
+                "Expected {} block transactions, got {}", missing.len(), transactions.len()
+            )));
+        }
+
+        for (index, tx) in missing.iter().zip(transactions.iter()) {
+            if short_id(&self.hash, &crate::types::transaction::transaction_hash(tx)) != self.compact.short_ids[*index as usize] {
+                return Err(MarvinError::Validation(format!("Transaction {} does not match its short id", index)));
+            }
+        }
+        for (index, tx) in missing.into_iter().zip(transactions) {
+            self.transactions[index as usize] = Some(tx);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the reconstructed block, `None` if transactions are still missing
+    pub fn into_block(self) -> Option<proto::Block> {
+        let transactions = self.transactions.into_iter().collect::<Option<Vec<_>>>()?;
+
+        Some(proto::Block {
+            header: self.compact.header,
+            transactions,
+            public_key: self.compact.public_key,
+            signature: self.compact.signature,
+            hash: self.hash.to_bytes().to_vec(),
+        })
+    }
+}
+
+/// Returns the transactions of a block at the requested indexes
+pub fn select_transactions(block: &proto::Block, indexes: &[u32]) -> Result<Vec<proto::Transaction>> {
+    indexes
+        .iter()
+        .map(|index| {
+            block
+                .transactions
+                .get(*index as usize)
+                .cloned()
+                .ok_or_else(|| MarvinError::Validation(format!("Block has no transaction at index {}", index)))
+        })
+        .collect()
+}
+
+/// Create a message carrying a compact block
+pub fn compact_block_message(compact: proto::CompactBlock) -> proto::Message {
+    proto::Message {
+        payload: Some(Payload::CompactBlock(compact)),
+    }
+}
+
+/// Create a GetBlockTransactions message requesting the missing transactions of a compact block
+pub fn get_block_transactions(block_hash: &Hash, indexes: Vec<u32>) -> proto::Message {
+    proto::Message {
+        payload: Some(Payload::GetBlockTransactions(proto::GetBlockTransactions {
+            block_hash: block_hash.to_bytes().to_vec(),
+            indexes,
+        })),
+    }
+}
+
+/// Create a BlockTransactions message carrying the requested transactions of a block
+pub fn block_transactions(block_hash: &Hash, transactions: Vec<proto::Transaction>) -> proto::Message {
+    proto::Message {
+        payload: Some(Payload::BlockTransactions(proto::BlockTransactions {
+            block_hash: block_hash.to_bytes().to_vec(),
+            transactions,
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys;
+
+    fn signed_transaction(nonce: i64) -> proto::Transaction {
+        let mut tx = proto::Transaction {
+            to: keys::generate_private_key().public_key().to_bytes().to_vec(),
+            value: 10,
+            nonce,
+            ..Default::default()
+        };
+        crate::types::transaction::sign_transaction(&mut keys::generate_private_key(), &mut tx).unwrap();
+
+        tx
+    }
+
+    fn signed_block(transactions: Vec<proto::Transaction>) -> proto::Block {
+        let mut block = proto::Block {
+            header: Some(proto::Header { height: 1, version: 1, ..Default::default() }),
+            ..Default::default()
+        };
+        for tx in transactions {
+            crate::types::block::add_transaction(&mut block, tx);
+        }
+        crate::types::block::sign_block(&mut keys::generate_private_key(), &mut block).unwrap();
+
+        block
+    }
+
+    #[test]
+    fn test_reconstruct_from_mempool() {
+        let transactions: Vec<proto::Transaction> = (0..3).map(signed_transaction).collect();
+        let block = signed_block(transactions.clone());
+
+        let mut mempool = Mempool::new();
+        for tx in transactions.iter() {
+            mempool.add(tx).unwrap();
+        }
+
+        let partial = PartialBlock::new(compact_block(&block), &mempool).unwrap();
+        assert!(partial.is_complete());
+        assert_eq!(partial.into_block(), Some(block));
+    }
+
+    #[test]
+    fn test_fill_missing_transactions() {
+        let transactions: Vec<proto::Transaction> = (0..4).map(signed_transaction).collect();
+        let block = signed_block(transactions.clone());
+
+        let mut mempool = Mempool::new();
+        mempool.add(&transactions[1]).unwrap();
+        mempool.add(&transactions[3]).unwrap();
+
+        let mut partial = PartialBlock::new(compact_block(&block), &mempool).unwrap();
+        assert_eq!(partial.missing(), vec![0, 2]);
+
+        // Transactions that do not match their short id are rejected
+        assert!(partial.fill(vec![transactions[0].clone()]).is_err());
+        assert!(partial.fill(vec![transactions[2].clone(), transactions[0].clone()]).is_err());
+
+        let requested = select_transactions(&block, &partial.missing()).unwrap();
+        partial.fill(requested).unwrap();
+        assert!(partial.missing().is_empty());
+        assert_eq!(partial.into_block(), Some(block));
+    }
+
+    #[test]
+    fn test_check_header() {
+        let mut block = signed_block(vec![signed_transaction(1)]);
+        block.header.as_mut().unwrap().difficulty = 8;
+        crate::types::block::mine_header(block.header.as_mut().unwrap());
+        crate::types::block::sign_block(&mut keys::generate_private_key(), &mut block).unwrap();
+
+        let compact = compact_block(&block);
+        assert!(check_header(&compact, 8).is_ok());
+        assert!(check_header(&compact, 9).is_err());
+
+        let mut unmined = compact.clone();
+        let header = unmined.header.as_mut().unwrap();
+        while crate::types::block::meets_difficulty(header) {
+            header.nonce += 1;
+        }
+        assert!(check_header(&unmined, 8).is_err());
+
+        let mut forged = compact.clone();
+        forged.public_key = keys::generate_private_key().public_key().to_bytes().to_vec();
+        assert!(check_header(&forged, 8).is_err());
+
+        let mut headless = compact;
+        headless.header = None;
+        assert!(check_header(&headless, 0).is_err());
+    }
+
+    #[test]
+    fn test_malformed_compact_block() {
+        let block = signed_block(vec![signed_transaction(1)]);
+        let mempool = Mempool::new();
+
+        let mut compact = compact_block(&block);
+        compact.short_ids.push(compact.short_ids[0]);
+        assert!(PartialBlock::new(compact, &mempool).is_err());
+
+        let mut compact = compact_block(&block);
+        compact.header = None;
+        assert!(PartialBlock::new(compact, &mempool).is_err());
+
+        assert!(select_transactions(&block, &[1]).is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::error::Result;
+use crate::network::transport::PeerId;
+use crate::proto;
+use crate::proto::message::Payload;
+use crate::proto::InventoryType;
+use crate::types::hash::Hash;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Default number of hashes remembered by the node to avoid requesting the same item twice
+pub const SEEN_HASHES_CAPACITY: usize = 16 * 1024;
+/// Maximum number of hashes in a single inventory, get data or not found message
+pub const MAX_INVENTORY_SIZE: usize = 1024;
+/// Time a peer has to deliver an item requested with a GetData message before it is requested from another peer
+pub const GOSSIP_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum number of other peers remembered for an item, to request it from if the first peer does not deliver it
+const MAX_ANNOUNCERS: usize = 8;
+
+/// SeenHashes is a bounded set of hashes, once full the oldest hashes are forgotten first
+pub struct SeenHashes {
+    capacity: usize,
+    order: VecDeque<Hash>,
+    hashes: HashSet<Hash>,
+}
+
+impl SeenHashes {
+    pub fn new(capacity: usize) -> Self {
+        SeenHashes {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            hashes: HashSet::with_capacity(capacity),
+        }
+    }
+
+    /// Insert a hash, returns false if the hash was already in the set
+    pub fn insert(&mut self, hash: Hash) -> bool {
+        if !self.hashes.insert(hash) {
+            return false;
+        }
+
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+
+        true
+    }
+
+    /// Check if a hash is in the set
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    /// Forget a hash, so the matching item can be requested again
+    pub fn remove(&mut self, hash: &Hash) -> bool {
+        if !self.hashes.remove(hash) {
+            return false;
+        }
+        self.order.retain(|existing| existing != hash);
+
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+}
+
+/// Request is an item requested from a peer, along with the other peers that announced it
+struct Request {
+    kind: InventoryType,
+    peer: PeerId,
+    deadline: Instant,
+    announcers: VecDeque<PeerId>,
+}
+
+/// GossipRequests tracks the items requested from peers with GetData messages and not delivered yet.
+/// An item that is not delivered in time is requested again from the next peer that announced it.
+pub struct GossipRequests {
+    timeout: Duration,
+    requests: HashMap<Hash, Request>,
+}
+
+impl GossipRequests {
+    pub fn new(timeout: Duration) -> Self {
+        GossipRequests {
+            timeout,
+            requests: HashMap::new(),
+        }
+    }
+
+    /// Record that an item was requested from a peer, the peers that announced it are kept
+    pub fn insert(&mut self, hash: Hash, kind: InventoryType, peer: PeerId, now: Instant) {
+        let mut announcers = self.requests.remove(&hash).map(|request| request.announcers).unwrap_or_default();
+        announcers.retain(|announcer| *announcer != peer);
+
+        self.requests.insert(hash, Request {
+            kind,
+            peer,
+            deadline: now + self.timeout,
+            announcers,
+        });
+    }
+
+    /// Remember a peer announcing an item already requested from another peer.
+    /// Returns false if the item is not requested.
+    pub fn add_announcer(&mut self, hash: &Hash, peer: PeerId) -> bool {
+        let request = match self.requests.get_mut(hash) {
+            Some(request) => request,
+            None => return false,
+        };
+
+        if request.peer != peer && !request.announcers.contains(&peer) && request.announcers.len() < MAX_ANNOUNCERS {
+            request.announcers.push_back(peer);
+        }
+
+        true
+    }
+
+    /// Forget the request of an item once it was received, returns false if the item was not requested
+    pub fn remove(&mut self, hash: &Hash) -> bool {
+        self.requests.remove(hash).is_some()
+    }
+
+    /// Check if an item is requested from any peer
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.requests.contains_key(hash)
+    }
+
+    /// Check if an item is requested from the given peer
+    pub fn is_requested_from(&self, hash: &Hash, peer: PeerId) -> bool {
+        self.requests.get(hash).is_some_and(|request| request.peer == peer)
+    }
+
+    /// Returns the items whose deadline passed
+    pub fn expired(&self, now: Instant) -> Vec<Hash> {
+        self.requests.iter().filter(|(_, request)| now > request.deadline).map(|(hash, _)| *hash).collect()
+    }
+
+    /// Forget a disconnected peer as an announcer, and return the items requested from it
+    pub fn peer_disconnected(&mut self, peer: PeerId) -> Vec<Hash> {
+        let mut requested = Vec::new();
+        for (hash, request) in self.requests.iter_mut() {
+            request.announcers.retain(|announcer| *announcer != peer);
+            if request.peer == peer {
+                requested.push(*hash);
+            }
+        }
+
+        requested
+    }
+
+    /// Move the request of an item to the next peer that announced it, returning that peer and the type of the item.
+    /// The request is forgotten if no other peer announced the item.
+    pub fn retry(&mut self, hash: &Hash, now: Instant) -> Option<(PeerId, InventoryType)> {
+        let request = self.requests.get_mut(hash)?;
+        match request.announcers.pop_front() {
+            Some(peer) => {
+                request.peer = peer;
+                request.deadline = now + self.timeout;
+                Some((peer, request.kind))
+            }
+            None => {
+                self.requests.remove(hash);
+                None
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+/// Parse the hashes of an inventory, get data or not found message
+pub fn parse_hashes(hashes: &[Vec<u8>]) -> Result<Vec<Hash>> {
+    hashes.iter().map(|hash| Hash::from_bytes(hash)).collect()
+}
+
+fn encode_hashes(hashes: &[Hash]) -> Vec<Vec<u8>> {
+    hashes.iter().map(|hash| hash.to_bytes().to_vec()).collect()
+}
+
+/// Create an Inventory message announcing the given items
+pub fn inventory(kind: InventoryType, hashes: &[Hash]) -> proto::Message {
+    proto::Message {
+        payload: Some(Payload::Inventory(proto::Inventory {
+            r#type: kind as i32,
+            hashes: encode_hashes(hashes),
+        })),
+    }
+}
+
+/// Create a GetData message requesting the given items
+pub fn get_data(kind: InventoryType, hashes: &[Hash]) -> proto::Message {
+    proto::Message {
+        payload: Some(Payload::GetData(proto::GetData {
+            r#type: kind as i32,
+            hashes: encode_hashes(hashes),
+        })),
+    }
+}
+
+/// Create a NotFound message for the requested items that are not available
+pub fn not_found(kind: InventoryType, hashes: &[Hash]) -> proto::Message {
+    proto::Message {
+        payload: Some(Payload::NotFound(proto::NotFound {
+            r#type: kind as i32,
+            hashes: encode_hashes(hashes),
+        })),
+    }
+}
+
+/// Create a message carrying a block
+pub fn block_message(block: proto::Block) -> proto::Message {
+    proto::Message {
+        payload: Some(Payload::Block(block)),
+    }
+}
+
+/// Create a message carrying a transaction
+pub fn transaction_message(tx: proto::Transaction) -> proto::Message {
+    proto::Message {
+        payload: Some(Payload::Transaction(tx)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seen_hashes() {
+        let mut seen = SeenHashes::new(2);
+        let (a, b, c) = (Hash([1; 32]), Hash([2; 32]), Hash([3; 32]));
+
+        assert!(seen.insert(a));
+        assert!(!seen.insert(a));
+        assert!(seen.insert(b));
+
+        // The oldest hash is forgotten once the capacity is exceeded
+        assert!(seen.insert(c));
+        assert_eq!(seen.len(), 2);
+        assert!(!seen.contains(&a));
+        assert!(seen.contains(&b));
+
+        assert!(seen.remove(&b));
+        assert!(!seen.remove(&b));
+        assert!(seen.insert(b));
+    }
+
+    #[test]
+    fn test_gossip_requests() {
+        let mut requests = GossipRequests::new(GOSSIP_REQUEST_TIMEOUT);
+        let (a, b) = (Hash([1; 32]), Hash([2; 32]));
+        let now = Instant::now();
+
+        assert!(!requests.add_announcer(&a, 2));
+        requests.insert(a, InventoryType::Transaction, 1, now);
+        requests.insert(b, InventoryType::Block, 1, now);
+        assert!(requests.add_announcer(&a, 1));
+        assert!(requests.add_announcer(&a, 2));
+        assert!(requests.add_announcer(&a, 3));
+        assert!(requests.is_requested_from(&a, 1));
+        assert!(requests.contains(&b) && !requests.is_requested_from(&b, 2));
+
+        // Expired requests move to the next peer that announced the item
+        assert!(requests.expired(now + GOSSIP_REQUEST_TIMEOUT / 2).is_empty());
+        let later = now + GOSSIP_REQUEST_TIMEOUT * 2;
+        assert_eq!(requests.expired(later).len(), 2);
+        assert_eq!(requests.retry(&a, later), Some((2, InventoryType::Transaction)));
+        assert_eq!(requests.retry(&b, later), None);
+        assert_eq!(requests.len(), 1);
+        assert!(requests.expired(later).is_empty());
+
+        // A disconnected peer is no longer asked for anything
+        assert_eq!(requests.peer_disconnected(3), vec![]);
+        assert_eq!(requests.peer_disconnected(2), vec![a]);
+        assert_eq!(requests.retry(&a, later), None);
+
+        requests.insert(b, InventoryType::Block, 1, now);
+        assert!(requests.remove(&b));
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn test_inventory_hashes_round_trip() {
+        let hashes = vec![Hash([1; 32]), Hash([2; 32])];
+
+        let message = inventory(InventoryType::Block, &hashes);
+        let inventory = match message.payload {
+            Some(Payload::Inventory(inventory)) => inventory,
+            payload => panic!("unexpected payload {:?}", payload),
+        };
+
+        assert_eq!(inventory.r#type(), InventoryType::Block);
+        assert_eq!(parse_hashes(&inventory.hashes).unwrap(), hashes);
+        assert!(parse_hashes(&[vec![1; 31]]).is_err());
+    }
+}
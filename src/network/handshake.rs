@@ -0,0 +1,150 @@
+use crate::crypto::keys::{PrivateKey, PublicKey, SignatureWrapper};
+use crate::error::{Result, MarvinError};
+use crate::proto;
+
+use prost::Message;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use std::time::Duration;
+
+/// Version of the peer-to-peer protocol spoken by this node
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Maximum difference between the timestamp of a handshake and the local clock
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// Create a handshake for the given network and chain tip, signed with the node private key
+pub fn new_handshake(
+    private_key: &mut PrivateKey,
+    chain_id: &str,
+    difficulty: u32,
+    genesis_hash: &[u8],
+    best_height: u64,
+    listen_addr: &str,
+    timestamp: i64,
+) -> Result<proto::Handshake> {
+    let mut handshake = proto::Handshake {
+        protocol_version: PROTOCOL_VERSION,
+        chain_id: chain_id.to_string(),
+        genesis_hash: genesis_hash.to_vec(),
+        best_height,
+        public_key: private_key.public_key().to_bytes().to_vec(),
+        timestamp,
+        signature: vec![],
+        listen_addr: listen_addr.to_string(),
+        difficulty,
+    };
+
+    let signature = private_key.sign(&hash_handshake(&handshake))?;
+    handshake.signature = signature.to_bytes().to_vec();
+
+    Ok(handshake)
+}
+
+/// Verify the handshake of a peer against the local network, returning the public key of the peer.
+/// `now` is the local time as a Unix timestamp in nanoseconds.
+pub fn verify_handshake(handshake: &proto::Handshake, chain_id: &str, difficulty: u32, genesis_hash: &[u8], now: i64) -> Result<PublicKey> {
+    if handshake.protocol_version != PROTOCOL_VERSION {
+        return Err(MarvinError::Network(format!(
+            "Unsupported protocol version {}, expected {}", handshake.protocol_version, PROTOCOL_VERSION
+        )));
+    }
+
+    if handshake.chain_id != chain_id {
+        return Err(MarvinError::Network(format!("Chain id mismatch, expected {} got {}", chain_id, handshake.chain_id)));
+    }
+
+    if handshake.difficulty != difficulty {
+        return Err(MarvinError::Network(format!(
+            "Minimum difficulty mismatch, expected {} got {}", difficulty, handshake.difficulty
+        )));
+    }
+
+    if handshake.genesis_hash != genesis_hash {
+        return Err(MarvinError::Network(String::from("Genesis hash mismatch")));
+    }
+
+    if handshake.timestamp.abs_diff(now) > MAX_CLOCK_SKEW.as_nanos() as u64 {
+        return Err(MarvinError::Network(String::from("Handshake timestamp is too far from the local clock")));
+    }
+
+    let public_key = PublicKey::from_bytes(&handshake.public_key)?;
+    let signature = SignatureWrapper::from_bytes(&handshake.signature)?;
+    if !signature.verify(&hash_handshake(handshake), &public_key) {
+        return Err(MarvinError::Network(String::from("Invalid handshake signature")));
+    }
+
+    Ok(public_key)
+}
+
+/// Calculate the hash of a handshake without its signature, which is the data signed by the peer
+fn hash_handshake(handshake: &proto::Handshake) -> Vec<u8> {
+    let mut unsigned = handshake.clone();
+    unsigned.signature = vec![];
+
+    let mut hasher = Sha256::new();
+    hasher.input(&unsigned.encode_to_vec());
+
+    let mut hash = [0; 32];
+    hasher.result(&mut hash);
+
+    hash.to_vec()
+}
+
+/// Wrap a handshake into a message
+pub fn handshake_message(handshake: proto::Handshake) -> proto::Message {
+    proto::Message {
+        payload: Some(proto::message::Payload::Handshake(handshake)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys;
+
+    const CHAIN_ID: &str = "marvin-test";
+    const DIFFICULTY: u32 = 8;
+    const GENESIS_HASH: [u8; 32] = [1; 32];
+    const NOW: i64 = 1722470400000000000;
+
+    #[test]
+    fn test_verify_handshake() {
+        let mut private_key = keys::generate_private_key();
+        let handshake = new_handshake(&mut private_key, CHAIN_ID, DIFFICULTY, &GENESIS_HASH, 10, "127.0.0.1:9000", NOW).unwrap();
+
+        let public_key = verify_handshake(&handshake, CHAIN_ID, DIFFICULTY, &GENESIS_HASH, NOW).unwrap();
+        assert_eq!(public_key, private_key.public_key());
+    }
+
+    #[test]
+    fn test_verify_handshake_mismatch() {
+        let mut private_key = keys::generate_private_key();
+        let handshake = new_handshake(&mut private_key, CHAIN_ID, DIFFICULTY, &GENESIS_HASH, 10, "127.0.0.1:9000", NOW).unwrap();
+
+        assert!(verify_handshake(&handshake, "marvin-other", DIFFICULTY, &GENESIS_HASH, NOW).is_err());
+        assert!(verify_handshake(&handshake, CHAIN_ID, DIFFICULTY, &[2; 32], NOW).is_err());
+        assert!(verify_handshake(&handshake, CHAIN_ID, DIFFICULTY + 1, &GENESIS_HASH, NOW).is_err());
+
+        let skew = MAX_CLOCK_SKEW.as_nanos() as i64;
+        assert!(verify_handshake(&handshake, CHAIN_ID, DIFFICULTY, &GENESIS_HASH, NOW + skew + 1).is_err());
+
+        let mut other_version = handshake.clone();
+        other_version.protocol_version = PROTOCOL_VERSION + 1;
+        assert!(verify_handshake(&other_version, CHAIN_ID, DIFFICULTY, &GENESIS_HASH, NOW).is_err());
+    }
+
+    #[test]
+    fn test_verify_handshake_tampered() {
+        let mut private_key = keys::generate_private_key();
+        let mut handshake = new_handshake(&mut private_key, CHAIN_ID, DIFFICULTY, &GENESIS_HASH, 10, "127.0.0.1:9000", NOW).unwrap();
+        handshake.best_height = 1000;
+        assert!(verify_handshake(&handshake, CHAIN_ID, DIFFICULTY, &GENESIS_HASH, NOW).is_err());
+
+        // Claiming the identity of another node
+        let mut handshake = new_handshake(&mut private_key, CHAIN_ID, DIFFICULTY, &GENESIS_HASH, 10, "127.0.0.1:9000", NOW).unwrap();
+        handshake.public_key = keys::generate_private_key().public_key().to_bytes().to_vec();
+        assert!(verify_handshake(&handshake, CHAIN_ID, DIFFICULTY, &GENESIS_HASH, NOW).is_err());
+    }
+}
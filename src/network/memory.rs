@@ -0,0 +1,258 @@
+use crate::crypto::keys::PublicKey;
+use crate::error::{Result, MarvinError};
+use crate::network::message::{decode_message, encode_frame, FRAME_HEADER_SIZE};
+use crate::network::transport::{report_disconnected, EventStream, PeerId, Transport, TransportEvent};
+use crate::network::transport::EVENT_CHANNEL_CAPACITY;
+use crate::proto;
+use crate::types::limits::SizeLimits;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+/// MemoryNetwork connects the memory transports of a single process to each other by address.
+/// It lets tests run several nodes in one process without opening any socket.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    listeners: Arc<Mutex<HashMap<String, Arc<Inner>>>>,
+}
+
+/// MemoryTransport is a transport whose connections are in-process channels, messages are delivered
+/// in order and go through the same encoding as the TCP transport
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    inner: Arc<Inner>,
+}
+
+/// Link is the local end of a connection, pointing to the transport on the other end
+struct Link {
+    remote: Arc<Inner>,
+    remote_peer: PeerId,
+}
+
+/// State of a memory transport, shared with the transports it is connected to
+struct Inner {
+    addr: String,
+    next_peer_id: AtomicU64,
+    links: Mutex<HashMap<PeerId, Link>>,
+    events: mpsc::Sender<TransportEvent>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        MemoryNetwork::default()
+    }
+
+    /// Create a transport accepting connections on the given address of the network.
+    /// Returns the transport along with the stream of its events.
+    pub fn bind(&self, addr: &str) -> Result<(MemoryTransport, EventStream)> {
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.contains_key(addr) {
+            return Err(MarvinError::Network(format!("Address {} is already in use", addr)));
+        }
+
+        let (events, event_stream) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let inner = Arc::new(Inner {
+            addr: addr.to_string(),
+            next_peer_id: AtomicU64::new(1),
+            links: Mutex::new(HashMap::new()),
+            events,
+        });
+        listeners.insert(addr.to_string(), inner.clone());
+
+        Ok((MemoryTransport { network: self.clone(), inner }, event_stream))
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network.listeners.lock().unwrap().remove(&self.inner.addr);
+
+        let peers: Vec<PeerId> = self.inner.links.lock().unwrap().keys().copied().collect();
+        for peer in peers {
+            self.inner.close(peer);
+        }
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn local_addr(&self) -> String {
+        self.inner.addr.clone()
+    }
+
+    async fn dial(&self, addr: &str) -> Result<PeerId> {
+        let remote = match self.network.listeners.lock().unwrap().get(addr) {
+            Some(remote) => remote.clone(),
+            None => return Err(MarvinError::Network(format!("Connection refused by {}", addr))),
+        };
+
+        let local_peer = self.inner.next_peer_id.fetch_add(1, Ordering::Relaxed);
+        let remote_peer = remote.next_peer_id.fetch_add(1, Ordering::Relaxed);
+
+        self.inner.links.lock().unwrap().insert(local_peer, Link { remote: remote.clone(), remote_peer });
+        remote.links.lock().unwrap().insert(remote_peer, Link { remote: self.inner.clone(), remote_peer: local_peer });
+
+        let connected = TransportEvent::Connected { peer: local_peer, addr: addr.to_string(), inbound: false };
+        let accepted = TransportEvent::Connected { peer: remote_peer, addr: self.inner.addr.clone(), inbound: true };
+        let _ = self.inner.events.send(connected).await;
+        let _ = remote.events.send(accepted).await;
+
+        Ok(local_peer)
+    }
+
+    async fn send(&self, peer: PeerId, message: proto::Message) -> Result<()> {
+        let (remote, event) = self.inner.deliver(peer, &message)?;
+
+        remote
+            .events
+            .send(event)
+            .await
+            .map_err(|_| MarvinError::Network(format!("Connection with peer {} is closed", peer)))
+    }
+
+    /// Messages are queued on the event stream of the remote transport, which is full when the remote node falls behind
+    fn try_send(&self, peer: PeerId, message: proto::Message) -> Result<()> {
+        let (remote, event) = self.inner.deliver(peer, &message)?;
+
+        remote.events.try_send(event).map_err(|e| match e {
+            TrySendError::Full(_) => MarvinError::Network(format!("Outgoing queue of peer {} is full", peer)),
+            TrySendError::Closed(_) => MarvinError::Network(format!("Connection with peer {} is closed", peer)),
+        })
+    }
+
+    fn disconnect(&self, peer: PeerId) {
+        self.inner.close(peer);
+    }
+
+    fn peers(&self) -> Vec<PeerId> {
+        self.inner.links.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Memory connections never leave the process and are not authenticated
+    fn remote_public_key(&self, _peer: PeerId) -> Option<PublicKey> {
+        None
+    }
+}
+
+impl Inner {
+    /// Returns the transport on the other end of a connection, along with the event delivering the message to it
+    fn deliver(&self, peer: PeerId, message: &proto::Message) -> Result<(Arc<Inner>, TransportEvent)> {
+        let (remote, remote_peer) = match self.links.lock().unwrap().get(&peer) {
+            Some(link) => (link.remote.clone(), link.remote_peer),
+            None => return Err(MarvinError::Network(format!("Peer {} is not connected", peer))),
+        };
+
+        // Go through the wire encoding, so the messages are exactly the ones a TCP peer would receive
+        let frame = encode_frame(message)?;
+        let message = decode_message(&frame[FRAME_HEADER_SIZE..], &SizeLimits::default())?;
+
+        Ok((remote, TransportEvent::Message { peer: remote_peer, message }))
+    }
+
+    /// Close both ends of a connection, reporting it on the event stream of both transports
+    fn close(&self, peer: PeerId) {
+        let link = self.links.lock().unwrap().remove(&peer);
+        if let Some(link) = link {
+            report_disconnected(&self.events, peer);
+
+            if link.remote.links.lock().unwrap().remove(&link.remote_peer).is_some() {
+                report_disconnected(&link.remote.events, link.remote_peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::message::{ping, pong};
+
+    #[tokio::test]
+    async fn test_dial_and_exchange_messages() {
+        let network = MemoryNetwork::new();
+        let (server, mut server_events) = network.bind("server").unwrap();
+        let (client, mut client_events) = network.bind("client").unwrap();
+
+        let server_peer = client.dial("server").await.unwrap();
+        assert_eq!(
+            client_events.recv().await.unwrap(),
+            TransportEvent::Connected { peer: server_peer, addr: String::from("server"), inbound: false }
+        );
+        let client_peer = match server_events.recv().await.unwrap() {
+            TransportEvent::Connected { peer, addr, inbound: true } if addr == "client" => peer,
+            event => panic!("unexpected event {:?}", event),
+        };
+
+        client.send(server_peer, ping(1)).await.unwrap();
+        server.send(client_peer, pong(1)).await.unwrap();
+
+        assert_eq!(server_events.recv().await.unwrap(), TransportEvent::Message { peer: client_peer, message: ping(1) });
+        assert_eq!(client_events.recv().await.unwrap(), TransportEvent::Message { peer: server_peer, message: pong(1) });
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let network = MemoryNetwork::new();
+        let (server, mut server_events) = network.bind("server").unwrap();
+        let (client, mut client_events) = network.bind("client").unwrap();
+
+        let server_peer = client.dial("server").await.unwrap();
+        client_events.recv().await.unwrap();
+        server_events.recv().await.unwrap();
+
+        client.disconnect(server_peer);
+        assert_eq!(client_events.recv().await.unwrap(), TransportEvent::Disconnected { peer: server_peer });
+        assert!(matches!(server_events.recv().await.unwrap(), TransportEvent::Disconnected { .. }));
+        assert!(client.peers().is_empty());
+        assert!(server.peers().is_empty());
+        assert!(client.send(server_peer, ping(1)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_try_send_full_queue() {
+        let network = MemoryNetwork::new();
+        let (_server, mut server_events) = network.bind("server").unwrap();
+        let (client, _client_events) = network.bind("client").unwrap();
+
+        let server_peer = client.dial("server").await.unwrap();
+        server_events.recv().await.unwrap();
+
+        // The server does not read its events, the messages pile up until the queue is full
+        for nonce in 0..EVENT_CHANNEL_CAPACITY as u64 {
+            client.try_send(server_peer, ping(nonce)).unwrap();
+        }
+        assert!(client.try_send(server_peer, ping(0)).is_err());
+
+        // The queue is usable again once the server catches up
+        assert!(matches!(server_events.recv().await.unwrap(), TransportEvent::Message { message, .. } if message == ping(0)));
+        client.try_send(server_peer, ping(0)).unwrap();
+
+        client.disconnect(server_peer);
+        assert!(client.try_send(server_peer, ping(0)).is_err());
+
+        // The disconnection is not lost on the full queue, it follows the messages
+        for _ in 0..EVENT_CHANNEL_CAPACITY {
+            assert!(matches!(server_events.recv().await.unwrap(), TransportEvent::Message { .. }));
+        }
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), server_events.recv()).await.unwrap();
+        assert!(matches!(event, Some(TransportEvent::Disconnected { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_bind_and_dial_errors() {
+        let network = MemoryNetwork::new();
+        let (client, _events) = network.bind("client").unwrap();
+
+        assert!(network.bind("client").is_err());
+        assert!(client.dial("nowhere").await.is_err());
+
+        // The address is released when the transport is dropped
+        let (server, _server_events) = network.bind("server").unwrap();
+        drop(server);
+        assert!(client.dial("server").await.is_err());
+        assert!(network.bind("server").is_ok());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::error::{Result, MarvinError};
+use crate::proto;
+use crate::types::limits::SizeLimits;
+
+use prost::Message;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the length prefix of every frame
+pub const FRAME_HEADER_SIZE: usize = 4;
+/// Maximum size of the payload of a frame sent by the node, received frames are bounded by the `SizeLimits` of the node
+pub const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+
+/// Encode a message into a frame, a 4 byte big endian length prefix followed by the protobuf encoded message
+pub fn encode_frame(message: &proto::Message) -> Result<Vec<u8>> {
+    let payload = message.encode_to_vec();
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(MarvinError::Network(format!("Message of {} bytes exceeds the maximum frame size", payload.len())));
+    }
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+
+    Ok(frame)
+}
+
+/// Write a message as a single frame
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &proto::Message) -> Result<()> {
+    let frame = encode_frame(message)?;
+    writer.write_all(&frame).await.map_err(|e| MarvinError::Network(e.to_string()))?;
+    writer.flush().await.map_err(|e| MarvinError::Network(e.to_string()))
+}
+
+/// Decode the payload of a frame, the sizes of the message and of the blocks and transactions it carries
+/// are checked before decoding
+pub fn decode_message(payload: &[u8], limits: &SizeLimits) -> Result<proto::Message> {
+    limits.check_message(payload)?;
+    proto::Message::decode(payload).map_err(|e| MarvinError::Network(e.to_string()))
+}
+
+/// Read the next frame and decode the message it carries.
+/// Returns `None` if the stream was closed cleanly before a new frame started.
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R, limits: &SizeLimits) -> Result<Option<proto::Message>> {
+    let mut header = [0; FRAME_HEADER_SIZE];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(MarvinError::Network(e.to_string())),
+    }
+
+    // Check the length before allocating anything for the payload
+    let length = u32::from_be_bytes(header) as usize;
+    if length > limits.max_message_size {
+        return Err(MarvinError::Network(format!("Frame of {} bytes exceeds the maximum message size", length)));
+    }
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload).await.map_err(|e| MarvinError::Network(e.to_string()))?;
+
+    decode_message(&payload, limits).map(Some)
+}
+
+/// Create a Ping message
+pub fn ping(nonce: u64) -> proto::Message {
+    proto::Message {
+        payload: Some(proto::message::Payload::Ping(proto::Ping { nonce })),
+    }
+}
+
+/// Create a Pong message
+pub fn pong(nonce: u64) -> proto::Message {
+    proto::Message {
+        payload: Some(proto::message::Payload::Pong(proto::Pong { nonce })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::limits::DEFAULT_MAX_MESSAGE_SIZE;
+
+    #[tokio::test]
+    async fn test_write_and_read_message() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        write_message(&mut client, &ping(1)).await.unwrap();
+        write_message(&mut client, &pong(2)).await.unwrap();
+        drop(client);
+
+        assert_eq!(read_message(&mut server, &SizeLimits::default()).await.unwrap(), Some(ping(1)));
+        assert_eq!(read_message(&mut server, &SizeLimits::default()).await.unwrap(), Some(pong(2)));
+        assert_eq!(read_message(&mut server, &SizeLimits::default()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_oversized_frame() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        client.write_all(&((DEFAULT_MAX_MESSAGE_SIZE + 1) as u32).to_be_bytes()).await.unwrap();
+
+        assert!(read_message(&mut server, &SizeLimits::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_truncated_frame() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let frame = encode_frame(&ping(1)).unwrap();
+        client.write_all(&frame[..frame.len() - 1]).await.unwrap();
+        drop(client);
+
+        assert!(read_message(&mut server, &SizeLimits::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_oversized_transaction() {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let limits = SizeLimits {
+            max_transaction_size: 1024,
+            ..SizeLimits::default()
+        };
+
+        let tx = proto::Transaction { data: vec![1; 2048], ..Default::default() };
+        let message = proto::Message {
+            payload: Some(proto::message::Payload::Transaction(tx)),
+        };
+        write_message(&mut client, &message).await.unwrap();
+        write_message(&mut client, &message).await.unwrap();
+
+        assert!(read_message(&mut server, &limits).await.is_err());
+        assert_eq!(read_message(&mut server, &SizeLimits::default()).await.unwrap(), Some(message));
+    }
+}
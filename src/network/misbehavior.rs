@@ -0,0 +1,107 @@
+use std::time::{Duration, Instant};
+
+/// Misbehavior score at which a peer is disconnected and banned
+pub const BAN_THRESHOLD: u32 = 100;
+/// Default duration of a ban
+pub const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+/// Default number of messages a peer can send per second, bursts of up to twice as many messages are tolerated
+pub const DEFAULT_MAX_MESSAGES_PER_SECOND: u32 = 500;
+
+/// Misbehavior is a violation of the protocol by a peer, each one adds a penalty to the score of the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// A block that can never be valid: bad proof of work, signature or transactions
+    InvalidBlock,
+    /// Headers that do not form a valid chain
+    InvalidHeaders,
+    /// A transaction with an invalid signature
+    InvalidTransaction,
+    /// A message that could not be understood: malformed hashes, too many items, missing fields
+    MalformedMessage,
+    /// A message sent above the rate limit
+    RateLimitExceeded,
+}
+
+impl Misbehavior {
+    /// Returns the penalty added to the score of the peer
+    pub fn penalty(&self) -> u32 {
+        match self {
+            Misbehavior::InvalidBlock => 100,
+            Misbehavior::InvalidHeaders => 50,
+            Misbehavior::InvalidTransaction => 10,
+            Misbehavior::MalformedMessage => 20,
+            Misbehavior::RateLimitExceeded => 5,
+        }
+    }
+}
+
+/// RateLimiter is a token bucket: every message takes a token, tokens are refilled at a constant rate
+/// up to the capacity of the bucket
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter allowing the given number of messages per second, with bursts of up to twice as many
+    pub fn new(per_second: u32) -> Self {
+        RateLimiter {
+            capacity: per_second as f64 * 2.0,
+            tokens: per_second as f64 * 2.0,
+            refill_per_second: per_second as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token, returns false if the bucket is empty
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_block_is_banned_immediately() {
+        assert!(Misbehavior::InvalidBlock.penalty() >= BAN_THRESHOLD);
+        assert!(Misbehavior::InvalidTransaction.penalty() < BAN_THRESHOLD);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(5);
+        let start = limiter.last_refill;
+
+        // Bursts of twice the rate are allowed
+        for _ in 0..10 {
+            assert!(limiter.try_acquire_at(start));
+        }
+        assert!(!limiter.try_acquire_at(start));
+
+        // Tokens are refilled over time, up to the capacity
+        assert!(limiter.try_acquire_at(start + Duration::from_millis(200)));
+        assert!(!limiter.try_acquire_at(start + Duration::from_millis(200)));
+        for _ in 0..10 {
+            assert!(limiter.try_acquire_at(start + Duration::from_secs(60)));
+        }
+        assert!(!limiter.try_acquire_at(start + Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,12 @@
+pub mod address_book;
+pub mod compact;
+pub mod gossip;
+pub mod handshake;
+pub mod memory;
+pub mod message;
+pub mod misbehavior;
+pub mod node;
+pub mod noise;
+pub mod sync;
+pub mod tcp;
+pub mod transport;
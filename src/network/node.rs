@@ -0,0 +1,1775 @@
+use crate::core::blockchain::{Blockchain, ChainEvent};
+use crate::core::mempool::MempoolEvent;
+use crate::crypto::keys::{PrivateKey, PublicKey};
+use crate::error::Result;
+use crate::network::address_book::{unix_now, AddressBook};
+use crate::network::compact::{self, PartialBlock};
+use crate::network::gossip::{self, GossipRequests, SeenHashes, GOSSIP_REQUEST_TIMEOUT, MAX_INVENTORY_SIZE, SEEN_HASHES_CAPACITY};
+use crate::network::handshake::{handshake_message, new_handshake, verify_handshake};
+use crate::network::message::pong;
+use crate::network::misbehavior::{Misbehavior, RateLimiter, BAN_THRESHOLD, DEFAULT_BAN_DURATION, DEFAULT_MAX_MESSAGES_PER_SECOND};
+use crate::network::sync::{self, SyncEngine, MAX_HEADERS_PER_MESSAGE};
+use crate::network::transport::{EventStream, PeerId, Transport, TransportEvent};
+use crate::proto;
+use crate::proto::message::Payload;
+use crate::proto::InventoryType;
+use crate::types::hash::Hash;
+use crate::utils::log::make_json_logger;
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+
+/// Default identifier of the network the node belongs to
+pub const DEFAULT_CHAIN_ID: &str = "marvin-devnet";
+/// Default time a peer has to complete the handshake before being disconnected
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval between two runs of the node housekeeping
+const TICK_INTERVAL: Duration = Duration::from_millis(500);
+/// Maximum number of hashes in the locator of a GetHeaders message
+const MAX_LOCATOR_SIZE: usize = 64;
+/// Default number of outbound connections the node tries to keep open
+pub const DEFAULT_TARGET_OUTBOUND: usize = 8;
+/// Maximum number of addresses in a single Addr message
+pub const MAX_ADDR_PER_MESSAGE: usize = 256;
+/// Interval between two saves of the address book
+const ADDRESS_BOOK_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Maximum number of compact blocks waiting for their missing transactions from a single peer
+const MAX_PARTIAL_BLOCKS_PER_PEER: usize = 4;
+/// Maximum number of compact blocks waiting for their missing transactions
+const MAX_PARTIAL_BLOCKS: usize = 64;
+/// Time a peer has to send the missing transactions of a compact block
+const PARTIAL_BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// NetworkConfig holds the peer-to-peer settings of a node
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    pub chain_id: String,
+    pub handshake_timeout: Duration,
+    /// Addresses of the nodes to connect to when the address book has no better candidates
+    pub bootnodes: Vec<String>,
+    /// Address advertised to peers, defaults to the local address of the transport
+    pub external_addr: Option<String>,
+    /// Number of outbound connections the node tries to keep open
+    pub target_outbound: usize,
+    /// File the address book is loaded from and saved to, the address book is only kept in memory if not set
+    pub address_book_path: Option<PathBuf>,
+    /// Duration of the ban of a peer reaching the misbehavior threshold
+    pub ban_duration: Duration,
+    /// Number of messages a peer can send per second
+    pub max_messages_per_second: u32,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            bootnodes: Vec::new(),
+            external_addr: None,
+            target_outbound: DEFAULT_TARGET_OUTBOUND,
+            address_book_path: None,
+            ban_duration: DEFAULT_BAN_DURATION,
+            max_messages_per_second: DEFAULT_MAX_MESSAGES_PER_SECOND,
+        }
+    }
+}
+
+/// PeerInfo is what a peer announced about itself in its handshake
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    pub public_key: PublicKey,
+    pub protocol_version: u32,
+    pub best_height: u64,
+    /// Address the peer accepts connections on
+    pub listen_addr: String,
+}
+
+/// Peer is a connection tracked by the node
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub addr: String,
+    pub inbound: bool,
+    pub connected_at: Instant,
+    /// Set once the peer completed a valid handshake
+    pub info: Option<PeerInfo>,
+    /// Sum of the penalties of the protocol violations of the peer, the peer is banned at `BAN_THRESHOLD`
+    pub misbehavior: u32,
+    pub rate_limiter: RateLimiter,
+}
+
+impl Peer {
+    /// Check if the peer completed the handshake and can exchange protocol messages
+    pub fn is_ready(&self) -> bool {
+        self.info.is_some()
+    }
+}
+
+/// Peer table of a node, shared with the subsystems that need to inspect the connected peers
+pub type SharedPeers = Arc<Mutex<HashMap<PeerId, Peer>>>;
+/// Address book of a node, shared with the subsystems that need to inspect the known addresses
+pub type SharedAddressBook = Arc<Mutex<AddressBook>>;
+/// Result of an outbound connection attempt, sent back to the node by the task that dialed the address
+type DialResult = (String, Result<PeerId>);
+
+/// Node is the peer-to-peer core of a Marvin node. It consumes the events of a transport,
+/// performs the handshake with every new peer and dispatches the messages of the ready ones.
+/// New blocks and transactions are gossiped: they are announced by hash, and peers request the ones they miss.
+/// Announced blocks are requested as compact blocks and rebuilt from the mempool, only the missing transactions are downloaded.
+/// A node behind its peers catches up with a headers-first download, see `SyncEngine`.
+pub struct Node<T: Transport> {
+    config: NetworkConfig,
+    transport: Arc<T>,
+    blockchain: Arc<Mutex<Blockchain>>,
+    private_key: PrivateKey,
+    peers: SharedPeers,
+    /// Hashes of the blocks and transactions already announced, requested or received by the node
+    seen: SeenHashes,
+    /// Items requested from peers and not delivered yet
+    requests: GossipRequests,
+    sync: SyncEngine,
+    /// Compact blocks waiting for their missing transactions, with the peer they were requested from and when
+    partial_blocks: HashMap<Hash, (PeerId, Instant, PartialBlock)>,
+    address_book: SharedAddressBook,
+    /// Peers the addresses were requested from and did not answer yet
+    addr_requested: HashSet<PeerId>,
+    /// Addresses being dialed
+    dialing: HashSet<String>,
+    last_saved: Instant,
+    logger: slog::Logger,
+}
+
+impl<T: Transport> Node<T> {
+    pub fn new(config: NetworkConfig, transport: Arc<T>, blockchain: Arc<Mutex<Blockchain>>, private_key: PrivateKey) -> Self {
+        let logger = make_json_logger();
+
+        let mut address_book = match &config.address_book_path {
+            Some(path) => AddressBook::load(path).unwrap_or_else(|e| {
+                warn!(logger, "Failed to load the address book"; "path" => path.display().to_string(), "error" => e.to_string());
+                AddressBook::new()
+            }),
+            None => AddressBook::new(),
+        };
+        for bootnode in &config.bootnodes {
+            address_book.add(bootnode);
+        }
+
+        Node {
+            config,
+            transport,
+            blockchain,
+            private_key,
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            seen: SeenHashes::new(SEEN_HASHES_CAPACITY),
+            requests: GossipRequests::new(GOSSIP_REQUEST_TIMEOUT),
+            sync: SyncEngine::new(),
+            partial_blocks: HashMap::new(),
+            address_book: Arc::new(Mutex::new(address_book)),
+            addr_requested: HashSet::new(),
+            dialing: HashSet::new(),
+            last_saved: Instant::now(),
+            logger,
+        }
+    }
+
+    /// Returns the peer table of the node
+    pub fn peers(&self) -> SharedPeers {
+        self.peers.clone()
+    }
+
+    /// Returns the address book of the node
+    pub fn address_book(&self) -> SharedAddressBook {
+        self.address_book.clone()
+    }
+
+    /// Run the node until the event stream of the transport is closed
+    pub async fn run(self, events: EventStream) {
+        self.run_until(events, std::future::pending()).await
+    }
+
+    /// Run the node until the event stream of the transport is closed or the shutdown future completes
+    pub async fn run_until(mut self, mut events: EventStream, shutdown: impl std::future::Future<Output = ()>) {
+        tokio::pin!(shutdown);
+        let mut tick = tokio::time::interval(TICK_INTERVAL);
+        let (mut chain_events, mut mempool_events) = {
+            let blockchain = self.blockchain.lock().unwrap();
+            (blockchain.subscribe(), blockchain.mempool.subscribe())
+        };
+        let (dials, mut dial_results) = mpsc::channel::<DialResult>(self.config.target_outbound.max(1));
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                event = events.recv() => match event {
+                    Some(event) => self.handle_event(event),
+                    None => break,
+                },
+                // Lagging behind only means some items are not announced, peers still get them from others
+                Ok(event) = chain_events.recv() => self.on_chain_event(event),
+                Ok(event) = mempool_events.recv() => self.on_mempool_event(event),
+                Some((addr, result)) = dial_results.recv() => self.on_dialed(addr, result),
+                _ = tick.tick() => self.housekeeping(&dials),
+            }
+        }
+
+        self.save_address_book();
+    }
+
+    fn handle_event(&mut self, event: TransportEvent) {
+        match event {
+            TransportEvent::Connected { peer, addr, inbound } => self.on_connected(peer, addr, inbound),
+            TransportEvent::Message { peer, message } => self.on_message(peer, message),
+            TransportEvent::Disconnected { peer } => {
+                if self.peers.lock().unwrap().remove(&peer).is_some() {
+                    info!(self.logger, "Peer disconnected"; "peer" => peer);
+                }
+                self.peer_disconnected(peer);
+            }
+        }
+    }
+
+    fn on_connected(&mut self, peer: PeerId, addr: String, inbound: bool) {
+        if self.address_book.lock().unwrap().is_banned(&addr, unix_now()) {
+            info!(self.logger, "Rejected connection from banned peer"; "peer" => peer, "addr" => &addr);
+            return self.transport.disconnect(peer);
+        }
+
+        info!(self.logger, "Peer connected"; "peer" => peer, "addr" => &addr, "inbound" => inbound);
+        self.peers.lock().unwrap().insert(peer, Peer {
+            addr,
+            inbound,
+            connected_at: Instant::now(),
+            info: None,
+            misbehavior: 0,
+            rate_limiter: RateLimiter::new(self.config.max_messages_per_second),
+        });
+
+        let handshake = match self.local_handshake() {
+            Ok(handshake) => handshake,
+            Err(e) => return self.disconnect_peer(peer, &e.to_string()),
+        };
+        self.send(peer, handshake_message(handshake));
+    }
+
+    fn on_message(&mut self, peer: PeerId, message: proto::Message) {
+        let (ready, allowed) = match self.peers.lock().unwrap().get_mut(&peer) {
+            Some(state) => (state.is_ready(), state.rate_limiter.try_acquire()),
+            // Messages still in flight from a peer that was already disconnected
+            None => return,
+        };
+        if !allowed {
+            return self.misbehaving(peer, Misbehavior::RateLimitExceeded, "Message rate limit exceeded");
+        }
+
+        match (ready, message.payload) {
+            (false, Some(Payload::Handshake(handshake))) => {
+                self.on_handshake(peer, handshake);
+                self.start_sync();
+            }
+            (false, _) => self.disconnect_peer(peer, "Expected a handshake"),
+            (true, Some(Payload::Handshake(_))) => self.disconnect_peer(peer, "Unexpected handshake"),
+            (true, Some(Payload::Ping(ping))) => {
+                self.send(peer, pong(ping.nonce));
+            }
+            (true, Some(Payload::Pong(_))) => {}
+            (true, Some(Payload::Inventory(inventory))) => {
+                self.on_inventory(peer, inventory.r#type(), &inventory.hashes)
+            }
+            (true, Some(Payload::GetData(request))) => self.on_get_data(peer, request.r#type(), &request.hashes),
+            (true, Some(Payload::NotFound(not_found))) => self.on_not_found(peer, &not_found.hashes),
+            (true, Some(Payload::Block(block))) => self.on_block(peer, block),
+            (true, Some(Payload::Transaction(tx))) => self.on_transaction(peer, tx),
+            (true, Some(Payload::GetHeaders(request))) => self.on_get_headers(peer, request),
+            (true, Some(Payload::Headers(headers))) => self.on_headers(peer, headers.headers),
+            (true, Some(Payload::GetAddr(_))) => self.on_get_addr(peer),
+            (true, Some(Payload::Addr(addr))) => self.on_addr(peer, addr.addrs),
+            (true, Some(Payload::CompactBlock(compact))) => self.on_compact_block(peer, compact),
+            (true, Some(Payload::GetBlockTransactions(request))) => self.on_get_block_transactions(peer, request),
+            (true, Some(Payload::BlockTransactions(response))) => self.on_block_transactions(peer, response),
+            (true, None) => self.misbehaving(peer, Misbehavior::MalformedMessage, "Empty message"),
+        }
+    }
+
+    /// Request the announced items the node has not seen yet, blocks are requested as compact blocks.
+    /// Items already requested from another peer are requested from this one if the other peer does not deliver them.
+    fn on_inventory(&mut self, peer: PeerId, kind: InventoryType, hashes: &[Vec<u8>]) {
+        if kind == InventoryType::CompactBlock {
+            return self.misbehaving(peer, Misbehavior::MalformedMessage, "Compact blocks can not be announced");
+        }
+        let hashes = match self.parse_inventory(peer, kind, hashes) {
+            Some(hashes) => hashes,
+            None => return,
+        };
+
+        let wanted: Vec<Hash> = {
+            let blockchain = self.blockchain.lock().unwrap();
+            hashes
+                .into_iter()
+                .filter(|hash| match kind {
+                    InventoryType::Block => !blockchain.contains_block(hash),
+                    _ => !blockchain.mempool.has(hash),
+                })
+                .collect()
+        };
+        let wanted: Vec<Hash> = wanted
+            .into_iter()
+            .filter(|hash| !self.requests.add_announcer(hash, peer) && self.seen.insert(*hash))
+            .collect();
+        if wanted.is_empty() {
+            return;
+        }
+
+        let kind = match kind {
+            InventoryType::Block => InventoryType::CompactBlock,
+            kind => kind,
+        };
+        self.request(peer, kind, &wanted);
+    }
+
+    /// Request items from a peer, they are requested from another peer if they are not delivered in time
+    fn request(&mut self, peer: PeerId, kind: InventoryType, hashes: &[Hash]) {
+        let now = Instant::now();
+        for hash in hashes {
+            self.requests.insert(*hash, kind, peer, now);
+        }
+        self.send(peer, gossip::get_data(kind, hashes));
+    }
+
+    /// Request items again from the next peers that announced them. The items no other peer announced are
+    /// forgotten, so they are requested again when they are announced.
+    fn retry_requests(&mut self, hashes: Vec<Hash>) {
+        let now = Instant::now();
+        let mut batches: HashMap<(PeerId, InventoryType), Vec<Hash>> = HashMap::new();
+        for hash in hashes {
+            match self.requests.retry(&hash, now) {
+                Some((peer, kind)) => batches.entry((peer, kind)).or_default().push(hash),
+                None => {
+                    self.seen.remove(&hash);
+                }
+            }
+        }
+
+        for ((peer, kind), hashes) in batches {
+            for chunk in hashes.chunks(MAX_INVENTORY_SIZE) {
+                if !self.send(peer, gossip::get_data(kind, chunk)) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Send the requested items to the peer, and a NotFound message with the ones the node does not have
+    fn on_get_data(&mut self, peer: PeerId, kind: InventoryType, hashes: &[Vec<u8>]) {
+        let hashes = match self.parse_inventory(peer, kind, hashes) {
+            Some(hashes) => hashes,
+            None => return,
+        };
+
+        let mut messages = Vec::new();
+        let mut missing = Vec::new();
+        {
+            let blockchain = self.blockchain.lock().unwrap();
+            for hash in hashes {
+                let message = match kind {
+                    InventoryType::Block => blockchain.get_block(&hash).ok().map(gossip::block_message),
+                    InventoryType::CompactBlock => blockchain
+                        .get_block(&hash)
+                        .ok()
+                        .map(|block| compact::compact_block_message(compact::compact_block(&block))),
+                    _ => blockchain.mempool.get(&hash).cloned().map(gossip::transaction_message),
+                };
+                match message {
+                    Some(message) => messages.push(message),
+                    None => missing.push(hash),
+                }
+            }
+        }
+        if !missing.is_empty() {
+            messages.push(gossip::not_found(kind, &missing));
+        }
+
+        for message in messages {
+            if !self.send(peer, message) {
+                return;
+            }
+        }
+    }
+
+    /// Request the items a peer could not deliver from another peer, or forget them so they can be requested again
+    fn on_not_found(&mut self, peer: PeerId, hashes: &[Vec<u8>]) {
+        let hashes = match gossip::parse_hashes(hashes) {
+            Ok(hashes) => hashes,
+            Err(_) => return self.misbehaving(peer, Misbehavior::MalformedMessage, "Malformed hash in not found message"),
+        };
+
+        let mut retried = Vec::new();
+        for hash in hashes {
+            self.sync.not_found(&hash);
+            if self.requests.is_requested_from(&hash, peer) {
+                retried.push(hash);
+            } else if !self.requests.contains(&hash) {
+                self.seen.remove(&hash);
+            }
+        }
+        self.retry_requests(retried);
+    }
+
+    /// Connect a block received from a peer. Connected blocks are announced to the other peers.
+    /// Blocks downloaded by the sync are connected in chain order once the previous ones are downloaded.
+    fn on_block(&mut self, peer: PeerId, block: proto::Block) {
+        if block.header.is_none() {
+            return self.misbehaving(peer, Misbehavior::MalformedMessage, "Block header is missing");
+        }
+        let hash = crate::types::block::block_hash(&block);
+        let height = block.header.as_ref().unwrap().height;
+        self.seen.insert(hash);
+        self.requests.remove(&hash);
+
+        let checked = self.blockchain.lock().unwrap().check_block(&block);
+        if let Err(e) = checked {
+            return self.misbehaving(peer, Misbehavior::InvalidBlock, &e.to_string());
+        }
+
+        if self.sync.is_pending(&hash) {
+            self.sync.block_received(peer, block);
+            self.connect_synced_blocks();
+            return self.request_blocks();
+        }
+
+        let (result, tip) = {
+            let mut blockchain = self.blockchain.lock().unwrap();
+            if blockchain.contains_block(&hash) {
+                return;
+            }
+            (blockchain.add_block(block), blockchain.height() as u64)
+        };
+
+        match result {
+            Ok(()) => {}
+            // The peer is ahead of the node, catch up with it
+            Err(_) if height > tip + 1 => {
+                self.update_best_height(peer, height);
+                self.start_sync();
+            }
+            Err(e) => {
+                warn!(self.logger, "Rejected block from peer"; "peer" => peer, "hash" => hash.to_string(), "error" => e.to_string());
+            }
+        }
+    }
+
+    /// Rebuild a compact block from the mempool, requesting the transactions the node does not have from the peer.
+    /// Only compact blocks extending the tip with a valid header wait for their missing transactions,
+    /// the full block is requested instead for the other ones or when too many compact blocks are waiting.
+    fn on_compact_block(&mut self, peer: PeerId, compact: proto::CompactBlock) {
+        if compact.header.is_none() {
+            return self.misbehaving(peer, Misbehavior::MalformedMessage, "Compact block header is missing");
+        }
+        let checked = compact::check_header(&compact, self.blockchain.lock().unwrap().difficulty);
+        if let Err(e) = checked {
+            return self.misbehaving(peer, Misbehavior::InvalidBlock, &e.to_string());
+        }
+
+        let partial = {
+            let blockchain = self.blockchain.lock().unwrap();
+            PartialBlock::new(compact, &blockchain.mempool)
+        };
+        let partial = match partial {
+            Ok(partial) => partial,
+            Err(e) => return self.misbehaving(peer, Misbehavior::MalformedMessage, &e.to_string()),
+        };
+        self.seen.insert(partial.hash);
+        self.requests.remove(&partial.hash);
+        if self.partial_blocks.contains_key(&partial.hash) || self.blockchain.lock().unwrap().contains_block(&partial.hash) {
+            return;
+        }
+
+        if partial.is_complete() {
+            return self.on_partial_block_complete(peer, partial);
+        }
+
+        // Validate the header against the tip, `None` if the block does not extend the tip
+        let validated = {
+            let blockchain = self.blockchain.lock().unwrap();
+            let tip = blockchain.headers.last().unwrap();
+            let header = partial.header();
+            (header.prev_block_hash == crate::types::block::hash_header(tip)).then(|| blockchain.validate_header(tip, header))
+        };
+        let pending = self.partial_blocks.values().filter(|(from, _, _)| *from == peer).count();
+        match validated {
+            Some(Ok(())) if pending < MAX_PARTIAL_BLOCKS_PER_PEER && self.partial_blocks.len() < MAX_PARTIAL_BLOCKS => {}
+            Some(Err(e)) => {
+                warn!(self.logger, "Rejected compact block from peer"; "peer" => peer, "hash" => partial.hash.to_string(), "error" => e.to_string());
+                return;
+            }
+            _ => {
+                self.request(peer, InventoryType::Block, &[partial.hash]);
+                return;
+            }
+        }
+
+        let message = compact::get_block_transactions(&partial.hash, partial.missing());
+        self.partial_blocks.insert(partial.hash, (peer, Instant::now(), partial));
+        self.send(peer, message);
+    }
+
+    /// Send the requested transactions of a block
+    fn on_get_block_transactions(&mut self, peer: PeerId, request: proto::GetBlockTransactions) {
+        let hash = match Hash::from_bytes(&request.block_hash) {
+            Ok(hash) => hash,
+            Err(_) => return self.misbehaving(peer, Misbehavior::MalformedMessage, "Malformed block hash"),
+        };
+
+        let block = self.blockchain.lock().unwrap().get_block(&hash);
+        let message = match block {
+            Ok(block) => match compact::select_transactions(&block, &request.indexes) {
+                Ok(transactions) => compact::block_transactions(&hash, transactions),
+                Err(e) => return self.misbehaving(peer, Misbehavior::MalformedMessage, &e.to_string()),
+            },
+            Err(_) => gossip::not_found(InventoryType::Block, &[hash]),
+        };
+
+        self.send(peer, message);
+    }
+
+    /// Complete a compact block with the transactions sent by the peer
+    fn on_block_transactions(&mut self, peer: PeerId, response: proto::BlockTransactions) {
+        let hash = match Hash::from_bytes(&response.block_hash) {
+            Ok(hash) => hash,
+            Err(_) => return self.misbehaving(peer, Misbehavior::MalformedMessage, "Malformed block hash"),
+        };
+
+        let mut partial = match self.partial_blocks.remove(&hash) {
+            Some((from, _, partial)) if from == peer => partial,
+            Some(pending) => {
+                self.partial_blocks.insert(hash, pending);
+                return self.misbehaving(peer, Misbehavior::MalformedMessage, "Unrequested block transactions");
+            }
+            None => return self.misbehaving(peer, Misbehavior::MalformedMessage, "Unrequested block transactions"),
+        };
+
+        if let Err(e) = partial.fill(response.transactions) {
+            self.seen.remove(&hash);
+            return self.misbehaving(peer, Misbehavior::MalformedMessage, &e.to_string());
+        }
+        self.on_partial_block_complete(peer, partial)
+    }
+
+    /// Connect a rebuilt compact block. If the transactions taken from the mempool do not match the
+    /// commitment of the header, a short id collided and the full block is requested instead.
+    fn on_partial_block_complete(&mut self, peer: PeerId, partial: PartialBlock) {
+        let hash = partial.hash;
+        let block = match partial.into_block() {
+            Some(block) => block,
+            None => return,
+        };
+
+        let header = block.header.as_ref().unwrap();
+        if header.tx_hash != crate::types::block::calculate_tx_hash(&block.transactions) {
+            self.request(peer, InventoryType::Block, &[hash]);
+            return;
+        }
+
+        self.on_block(peer, block)
+    }
+
+    /// Share the known addresses with the peer
+    fn on_get_addr(&mut self, peer: PeerId) {
+        let addrs = self.address_book.lock().unwrap().addresses_to_share(MAX_ADDR_PER_MESSAGE, unix_now());
+        let message = proto::Message {
+            payload: Some(Payload::Addr(proto::Addr { addrs })),
+        };
+
+        self.send(peer, message);
+    }
+
+    /// Add the addresses shared by a peer to the address book
+    fn on_addr(&mut self, peer: PeerId, addrs: Vec<String>) {
+        // Addresses that were not requested are ignored, a peer only answers once
+        if !self.addr_requested.remove(&peer) {
+            return;
+        }
+        if addrs.len() > MAX_ADDR_PER_MESSAGE {
+            return self.misbehaving(peer, Misbehavior::MalformedMessage, "Too many addresses");
+        }
+
+        let listen_addr = self.listen_addr();
+        let mut address_book = self.address_book.lock().unwrap();
+        let added = addrs.iter().filter(|addr| **addr != listen_addr && address_book.add(addr)).count();
+        drop(address_book);
+
+        if added > 0 {
+            info!(self.logger, "Addresses received"; "peer" => peer, "added" => added);
+        }
+    }
+
+    /// Send the headers following the locator of the peer
+    fn on_get_headers(&mut self, peer: PeerId, request: proto::GetHeaders) {
+        if request.locator.len() > MAX_LOCATOR_SIZE {
+            return self.misbehaving(peer, Misbehavior::MalformedMessage, "Too many hashes in locator");
+        }
+        let locator = match gossip::parse_hashes(&request.locator) {
+            Ok(locator) => locator,
+            Err(_) => return self.misbehaving(peer, Misbehavior::MalformedMessage, "Malformed hash in locator"),
+        };
+
+        let max = (request.max_headers as usize).min(MAX_HEADERS_PER_MESSAGE);
+        let headers = self.blockchain.lock().unwrap().headers_after(&locator, max);
+
+        self.send(peer, sync::headers_message(headers));
+    }
+
+    /// Validate the headers received from the sync peer, then request more headers or start downloading the blocks
+    fn on_headers(&mut self, peer: PeerId, headers: Vec<proto::Header>) {
+        // Headers that were not requested are ignored
+        if self.sync.header_peer() != Some(peer) {
+            return;
+        }
+        if headers.len() > MAX_HEADERS_PER_MESSAGE {
+            self.sync.reset();
+            return self.misbehaving(peer, Misbehavior::MalformedMessage, "Too many headers");
+        }
+        let count = headers.len();
+
+        let (result, locator, height) = {
+            let blockchain = self.blockchain.lock().unwrap();
+            let result = self.sync.add_headers(&blockchain, headers);
+            (result, self.sync.locator(&blockchain), blockchain.height() as u64)
+        };
+        if let Err(e) = result {
+            self.sync.reset();
+            return self.misbehaving(peer, Misbehavior::InvalidHeaders, &e.to_string());
+        }
+
+        let target = self.sync.target_height().unwrap_or(height).max(height);
+        info!(self.logger, "Headers received"; "peer" => peer, "count" => count, "target_height" => target);
+
+        if count == MAX_HEADERS_PER_MESSAGE && !self.sync.wants_headers() {
+            // The peer has more headers, they are requested again once the blocks of the pending headers are connected
+            self.sync.headers_done();
+            self.update_best_height(peer, target + 1);
+        } else if count == MAX_HEADERS_PER_MESSAGE {
+            self.sync.headers_requested(peer, Instant::now());
+            if !self.send(peer, sync::get_headers(&locator, MAX_HEADERS_PER_MESSAGE)) {
+                return;
+            }
+        } else {
+            // The peer sent all the headers it has
+            self.sync.headers_done();
+            self.update_best_height(peer, target);
+            if target <= height {
+                self.sync.reset();
+            }
+        }
+
+        self.request_blocks();
+    }
+
+    /// Start downloading the headers from the peer with the best chain, if it is ahead of the node
+    /// and the blocks of the pending headers leave room for more headers
+    fn start_sync(&mut self) {
+        if self.sync.header_peer().is_some() || !self.sync.wants_headers() {
+            return;
+        }
+
+        let (locator, height) = {
+            let blockchain = self.blockchain.lock().unwrap();
+            let height = blockchain.height() as u64;
+            (self.sync.locator(&blockchain), self.sync.target_height().unwrap_or(height).max(height))
+        };
+
+        let best = self
+            .ready_peers()
+            .into_iter()
+            .filter(|(_, best_height)| *best_height > height)
+            .max_by_key(|(peer, best_height)| (*best_height, std::cmp::Reverse(*peer)));
+        let peer = match best {
+            Some((peer, best_height)) => {
+                info!(self.logger, "Starting sync"; "peer" => peer, "height" => height, "best_height" => best_height);
+                peer
+            }
+            None => return,
+        };
+
+        self.sync.headers_requested(peer, Instant::now());
+        self.send(peer, sync::get_headers(&locator, MAX_HEADERS_PER_MESSAGE));
+    }
+
+    /// Request the blocks of the downloaded headers from the peers that have them
+    fn request_blocks(&mut self) {
+        let requests = self.sync.schedule(&self.ready_peers(), Instant::now());
+
+        for (peer, hashes) in requests {
+            self.send(peer, gossip::get_data(InventoryType::Block, &hashes));
+        }
+    }
+
+    /// Connect the downloaded blocks that follow the current chain
+    fn connect_synced_blocks(&mut self) {
+        let result = {
+            let mut blockchain = self.blockchain.lock().unwrap();
+            self.sync.connect_blocks(&mut blockchain)
+        };
+
+        if let Err((peer, e)) = result {
+            self.misbehaving(peer, Misbehavior::InvalidBlock, &e.to_string());
+        }
+    }
+
+    /// Returns the ready peers along with their best height, ordered by peer id
+    fn ready_peers(&self) -> Vec<(PeerId, u64)> {
+        let mut ready: Vec<(PeerId, u64)> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(peer, state)| state.info.as_ref().map(|info| (*peer, info.best_height)))
+            .collect();
+        ready.sort();
+
+        ready
+    }
+
+    /// Update the best height a peer is known to have
+    fn update_best_height(&mut self, peer: PeerId, best_height: u64) {
+        if let Some(info) = self.peers.lock().unwrap().get_mut(&peer).and_then(|state| state.info.as_mut()) {
+            info.best_height = best_height;
+        }
+    }
+
+    /// Add a transaction received from a peer to the mempool. Added transactions are announced to the other peers.
+    fn on_transaction(&mut self, peer: PeerId, tx: proto::Transaction) {
+        let hash = crate::types::transaction::transaction_hash(&tx);
+        self.seen.insert(hash);
+        self.requests.remove(&hash);
+
+        if let Err(e) = crate::types::transaction::verify_transaction(&mut tx.clone()) {
+            return self.misbehaving(peer, Misbehavior::InvalidTransaction, &e.to_string());
+        }
+
+        let result = {
+            let mut blockchain = self.blockchain.lock().unwrap();
+            if blockchain.mempool.has(&hash) {
+                return;
+            }
+            blockchain.add_transaction(&tx)
+        };
+
+        if let Err(e) = result {
+            warn!(self.logger, "Rejected transaction from peer"; "peer" => peer, "hash" => hash.to_string(), "error" => e.to_string());
+        }
+    }
+
+    /// Parse the hashes of an inventory or get data message, disconnecting the peer if the message is malformed
+    fn parse_inventory(&mut self, peer: PeerId, kind: InventoryType, hashes: &[Vec<u8>]) -> Option<Vec<Hash>> {
+        if kind == InventoryType::Unspecified {
+            self.misbehaving(peer, Misbehavior::MalformedMessage, "Unknown inventory type");
+            return None;
+        }
+        if hashes.len() > MAX_INVENTORY_SIZE {
+            self.misbehaving(peer, Misbehavior::MalformedMessage, "Too many hashes in inventory");
+            return None;
+        }
+
+        match gossip::parse_hashes(hashes) {
+            Ok(hashes) => Some(hashes),
+            Err(_) => {
+                self.misbehaving(peer, Misbehavior::MalformedMessage, "Malformed hash in inventory");
+                None
+            }
+        }
+    }
+
+    /// Announce the blocks connected to the local chain
+    fn on_chain_event(&mut self, event: ChainEvent) {
+        if let ChainEvent::BlockConnected { hash, .. } = event {
+            self.seen.insert(hash);
+            self.broadcast(gossip::inventory(InventoryType::Block, &[hash]));
+        }
+    }
+
+    /// Announce the transactions added to the local mempool
+    fn on_mempool_event(&mut self, event: MempoolEvent) {
+        let hash = match event {
+            MempoolEvent::Added { hash, .. } => hash,
+            MempoolEvent::Replaced { new, .. } => new,
+            _ => return,
+        };
+
+        self.seen.insert(hash);
+        self.broadcast(gossip::inventory(InventoryType::Transaction, &[hash]));
+    }
+
+    /// Send a message to all the peers that completed the handshake
+    fn broadcast(&mut self, message: proto::Message) {
+        for (peer, _) in self.ready_peers() {
+            self.send(peer, message.clone());
+        }
+    }
+
+    fn on_handshake(&mut self, peer: PeerId, handshake: proto::Handshake) {
+        let (genesis_hash, difficulty) = {
+            let blockchain = self.blockchain.lock().unwrap();
+            (blockchain.genesis_hash(), blockchain.difficulty)
+        };
+        let now = Blockchain::get_current_timestamp_as_unix_nano() as i64;
+
+        let public_key = match verify_handshake(&handshake, &self.config.chain_id, difficulty, &genesis_hash, now) {
+            Ok(public_key) => public_key,
+            Err(e) => return self.disconnect_peer(peer, &e.to_string()),
+        };
+
+        // The handshake must be signed by the identity the connection was authenticated with
+        if self.transport.remote_public_key(peer).is_some_and(|session_key| session_key != public_key) {
+            return self.disconnect_peer(peer, "Handshake public key does not match the connection identity");
+        }
+
+        if public_key == self.private_key.public_key() {
+            // Never dial this address again, it is one of the addresses of the node itself
+            if let Some(state) = self.peers.lock().unwrap().get(&peer).filter(|state| !state.inbound) {
+                self.address_book.lock().unwrap().entries.remove(&state.addr);
+            }
+            return self.disconnect_peer(peer, "Connected to self");
+        }
+
+        // Record what the peer announced, unless the node is already connected to the same node
+        let inbound = {
+            let mut peers = self.peers.lock().unwrap();
+            let duplicate = peers
+                .iter()
+                .any(|(id, state)| *id != peer && state.info.as_ref().map(|info| info.public_key) == Some(public_key));
+
+            match peers.get_mut(&peer) {
+                Some(_) if duplicate => None,
+                Some(state) => {
+                    state.info = Some(PeerInfo {
+                        public_key,
+                        protocol_version: handshake.protocol_version,
+                        best_height: handshake.best_height,
+                        listen_addr: handshake.listen_addr.clone(),
+                    });
+                    Some(state.inbound)
+                }
+                None => return,
+            }
+        };
+        let inbound = match inbound {
+            Some(inbound) => inbound,
+            None => return self.disconnect_peer(peer, "Already connected to the same node"),
+        };
+
+        info!(self.logger, "Peer handshake completed";
+            "peer" => peer,
+            "public_key" => public_key.to_string(),
+            "best_height" => handshake.best_height,
+            "listen_addr" => &handshake.listen_addr
+        );
+
+        // Inbound peers can be dialed by other nodes on their listen address,
+        // outbound peers are asked for the addresses they know
+        if inbound {
+            self.address_book.lock().unwrap().add(&handshake.listen_addr);
+        } else {
+            let message = proto::Message {
+                payload: Some(Payload::GetAddr(proto::GetAddr {})),
+            };
+            self.addr_requested.insert(peer);
+            self.send(peer, message);
+        }
+    }
+
+    /// Record the result of an outbound connection attempt in the address book
+    fn on_dialed(&mut self, addr: String, result: Result<PeerId>) {
+        self.dialing.remove(&addr);
+
+        let now = unix_now();
+        match result {
+            Ok(_) => {
+                self.address_book.lock().unwrap().mark_seen(&addr, now);
+                self.save_address_book();
+            }
+            Err(e) => {
+                warn!(self.logger, "Failed to connect to peer"; "addr" => &addr, "error" => e.to_string());
+                self.address_book.lock().unwrap().mark_failed(&addr, now);
+            }
+        }
+    }
+
+    /// Dial addresses from the address book until the node has the target number of outbound connections.
+    /// The connections are opened in the background and their result is sent back to the node.
+    fn connect_outbound(&mut self, dials: &mpsc::Sender<DialResult>) {
+        let (outbound, mut exclude) = {
+            let peers = self.peers.lock().unwrap();
+            let outbound = peers.values().filter(|state| !state.inbound).count();
+            let mut exclude: HashSet<String> = peers.values().map(|state| state.addr.clone()).collect();
+            exclude.extend(peers.values().filter_map(|state| state.info.as_ref().map(|info| info.listen_addr.clone())));
+            (outbound, exclude)
+        };
+
+        let missing = self.config.target_outbound.saturating_sub(outbound + self.dialing.len());
+        if missing == 0 {
+            return;
+        }
+        exclude.extend(self.dialing.iter().cloned());
+        exclude.insert(self.listen_addr());
+
+        let now = unix_now();
+        let addrs = {
+            let mut address_book = self.address_book.lock().unwrap();
+            let addrs = address_book.select_outbound(&exclude, missing, now);
+            addrs.iter().for_each(|addr| address_book.mark_attempt(addr, now));
+            addrs
+        };
+
+        for addr in addrs {
+            self.dialing.insert(addr.clone());
+
+            let transport = self.transport.clone();
+            let dials = dials.clone();
+            tokio::spawn(async move {
+                let result = transport.dial(&addr).await;
+                let _ = dials.send((addr, result)).await;
+            });
+        }
+    }
+
+    /// Save the address book, if it has a file
+    fn save_address_book(&mut self) {
+        self.last_saved = Instant::now();
+        self.address_book.lock().unwrap().remove_expired_bans(unix_now());
+
+        if let Some(path) = &self.config.address_book_path {
+            if let Err(e) = self.address_book.lock().unwrap().save(path) {
+                warn!(self.logger, "Failed to save the address book"; "path" => path.display().to_string(), "error" => e.to_string());
+            }
+        }
+    }
+
+    /// Returns the address advertised to peers
+    fn listen_addr(&self) -> String {
+        self.config.external_addr.clone().unwrap_or_else(|| self.transport.local_addr())
+    }
+
+    /// Disconnect the peers that did not complete the handshake or did not answer a sync request in time,
+    /// request the gossiped items that were not delivered in time from other peers,
+    /// open new outbound connections and resume the sync if the node is behind its peers
+    fn housekeeping(&mut self, dials: &mpsc::Sender<DialResult>) {
+        let timed_out: Vec<PeerId> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, state)| !state.is_ready() && state.connected_at.elapsed() > self.config.handshake_timeout)
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        for peer in timed_out {
+            self.disconnect_peer(peer, "Handshake timed out");
+        }
+
+        for peer in self.sync.expire(Instant::now()) {
+            self.disconnect_peer(peer, "Sync request timed out");
+        }
+        for peer in self.expire_partial_blocks(Instant::now()) {
+            self.disconnect_peer(peer, "Block transactions request timed out");
+        }
+        let expired = self.requests.expired(Instant::now());
+        self.retry_requests(expired);
+        self.connect_outbound(dials);
+        self.start_sync();
+        self.request_blocks();
+
+        if self.last_saved.elapsed() > ADDRESS_BOOK_SAVE_INTERVAL {
+            self.save_address_book();
+        }
+    }
+
+    /// Forget the compact blocks whose missing transactions were not received in time.
+    /// Returns the peers that did not answer, the blocks can be requested again from other peers.
+    fn expire_partial_blocks(&mut self, now: Instant) -> Vec<PeerId> {
+        let expired: Vec<(Hash, PeerId)> = self
+            .partial_blocks
+            .iter()
+            .filter(|(_, (_, requested_at, _))| now.duration_since(*requested_at) > PARTIAL_BLOCK_TIMEOUT)
+            .map(|(hash, (peer, _, _))| (*hash, *peer))
+            .collect();
+
+        let mut stalled = Vec::new();
+        for (hash, peer) in expired {
+            self.partial_blocks.remove(&hash);
+            self.seen.remove(&hash);
+            if !stalled.contains(&peer) {
+                stalled.push(peer);
+            }
+        }
+
+        stalled
+    }
+
+    /// Create the handshake of the node for the current chain tip
+    fn local_handshake(&mut self) -> Result<proto::Handshake> {
+        let (genesis_hash, difficulty, best_height) = {
+            let blockchain = self.blockchain.lock().unwrap();
+            (blockchain.genesis_hash(), blockchain.difficulty, blockchain.height() as u64)
+        };
+        let now = Blockchain::get_current_timestamp_as_unix_nano() as i64;
+        let listen_addr = self.listen_addr();
+
+        new_handshake(&mut self.private_key, &self.config.chain_id, difficulty, &genesis_hash, best_height, &listen_addr, now)
+    }
+
+    /// Add the penalty of a protocol violation to the misbehavior score of a peer.
+    /// A peer reaching the ban threshold is disconnected and its host is banned.
+    fn misbehaving(&mut self, peer: PeerId, misbehavior: Misbehavior, reason: &str) {
+        let (addr, score) = match self.peers.lock().unwrap().get_mut(&peer) {
+            Some(state) => {
+                state.misbehavior += misbehavior.penalty();
+                (state.addr.clone(), state.misbehavior)
+            }
+            None => return,
+        };
+        warn!(self.logger, "Peer misbehaving";
+            "peer" => peer,
+            "misbehavior" => format!("{:?}", misbehavior),
+            "reason" => reason,
+            "score" => score
+        );
+
+        if score >= BAN_THRESHOLD {
+            let until = unix_now() + self.config.ban_duration.as_secs();
+            self.address_book.lock().unwrap().ban(&addr, until);
+            self.save_address_book();
+            self.disconnect_peer(peer, "Banned for misbehavior");
+        }
+    }
+
+    /// Remove a peer from the peer table and close the connection
+    fn disconnect_peer(&mut self, peer: PeerId, reason: &str) {
+        if self.peers.lock().unwrap().remove(&peer).is_some() {
+            warn!(self.logger, "Disconnecting peer"; "peer" => peer, "reason" => reason);
+        }
+        self.peer_disconnected(peer);
+        self.transport.disconnect(peer);
+    }
+
+    /// Queue a message for a peer without waiting for it, so a slow peer can not hold back the node.
+    /// A peer whose queue is full is disconnected. Returns false if the message was not queued.
+    fn send(&mut self, peer: PeerId, message: proto::Message) -> bool {
+        match self.transport.try_send(peer, message) {
+            Ok(()) => true,
+            Err(e) => {
+                self.disconnect_peer(peer, &e.to_string());
+                false
+            }
+        }
+    }
+
+    /// Forget the pending requests of a disconnected peer, so they can be made to other peers
+    fn peer_disconnected(&mut self, peer: PeerId) {
+        self.sync.peer_disconnected(peer);
+        self.addr_requested.remove(&peer);
+
+        let abandoned: Vec<Hash> = self
+            .partial_blocks
+            .iter()
+            .filter(|(_, (from, _, _))| *from == peer)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in abandoned {
+            self.partial_blocks.remove(&hash);
+            self.seen.remove(&hash);
+        }
+
+        let requested = self.requests.peer_disconnected(peer);
+        self.retry_requests(requested);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::blockchain::DEFAULT_DIFFICULTY;
+    use crate::core::storage::MemoryStore;
+    use crate::crypto::keys;
+    use crate::network::memory::{MemoryNetwork, MemoryTransport};
+    use crate::network::message::write_message;
+    use crate::network::noise::{self, NoiseKeys, NoiseWriter};
+    use crate::network::tcp::TcpTransport;
+    use crate::types::limits::SizeLimits;
+
+    use tokio::io::AsyncReadExt;
+
+    use tokio::time::{sleep, timeout};
+
+    async fn start_node(config: NetworkConfig) -> (Arc<TcpTransport>, SharedPeers) {
+        let mut private_key = keys::generate_private_key();
+        let (transport, events) = TcpTransport::bind("127.0.0.1:0", &mut private_key, SizeLimits::default()).await.unwrap();
+        let transport = Arc::new(transport);
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(Box::new(MemoryStore::new()))));
+
+        let node = Node::new(config, transport.clone(), blockchain, private_key);
+        let peers = node.peers();
+        tokio::spawn(node.run(events));
+
+        (transport, peers)
+    }
+
+    struct MemoryNode {
+        transport: Arc<MemoryTransport>,
+        peers: SharedPeers,
+        blockchain: Arc<Mutex<Blockchain>>,
+        address_book: SharedAddressBook,
+    }
+
+    fn start_memory_node(network: &MemoryNetwork, addr: &str) -> MemoryNode {
+        start_memory_node_with_config(network, addr, NetworkConfig::default())
+    }
+
+    fn start_memory_node_with_config(network: &MemoryNetwork, addr: &str, config: NetworkConfig) -> MemoryNode {
+        let (transport, events) = network.bind(addr).unwrap();
+        let transport = Arc::new(transport);
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(Box::new(MemoryStore::new()))));
+
+        let node = Node::new(config, transport.clone(), blockchain.clone(), keys::generate_private_key());
+        let peers = node.peers();
+        let address_book = node.address_book();
+        tokio::spawn(node.run(events));
+
+        MemoryNode { transport, peers, blockchain, address_book }
+    }
+
+    /// Start the given number of nodes on a memory network, each one connected to the previous one
+    async fn start_memory_line(count: usize) -> Vec<MemoryNode> {
+        let network = MemoryNetwork::new();
+        let nodes: Vec<MemoryNode> = (0..count).map(|i| start_memory_node(&network, &format!("node-{}", i))).collect();
+
+        for pair in nodes.windows(2) {
+            pair[1].transport.dial(&pair[0].transport.local_addr()).await.unwrap();
+        }
+        wait_until(|| {
+            nodes.iter().enumerate().all(|(i, node)| ready_peers(&node.peers) == if i == 0 || i == count - 1 { 1 } else { 2 })
+        })
+        .await;
+
+        nodes
+    }
+
+    /// Create a signed block on top of the current tip of the blockchain
+    fn next_block(blockchain: &Blockchain, transactions: Vec<proto::Transaction>) -> proto::Block {
+        let mut block = proto::Block {
+            header: Some(proto::Header {
+                height: blockchain.height() as u64 + 1,
+                version: 1,
+                timestamp: Blockchain::get_current_timestamp_as_unix_nano() as i64,
+                prev_block_hash: crate::types::block::hash_header(blockchain.headers.last().unwrap()),
+                difficulty: blockchain.difficulty,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        for tx in transactions {
+            crate::types::block::add_transaction(&mut block, tx);
+        }
+        block.header.as_mut().unwrap().tx_hash = crate::types::block::calculate_tx_hash(&block.transactions);
+        crate::types::block::mine_header(block.header.as_mut().unwrap());
+        crate::types::block::sign_block(&mut keys::generate_private_key(), &mut block).unwrap();
+
+        block
+    }
+
+    fn signed_transaction(nonce: i64) -> proto::Transaction {
+        let mut tx = proto::Transaction {
+            to: keys::generate_private_key().public_key().to_bytes().to_vec(),
+            nonce,
+            ..Default::default()
+        };
+        crate::types::transaction::sign_transaction(&mut keys::generate_private_key(), &mut tx).unwrap();
+
+        tx
+    }
+
+    async fn wait_until<F: Fn() -> bool>(condition: F) {
+        timeout(Duration::from_secs(5), async {
+            while !condition() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("condition not met in time");
+    }
+
+    fn ready_peers(peers: &SharedPeers) -> usize {
+        peers.lock().unwrap().values().filter(|peer| peer.is_ready()).count()
+    }
+
+    /// Connect a transport to a node and complete the handshake without running a node on this end
+    async fn connect_raw_memory(network: &MemoryNetwork, node: &MemoryNode, addr: &str) -> (MemoryTransport, EventStream, PeerId) {
+        let (transport, events) = network.bind(addr).unwrap();
+        let peer = transport.dial(&node.transport.local_addr()).await.unwrap();
+        let ready = ready_peers(&node.peers);
+
+        let genesis_hash = node.blockchain.lock().unwrap().genesis_hash();
+        let now = Blockchain::get_current_timestamp_as_unix_nano() as i64;
+        let handshake = new_handshake(&mut keys::generate_private_key(), DEFAULT_CHAIN_ID, DEFAULT_DIFFICULTY, &genesis_hash, 0, addr, now).unwrap();
+        transport.try_send(peer, handshake_message(handshake)).unwrap();
+        wait_until(|| ready_peers(&node.peers) == ready + 1).await;
+
+        (transport, events, peer)
+    }
+
+    /// Wait for the next message of a raw connection that is not part of the handshake
+    async fn next_request(events: &mut EventStream) -> Payload {
+        loop {
+            match timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap() {
+                TransportEvent::Message { message, .. } => match message.payload.unwrap() {
+                    Payload::Handshake(_) | Payload::GetAddr(_) => continue,
+                    payload => return payload,
+                },
+                _ => continue,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let (transport_a, peers_a) = start_node(NetworkConfig::default()).await;
+        let (transport_b, peers_b) = start_node(NetworkConfig::default()).await;
+
+        transport_a.dial(&transport_b.local_addr()).await.unwrap();
+
+        wait_until(|| ready_peers(&peers_a) == 1 && ready_peers(&peers_b) == 1).await;
+        let info = peers_a.lock().unwrap().values().next().unwrap().info.clone().unwrap();
+        assert_eq!(info.best_height, 0);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_chain_id_mismatch() {
+        let (transport_a, peers_a) = start_node(NetworkConfig::default()).await;
+        let other_network = NetworkConfig {
+            chain_id: String::from("marvin-other"),
+            ..NetworkConfig::default()
+        };
+        let (transport_b, peers_b) = start_node(other_network).await;
+
+        transport_a.dial(&transport_b.local_addr()).await.unwrap();
+
+        wait_until(|| transport_a.peers().is_empty() && transport_b.peers().is_empty()).await;
+        assert_eq!(ready_peers(&peers_a), 0);
+        assert_eq!(ready_peers(&peers_b), 0);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_difficulty_mismatch() {
+        let network = MemoryNetwork::new();
+        let node_a = start_memory_node(&network, "node-a");
+        let node_b = start_memory_node(&network, "node-b");
+        node_b.blockchain.lock().unwrap().difficulty += 1;
+
+        // Nodes requiring different minimum difficulties would not accept the blocks of each other
+        node_a.transport.dial("node-b").await.unwrap();
+
+        wait_until(|| node_a.transport.peers().is_empty() && node_b.transport.peers().is_empty()).await;
+        assert_eq!(ready_peers(&node_a.peers), 0);
+        assert_eq!(ready_peers(&node_b.peers), 0);
+    }
+
+    /// Open an encrypted connection to a node without running a node on this end
+    async fn connect_raw(transport: &TcpTransport, private_key: &mut PrivateKey) -> NoiseWriter<tokio::net::TcpStream> {
+        let mut stream = tokio::net::TcpStream::connect(transport.local_addr()).await.unwrap();
+        let session = noise::handshake(&mut stream, &NoiseKeys::new(private_key).unwrap(), true).await.unwrap();
+
+        session.split((), stream).1
+    }
+
+    #[tokio::test]
+    async fn test_handshake_required_first() {
+        let (transport, peers) = start_node(NetworkConfig::default()).await;
+
+        let mut writer = connect_raw(&transport, &mut keys::generate_private_key()).await;
+        wait_until(|| transport.peers().len() == 1).await;
+
+        writer.write_message(&crate::network::message::ping(1)).await.unwrap();
+        wait_until(|| transport.peers().is_empty()).await;
+        assert!(peers.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_must_match_connection_identity() {
+        let (transport, peers) = start_node(NetworkConfig::default()).await;
+
+        let mut writer = connect_raw(&transport, &mut keys::generate_private_key()).await;
+        wait_until(|| transport.peers().len() == 1).await;
+
+        // A valid handshake, signed by another identity than the one of the encrypted connection
+        let genesis_hash = Blockchain::new(Box::new(MemoryStore::new())).genesis_hash();
+        let now = Blockchain::get_current_timestamp_as_unix_nano() as i64;
+        let handshake = new_handshake(&mut keys::generate_private_key(), DEFAULT_CHAIN_ID, DEFAULT_DIFFICULTY, &genesis_hash, 0, "", now).unwrap();
+        writer.write_message(&handshake_message(handshake)).await.unwrap();
+
+        wait_until(|| transport.peers().is_empty()).await;
+        assert_eq!(ready_peers(&peers), 0);
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_connection_is_rejected() {
+        let (transport, _peers) = start_node(NetworkConfig::default()).await;
+
+        let mut stream = tokio::net::TcpStream::connect(transport.local_addr()).await.unwrap();
+        write_message(&mut stream, &crate::network::message::ping(1)).await.unwrap();
+
+        // The node closes the connection without ever reporting it
+        let mut buffer = [0; 64];
+        let read = timeout(Duration::from_secs(5), stream.read(&mut buffer)).await.unwrap();
+        assert!(matches!(read, Ok(0) | Err(_)));
+        assert!(transport.peers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let config = NetworkConfig {
+            handshake_timeout: Duration::from_millis(100),
+            ..NetworkConfig::default()
+        };
+        let (transport, _peers) = start_node(config).await;
+
+        let _writer = connect_raw(&transport, &mut keys::generate_private_key()).await;
+        wait_until(|| transport.peers().len() == 1).await;
+        wait_until(|| transport.peers().is_empty()).await;
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_self() {
+        let (transport, peers) = start_node(NetworkConfig::default()).await;
+
+        transport.dial(&transport.local_addr()).await.unwrap();
+
+        wait_until(|| transport.peers().is_empty()).await;
+        assert!(peers.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_memory_network_mesh() {
+        let network = MemoryNetwork::new();
+        let nodes: Vec<_> = (0..4).map(|i| start_memory_node(&network, &format!("node-{}", i))).collect();
+
+        for (i, node) in nodes.iter().enumerate() {
+            for other in &nodes[i + 1..] {
+                node.transport.dial(&other.transport.local_addr()).await.unwrap();
+            }
+        }
+
+        wait_until(|| nodes.iter().all(|node| ready_peers(&node.peers) == 3)).await;
+    }
+
+    #[tokio::test]
+    async fn test_block_gossip() {
+        let nodes = start_memory_line(3).await;
+
+        for _ in 0..3 {
+            let mut blockchain = nodes[0].blockchain.lock().unwrap();
+            let block = next_block(&blockchain, vec![]);
+            blockchain.add_block(block).unwrap();
+        }
+
+        wait_until(|| nodes.iter().all(|node| node.blockchain.lock().unwrap().height() == 3)).await;
+        let tip = nodes[0].blockchain.lock().unwrap().headers.last().cloned();
+        assert_eq!(nodes[2].blockchain.lock().unwrap().headers.last().cloned(), tip);
+    }
+
+    #[tokio::test]
+    async fn test_transaction_gossip() {
+        let nodes = start_memory_line(3).await;
+
+        let tx = signed_transaction(0);
+        nodes[2].blockchain.lock().unwrap().add_transaction(&tx).unwrap();
+        wait_until(|| nodes.iter().all(|node| node.blockchain.lock().unwrap().mempool.contains(&tx))).await;
+
+        // Mining the transaction removes it from every mempool
+        {
+            let mut blockchain = nodes[0].blockchain.lock().unwrap();
+            let block = next_block(&blockchain, vec![tx.clone()]);
+            blockchain.add_block(block).unwrap();
+        }
+        wait_until(|| nodes.iter().all(|node| node.blockchain.lock().unwrap().mempool.is_empty())).await;
+    }
+
+    #[tokio::test]
+    async fn test_compact_block_with_missing_transactions() {
+        let nodes = start_memory_line(2).await;
+
+        let known = signed_transaction(0);
+        nodes[0].blockchain.lock().unwrap().add_transaction(&known).unwrap();
+        wait_until(|| nodes[1].blockchain.lock().unwrap().mempool.contains(&known)).await;
+
+        // The second transaction was never relayed, it is requested along with the compact block
+        let unknown = signed_transaction(0);
+        {
+            let mut blockchain = nodes[0].blockchain.lock().unwrap();
+            let block = next_block(&blockchain, vec![known.clone(), unknown]);
+            blockchain.add_block(block).unwrap();
+        }
+
+        wait_until(|| nodes[1].blockchain.lock().unwrap().height() == 1).await;
+        assert_eq!(tip(&nodes[1]), tip(&nodes[0]));
+        assert!(nodes[1].blockchain.lock().unwrap().mempool.is_empty());
+    }
+
+    fn extend_chain(node: &MemoryNode, count: usize) {
+        let mut blockchain = node.blockchain.lock().unwrap();
+        for _ in 0..count {
+            let block = next_block(&blockchain, vec![]);
+            blockchain.add_block(block).unwrap();
+        }
+    }
+
+    fn tip(node: &MemoryNode) -> Option<proto::Header> {
+        node.blockchain.lock().unwrap().headers.last().cloned()
+    }
+
+    #[tokio::test]
+    async fn test_initial_block_download() {
+        let network = MemoryNetwork::new();
+        let nodes: Vec<MemoryNode> = (0..3).map(|i| start_memory_node(&network, &format!("node-{}", i))).collect();
+        extend_chain(&nodes[0], 40);
+
+        nodes[1].transport.dial("node-0").await.unwrap();
+        wait_until(|| tip(&nodes[1]) == tip(&nodes[0])).await;
+
+        // The last node downloads the blocks from both synced nodes
+        nodes[2].transport.dial("node-0").await.unwrap();
+        nodes[2].transport.dial("node-1").await.unwrap();
+        wait_until(|| tip(&nodes[2]) == tip(&nodes[0])).await;
+    }
+
+    #[tokio::test]
+    async fn test_sync_switches_to_longer_fork() {
+        let network = MemoryNetwork::new();
+        let nodes: Vec<MemoryNode> = (0..2).map(|i| start_memory_node(&network, &format!("node-{}", i))).collect();
+        extend_chain(&nodes[0], 5);
+        extend_chain(&nodes[1], 3);
+
+        nodes[1].transport.dial("node-0").await.unwrap();
+        wait_until(|| tip(&nodes[1]) == tip(&nodes[0])).await;
+        assert_eq!(nodes[1].blockchain.lock().unwrap().height(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_peer_discovery() {
+        let network = MemoryNetwork::new();
+        let with_bootnode = |addr: &str| NetworkConfig {
+            bootnodes: vec![addr.to_string()],
+            ..NetworkConfig::default()
+        };
+
+        // The bootnode connects to the first node, so it knows the address is reachable and shares it
+        let first = start_memory_node(&network, "node-1:7000");
+        let bootnode = start_memory_node_with_config(&network, "node-0:7000", with_bootnode("node-1:7000"));
+        wait_until(|| bootnode.address_book.lock().unwrap().entries.get("node-1:7000").and_then(|entry| entry.last_seen).is_some()).await;
+
+        // The last node only knows the bootnode, it learns about the first node through the peer exchange
+        let last = start_memory_node_with_config(&network, "node-2:7000", with_bootnode("node-0:7000"));
+        wait_until(|| ready_peers(&last.peers) == 2 && ready_peers(&first.peers) == 2).await;
+
+        let address_book = last.address_book.lock().unwrap();
+        assert!(address_book.entries["node-0:7000"].last_seen.is_some());
+        assert!(address_book.entries["node-1:7000"].last_seen.is_some());
+        assert!(!address_book.entries.contains_key("node-2:7000"));
+    }
+
+    #[tokio::test]
+    async fn test_only_requested_addresses_are_added() {
+        let network = MemoryNetwork::new();
+        let server = start_memory_node(&network, "server:7000");
+        let (client, mut events) = network.bind("client:7000").unwrap();
+
+        // The node asks the peers it dials for the addresses they know
+        server.transport.dial("client:7000").await.unwrap();
+        let peer = match timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap() {
+            TransportEvent::Connected { peer, .. } => peer,
+            event => panic!("unexpected event {:?}", event),
+        };
+        let genesis_hash = server.blockchain.lock().unwrap().genesis_hash();
+        let now = Blockchain::get_current_timestamp_as_unix_nano() as i64;
+        let handshake = new_handshake(&mut keys::generate_private_key(), DEFAULT_CHAIN_ID, DEFAULT_DIFFICULTY, &genesis_hash, 0, "client:7000", now).unwrap();
+        client.try_send(peer, handshake_message(handshake)).unwrap();
+        loop {
+            if let TransportEvent::Message { message: proto::Message { payload: Some(Payload::GetAddr(_)) }, .. } = events.recv().await.unwrap() {
+                break;
+            }
+        }
+
+        let addr = |addrs: &[&str]| proto::Message {
+            payload: Some(Payload::Addr(proto::Addr { addrs: addrs.iter().map(|addr| addr.to_string()).collect() })),
+        };
+        client.try_send(peer, addr(&["10.0.0.1:7000", "not-an-address", "10.0.0.2:70000", ""])).unwrap();
+        client.try_send(peer, addr(&["10.0.0.3:7000"])).unwrap();
+
+        // The messages of a peer are handled in order, the addresses were processed once the ping is answered
+        client.try_send(peer, crate::network::message::ping(1)).unwrap();
+        assert!(matches!(next_request(&mut events).await, Payload::Pong(_)));
+
+        let address_book = server.address_book.lock().unwrap();
+        assert!(address_book.entries.contains_key("10.0.0.1:7000"));
+        assert!(!address_book.entries.contains_key("not-an-address"));
+        assert!(!address_book.entries.contains_key("10.0.0.2:70000"));
+        assert!(!address_book.entries.contains_key("10.0.0.3:7000"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_dial_is_recorded() {
+        let network = MemoryNetwork::new();
+        let config = NetworkConfig {
+            bootnodes: vec![String::from("nowhere:7000")],
+            ..NetworkConfig::default()
+        };
+        let node = start_memory_node_with_config(&network, "node-0", config);
+
+        wait_until(|| node.address_book.lock().unwrap().entries["nowhere:7000"].failures == 1).await;
+    }
+
+    #[tokio::test]
+    async fn test_address_book_is_saved() {
+        let path = std::env::temp_dir().join(format!("marvin-peers-{}.json", hex::encode(keys::new_entropy())));
+        let network = MemoryNetwork::new();
+        let _bootnode = start_memory_node(&network, "node-0:7000");
+
+        let config = NetworkConfig {
+            bootnodes: vec![String::from("node-0:7000")],
+            address_book_path: Some(path.clone()),
+            ..NetworkConfig::default()
+        };
+        let _node = start_memory_node_with_config(&network, "node-1:7000", config);
+
+        wait_until(|| {
+            AddressBook::load(&path).map(|book| book.entries.get("node-0:7000").and_then(|entry| entry.last_seen).is_some()) == Ok(true)
+        })
+        .await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_transaction_is_not_relayed() {
+        let nodes = start_memory_line(2).await;
+
+        let mut tx = signed_transaction(1);
+        tx.value += 1;
+        let peer = nodes[0].transport.peers()[0];
+        nodes[0].transport.send(peer, gossip::transaction_message(tx.clone())).await.unwrap();
+
+        let valid = signed_transaction(2);
+        nodes[0].transport.send(peer, gossip::transaction_message(valid.clone())).await.unwrap();
+        wait_until(|| nodes[1].blockchain.lock().unwrap().mempool.contains(&valid)).await;
+        assert!(!nodes[1].blockchain.lock().unwrap().mempool.contains(&tx));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_block_bans_peer() {
+        let nodes = start_memory_line(2).await;
+
+        let mut block = next_block(&nodes[0].blockchain.lock().unwrap(), vec![]);
+        block.header.as_mut().unwrap().timestamp += 1;
+        let peer = nodes[0].transport.peers()[0];
+        nodes[0].transport.send(peer, gossip::block_message(block)).await.unwrap();
+
+        wait_until(|| nodes[1].transport.peers().is_empty()).await;
+        assert!(nodes[1].address_book.lock().unwrap().is_banned("node-0", unix_now()));
+        assert_eq!(nodes[1].blockchain.lock().unwrap().height(), 0);
+
+        // The banned peer can not connect again
+        nodes[0].transport.dial("node-1").await.unwrap();
+        wait_until(|| nodes[0].transport.peers().is_empty() && nodes[1].transport.peers().is_empty()).await;
+        assert!(nodes[1].peers.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_transactions_ban_peer() {
+        let nodes = start_memory_line(2).await;
+        let peer = nodes[0].transport.peers()[0];
+
+        for nonce in 0..BAN_THRESHOLD / Misbehavior::InvalidTransaction.penalty() {
+            let mut tx = signed_transaction(nonce as i64);
+            tx.value += 1;
+            nodes[0].transport.send(peer, gossip::transaction_message(tx)).await.unwrap();
+        }
+
+        wait_until(|| nodes[1].transport.peers().is_empty()).await;
+        assert!(nodes[1].address_book.lock().unwrap().is_banned("node-0", unix_now()));
+        assert!(nodes[1].blockchain.lock().unwrap().mempool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_message_flood_bans_peer() {
+        let network = MemoryNetwork::new();
+        let config = NetworkConfig {
+            max_messages_per_second: 5,
+            ..NetworkConfig::default()
+        };
+        let server = start_memory_node_with_config(&network, "server", config);
+        let client = start_memory_node(&network, "client");
+
+        let peer = client.transport.dial("server").await.unwrap();
+        wait_until(|| ready_peers(&server.peers) == 1).await;
+
+        for nonce in 0..100 {
+            if client.transport.send(peer, crate::network::message::ping(nonce)).await.is_err() {
+                break;
+            }
+        }
+
+        wait_until(|| server.transport.peers().is_empty()).await;
+        assert!(server.address_book.lock().unwrap().is_banned("client", unix_now()));
+    }
+
+    #[tokio::test]
+    async fn test_slow_peer_is_disconnected() {
+        let network = MemoryNetwork::new();
+        let config = NetworkConfig {
+            max_messages_per_second: 100_000,
+            ..NetworkConfig::default()
+        };
+        let server = start_memory_node_with_config(&network, "server", config);
+
+        // A peer that completes the handshake, then never reads the messages of the node
+        let (client, _client_events, peer) = connect_raw_memory(&network, &server, "client").await;
+
+        // Every ping is answered, until the queue of the peer is full
+        for nonce in 0..4 * crate::network::transport::EVENT_CHANNEL_CAPACITY as u64 {
+            if client.try_send(peer, crate::network::message::ping(nonce)).is_err() {
+                break;
+            }
+            if nonce % 64 == 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+        wait_until(|| server.transport.peers().is_empty()).await;
+        assert!(!server.address_book.lock().unwrap().is_banned("client", unix_now()));
+
+        // The node keeps serving the other peers
+        let other = start_memory_node(&network, "other");
+        other.transport.dial("server").await.unwrap();
+        wait_until(|| ready_peers(&server.peers) == 1 && ready_peers(&other.peers) == 1).await;
+    }
+
+    #[tokio::test]
+    async fn test_undelivered_items_are_requested_from_other_peers() {
+        let network = MemoryNetwork::new();
+        let server = start_memory_node(&network, "server");
+        let (first, mut first_events, first_peer) = connect_raw_memory(&network, &server, "first").await;
+        let (second, mut second_events, second_peer) = connect_raw_memory(&network, &server, "second").await;
+
+        // Wait for the next request of the node, skipping the announcements
+        async fn next_get_data(events: &mut EventStream) -> Vec<Vec<u8>> {
+            loop {
+                match next_request(events).await {
+                    Payload::Inventory(_) => continue,
+                    Payload::GetData(request) => return request.hashes,
+                    payload => panic!("unexpected message {:?}", payload),
+                }
+            }
+        }
+
+        // Both peers announce the transaction, it is only requested from the first one
+        let tx = signed_transaction(0);
+        let hash = crate::types::transaction::transaction_hash(&tx);
+        first.try_send(first_peer, gossip::inventory(InventoryType::Transaction, &[hash])).unwrap();
+        assert_eq!(next_get_data(&mut first_events).await, vec![hash.to_bytes().to_vec()]);
+        second.try_send(second_peer, gossip::inventory(InventoryType::Transaction, &[hash])).unwrap();
+
+        // The first peer does not have it, the second one is asked
+        first.try_send(first_peer, gossip::not_found(InventoryType::Transaction, &[hash])).unwrap();
+        assert_eq!(next_get_data(&mut second_events).await, vec![hash.to_bytes().to_vec()]);
+        second.try_send(second_peer, gossip::transaction_message(tx.clone())).unwrap();
+        wait_until(|| server.blockchain.lock().unwrap().mempool.contains(&tx)).await;
+
+        // The second peer is asked when the first one disconnects before delivering
+        let tx = signed_transaction(0);
+        let hash = crate::types::transaction::transaction_hash(&tx);
+        first.try_send(first_peer, gossip::inventory(InventoryType::Transaction, &[hash])).unwrap();
+        assert_eq!(next_get_data(&mut first_events).await, vec![hash.to_bytes().to_vec()]);
+        second.try_send(second_peer, gossip::inventory(InventoryType::Transaction, &[hash])).unwrap();
+        first.disconnect(first_peer);
+        assert_eq!(next_get_data(&mut second_events).await, vec![hash.to_bytes().to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_compact_blocks_waiting_for_transactions_are_limited() {
+        let network = MemoryNetwork::new();
+        let server = start_memory_node(&network, "server");
+        let (client, mut events, peer) = connect_raw_memory(&network, &server, "client").await;
+
+        // Blocks extending the tip, each with a transaction the node does not have
+        let blocks: Vec<proto::Block> = (0..MAX_PARTIAL_BLOCKS_PER_PEER + 1)
+            .map(|nonce| next_block(&server.blockchain.lock().unwrap(), vec![signed_transaction(nonce as i64)]))
+            .collect();
+        for block in &blocks {
+            client.try_send(peer, compact::compact_block_message(compact::compact_block(block))).unwrap();
+        }
+
+        // The missing transactions are requested for the first blocks, the full block once the peer reached its limit
+        for block in &blocks[..MAX_PARTIAL_BLOCKS_PER_PEER] {
+            match next_request(&mut events).await {
+                Payload::GetBlockTransactions(request) => assert_eq!(request.block_hash, block.hash),
+                payload => panic!("unexpected message {:?}", payload),
+            }
+        }
+        match next_request(&mut events).await {
+            Payload::GetData(request) => {
+                assert_eq!(request.r#type(), InventoryType::Block);
+                assert_eq!(request.hashes, vec![blocks[MAX_PARTIAL_BLOCKS_PER_PEER].hash.clone()]);
+            }
+            payload => panic!("unexpected message {:?}", payload),
+        }
+
+        // A compact block that does not extend the tip is not rebuilt either
+        let mut fork = next_block(&server.blockchain.lock().unwrap(), vec![signed_transaction(0)]);
+        let header = fork.header.as_mut().unwrap();
+        header.height += 1;
+        header.prev_block_hash = vec![1; 32];
+        crate::types::block::mine_header(header);
+        crate::types::block::sign_block(&mut keys::generate_private_key(), &mut fork).unwrap();
+        client.try_send(peer, compact::compact_block_message(compact::compact_block(&fork))).unwrap();
+        assert!(matches!(next_request(&mut events).await, Payload::GetData(request) if request.hashes == vec![fork.hash.clone()]));
+    }
+
+    #[tokio::test]
+    async fn test_compact_block_without_proof_of_work_bans_peer() {
+        let network = MemoryNetwork::new();
+        let server = start_memory_node(&network, "server");
+        let (client, _events, peer) = connect_raw_memory(&network, &server, "client").await;
+
+        let mut block = next_block(&server.blockchain.lock().unwrap(), vec![signed_transaction(1)]);
+        let header = block.header.as_mut().unwrap();
+        while crate::types::block::meets_difficulty(header) {
+            header.nonce += 1;
+        }
+        crate::types::block::sign_block(&mut keys::generate_private_key(), &mut block).unwrap();
+        client.try_send(peer, compact::compact_block_message(compact::compact_block(&block))).unwrap();
+
+        wait_until(|| server.transport.peers().is_empty()).await;
+        assert!(server.address_book.lock().unwrap().is_banned("client", unix_now()));
+    }
+
+    #[tokio::test]
+    async fn test_partial_blocks_expire() {
+        let network = MemoryNetwork::new();
+        let (transport, _events) = network.bind("node").unwrap();
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(Box::new(MemoryStore::new()))));
+        let mut node = Node::new(NetworkConfig::default(), Arc::new(transport), blockchain.clone(), keys::generate_private_key());
+
+        let block = next_block(&blockchain.lock().unwrap(), vec![signed_transaction(1)]);
+        let partial = PartialBlock::new(compact::compact_block(&block), &blockchain.lock().unwrap().mempool).unwrap();
+        let hash = partial.hash;
+        let requested_at = Instant::now();
+        node.seen.insert(hash);
+        node.partial_blocks.insert(hash, (7, requested_at, partial));
+
+        assert!(node.expire_partial_blocks(requested_at + PARTIAL_BLOCK_TIMEOUT / 2).is_empty());
+        assert_eq!(node.expire_partial_blocks(requested_at + PARTIAL_BLOCK_TIMEOUT * 2), vec![7]);
+        assert!(node.partial_blocks.is_empty());
+
+        // The block can be requested again from another peer
+        assert!(!node.seen.contains(&hash));
+    }
+}
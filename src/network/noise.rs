@@ -0,0 +1,322 @@
+use crate::crypto::keys::{PrivateKey, PublicKey, SignatureWrapper};
+use crate::error::{Result, MarvinError};
+use crate::network::message::{decode_message, encode_frame, FRAME_HEADER_SIZE};
+use crate::proto;
+use crate::types::limits::SizeLimits;
+
+use prost::Message;
+use snow::{Builder, HandshakeState, StatelessTransportState};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Noise protocol used to encrypt the connections between peers
+pub const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+/// Maximum time a peer has to complete the Noise handshake
+pub const NOISE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum size of a Noise message
+const MAX_NOISE_MESSAGE_SIZE: usize = 65535;
+/// Size of the authentication tag appended to every encrypted Noise message
+const TAG_SIZE: usize = 16;
+/// Maximum size of the plaintext carried by a single Noise message, larger frames are split in several messages
+const MAX_CHUNK_SIZE: usize = MAX_NOISE_MESSAGE_SIZE - TAG_SIZE;
+/// Context of the signature binding the static Noise key to the identity of the node
+const IDENTITY_SIGNATURE_CONTEXT: &[u8] = b"marvin-noise-static-key:";
+
+/// NoiseKeys is the static Noise key of a transport along with the signed identity sent to its peers
+pub struct NoiseKeys {
+    static_private_key: Vec<u8>,
+    identity: Vec<u8>,
+}
+
+impl NoiseKeys {
+    /// Generate a static Noise key and sign it with the identity key of the node
+    pub fn new(private_key: &mut PrivateKey) -> Result<NoiseKeys> {
+        let keypair = builder().generate_keypair().map_err(noise_error)?;
+
+        let signature = private_key.sign(&identity_message(&keypair.public))?;
+        let identity = proto::NoiseIdentity {
+            public_key: private_key.public_key().to_bytes().to_vec(),
+            signature: signature.to_bytes().to_vec(),
+        };
+
+        Ok(NoiseKeys {
+            static_private_key: keypair.private,
+            identity: identity.encode_to_vec(),
+        })
+    }
+}
+
+/// NoiseSession is an encrypted connection after a completed handshake, authenticated with the identity of the peer
+pub struct NoiseSession {
+    state: Arc<StatelessTransportState>,
+    pub remote_public_key: PublicKey,
+}
+
+/// NoiseReader decrypts the messages received on the read half of a connection
+pub struct NoiseReader<R> {
+    reader: R,
+    state: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+/// NoiseWriter encrypts the messages sent on the write half of a connection
+pub struct NoiseWriter<W> {
+    writer: W,
+    state: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+/// Perform a Noise XX handshake on a stream. Both sides send their signed identity encrypted in the handshake,
+/// the session is only established if the identity of the peer signed its static Noise key.
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, keys: &NoiseKeys, initiator: bool) -> Result<NoiseSession> {
+    let builder = builder().local_private_key(&keys.static_private_key);
+    let mut state = if initiator {
+        builder.build_initiator().map_err(noise_error)?
+    } else {
+        builder.build_responder().map_err(noise_error)?
+    };
+
+    // -> e
+    // <- e, ee, s, es, identity
+    // -> s, se, identity
+    let payload = if initiator {
+        send_handshake_message(stream, &mut state, &[]).await?;
+        let payload = receive_handshake_message(stream, &mut state).await?;
+        send_handshake_message(stream, &mut state, &keys.identity).await?;
+        payload
+    } else {
+        receive_handshake_message(stream, &mut state).await?;
+        send_handshake_message(stream, &mut state, &keys.identity).await?;
+        receive_handshake_message(stream, &mut state).await?
+    };
+
+    let remote_static = state
+        .get_remote_static()
+        .ok_or_else(|| MarvinError::Network(String::from("Peer did not send its static key")))?
+        .to_vec();
+    let remote_public_key = verify_identity(&payload, &remote_static)?;
+
+    let state = state.into_stateless_transport_mode().map_err(noise_error)?;
+
+    Ok(NoiseSession {
+        state: Arc::new(state),
+        remote_public_key,
+    })
+}
+
+impl NoiseSession {
+    /// Split the session into a reader and a writer wrapping the two halves of the connection
+    pub fn split<R, W>(self, reader: R, writer: W) -> (NoiseReader<R>, NoiseWriter<W>) {
+        (
+            NoiseReader { reader, state: self.state.clone(), nonce: 0 },
+            NoiseWriter { writer, state: self.state, nonce: 0 },
+        )
+    }
+}
+
+impl<R: AsyncRead + Unpin> NoiseReader<R> {
+    /// Read and decrypt the next message, messages exceeding the size limits are rejected.
+    /// Returns `None` if the stream was closed cleanly before a new message started.
+    pub async fn read_message(&mut self, limits: &SizeLimits) -> Result<Option<proto::Message>> {
+        let mut frame = match read_chunk(&mut self.reader).await? {
+            Some(chunk) => self.decrypt(&chunk)?,
+            None => return Ok(None),
+        };
+        if frame.len() < FRAME_HEADER_SIZE {
+            return Err(MarvinError::Network(String::from("Encrypted frame is too short")));
+        }
+
+        // Check the length before buffering the rest of the frame
+        let length = u32::from_be_bytes(frame[..FRAME_HEADER_SIZE].try_into().unwrap()) as usize;
+        if length > limits.max_message_size {
+            return Err(MarvinError::Network(format!("Frame of {} bytes exceeds the maximum message size", length)));
+        }
+
+        while frame.len() < FRAME_HEADER_SIZE + length {
+            let chunk = read_chunk(&mut self.reader)
+                .await?
+                .ok_or_else(|| MarvinError::Network(String::from("Connection closed in the middle of a frame")))?;
+            frame.extend_from_slice(&self.decrypt(&chunk)?);
+        }
+        if frame.len() != FRAME_HEADER_SIZE + length {
+            return Err(MarvinError::Network(String::from("Encrypted frame is longer than its length prefix")));
+        }
+
+        decode_message(&frame[FRAME_HEADER_SIZE..], limits).map(Some)
+    }
+
+    fn decrypt(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        let mut plaintext = vec![0; chunk.len()];
+        let size = self.state.read_message(self.nonce, chunk, &mut plaintext).map_err(noise_error)?;
+        self.nonce += 1;
+        plaintext.truncate(size);
+
+        Ok(plaintext)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> NoiseWriter<W> {
+    /// Encrypt and write a message, frames larger than a Noise message are split in several Noise messages
+    pub async fn write_message(&mut self, message: &proto::Message) -> Result<()> {
+        let frame = encode_frame(message)?;
+
+        for chunk in frame.chunks(MAX_CHUNK_SIZE) {
+            let mut ciphertext = vec![0; chunk.len() + TAG_SIZE];
+            let size = self.state.write_message(self.nonce, chunk, &mut ciphertext).map_err(noise_error)?;
+            self.nonce += 1;
+
+            write_chunk(&mut self.writer, &ciphertext[..size]).await?;
+        }
+
+        self.writer.flush().await.map_err(|e| MarvinError::Network(e.to_string()))
+    }
+}
+
+fn builder() -> Builder<'static> {
+    Builder::new(NOISE_PARAMS.parse().expect("Noise parameters are valid"))
+}
+
+fn noise_error(e: snow::Error) -> MarvinError {
+    MarvinError::Network(format!("Noise error: {}", e))
+}
+
+/// Returns the message signed by the identity key of a node to bind it to its static Noise key
+fn identity_message(static_public_key: &[u8]) -> Vec<u8> {
+    [IDENTITY_SIGNATURE_CONTEXT, static_public_key].concat()
+}
+
+/// Verify that the identity sent by a peer signed the static key it used in the handshake
+fn verify_identity(payload: &[u8], remote_static: &[u8]) -> Result<PublicKey> {
+    let identity = proto::NoiseIdentity::decode(payload).map_err(|e| MarvinError::Network(e.to_string()))?;
+
+    let public_key = PublicKey::from_bytes(&identity.public_key)?;
+    let signature = SignatureWrapper::from_bytes(&identity.signature)?;
+    if !signature.verify(&identity_message(remote_static), &public_key) {
+        return Err(MarvinError::Network(String::from("Invalid signature of the peer static key")));
+    }
+
+    Ok(public_key)
+}
+
+async fn send_handshake_message<W: AsyncWrite + Unpin>(writer: &mut W, state: &mut HandshakeState, payload: &[u8]) -> Result<()> {
+    let mut message = vec![0; MAX_NOISE_MESSAGE_SIZE];
+    let size = state.write_message(payload, &mut message).map_err(noise_error)?;
+
+    write_chunk(writer, &message[..size]).await?;
+    writer.flush().await.map_err(|e| MarvinError::Network(e.to_string()))
+}
+
+async fn receive_handshake_message<R: AsyncRead + Unpin>(reader: &mut R, state: &mut HandshakeState) -> Result<Vec<u8>> {
+    let message = read_chunk(reader)
+        .await?
+        .ok_or_else(|| MarvinError::Network(String::from("Connection closed during the Noise handshake")))?;
+
+    let mut payload = vec![0; MAX_NOISE_MESSAGE_SIZE];
+    let size = state.read_message(&message, &mut payload).map_err(noise_error)?;
+    payload.truncate(size);
+
+    Ok(payload)
+}
+
+/// Write a Noise message prefixed with its 2 byte big endian length
+async fn write_chunk<W: AsyncWrite + Unpin>(writer: &mut W, chunk: &[u8]) -> Result<()> {
+    writer.write_all(&(chunk.len() as u16).to_be_bytes()).await.map_err(|e| MarvinError::Network(e.to_string()))?;
+    writer.write_all(chunk).await.map_err(|e| MarvinError::Network(e.to_string()))
+}
+
+/// Read the next length prefixed Noise message.
+/// Returns `None` if the stream was closed cleanly before a new message started.
+async fn read_chunk<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut header = [0; 2];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(MarvinError::Network(e.to_string())),
+    }
+
+    let mut chunk = vec![0; u16::from_be_bytes(header) as usize];
+    reader.read_exact(&mut chunk).await.map_err(|e| MarvinError::Network(e.to_string()))?;
+
+    Ok(Some(chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys;
+    use crate::network::message::ping;
+
+    async fn connect(client_keys: &NoiseKeys, server_keys: &NoiseKeys) -> (Result<NoiseSession>, Result<NoiseSession>, tokio::io::DuplexStream, tokio::io::DuplexStream) {
+        let (mut client, mut server) = tokio::io::duplex(1024 * 1024);
+        let (client_session, server_session) = tokio::join!(
+            handshake(&mut client, client_keys, true),
+            handshake(&mut server, server_keys, false)
+        );
+
+        (client_session, server_session, client, server)
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_exchange_messages() {
+        let mut client_key = keys::generate_private_key();
+        let mut server_key = keys::generate_private_key();
+        let client_keys = NoiseKeys::new(&mut client_key).unwrap();
+        let server_keys = NoiseKeys::new(&mut server_key).unwrap();
+
+        let (client_session, server_session, client, server) = connect(&client_keys, &server_keys).await;
+        let (client_session, server_session) = (client_session.unwrap(), server_session.unwrap());
+        assert_eq!(client_session.remote_public_key, server_key.public_key());
+        assert_eq!(server_session.remote_public_key, client_key.public_key());
+
+        let (client_read, client_write) = tokio::io::split(client);
+        let (server_read, server_write) = tokio::io::split(server);
+        let (_, mut client_writer) = client_session.split(client_read, client_write);
+        let (mut server_reader, _) = server_session.split(server_read, server_write);
+
+        // Messages larger than a Noise message are split and reassembled
+        let block = proto::Message {
+            payload: Some(proto::message::Payload::Block(proto::Block {
+                transactions: vec![proto::Transaction { data: vec![7; 4 * 1024], ..Default::default() }; 50],
+                ..Default::default()
+            })),
+        };
+        client_writer.write_message(&ping(1)).await.unwrap();
+        client_writer.write_message(&block).await.unwrap();
+        client_writer.write_message(&ping(2)).await.unwrap();
+
+        assert_eq!(server_reader.read_message(&SizeLimits::default()).await.unwrap(), Some(ping(1)));
+        assert_eq!(server_reader.read_message(&SizeLimits::default()).await.unwrap(), Some(block));
+        assert_eq!(server_reader.read_message(&SizeLimits::default()).await.unwrap(), Some(ping(2)));
+    }
+
+    #[tokio::test]
+    async fn test_identity_must_sign_static_key() {
+        let client_keys = NoiseKeys::new(&mut keys::generate_private_key()).unwrap();
+        let mut server_keys = NoiseKeys::new(&mut keys::generate_private_key()).unwrap();
+
+        // The server presents an identity signed for another static key
+        server_keys.identity = NoiseKeys::new(&mut keys::generate_private_key()).unwrap().identity;
+
+        let (client_session, _, _, _) = connect(&client_keys, &server_keys).await;
+        assert!(client_session.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tampered_message_is_rejected() {
+        let client_keys = NoiseKeys::new(&mut keys::generate_private_key()).unwrap();
+        let server_keys = NoiseKeys::new(&mut keys::generate_private_key()).unwrap();
+
+        let (client_session, server_session, _client, _server) = connect(&client_keys, &server_keys).await;
+        let (_, mut client_writer) = client_session.unwrap().split(tokio::io::empty(), Vec::new());
+        client_writer.write_message(&ping(1)).await.unwrap();
+
+        let mut ciphertext = client_writer.writer;
+        *ciphertext.last_mut().unwrap() ^= 1;
+
+        let (mut server_reader, _) = server_session.unwrap().split(ciphertext.as_slice(), tokio::io::sink());
+        assert!(server_reader.read_message(&SizeLimits::default()).await.is_err());
+    }
+}
@@ -0,0 +1,590 @@
+use crate::core::blockchain::{Blockchain, MAX_REORG_DEPTH};
+use crate::core::header_list::HeaderList;
+use crate::error::{Result, MarvinError};
+use crate::network::transport::PeerId;
+use crate::proto;
+use crate::proto::message::Payload;
+use crate::types::hash::Hash;
+use crate::types::limits::DEFAULT_MAX_BLOCK_SIZE;
+
+use prost::Message;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Maximum number of headers in a single Headers message
+pub const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+/// Maximum number of blocks requested from a single peer at the same time
+pub const MAX_BLOCKS_IN_FLIGHT_PER_PEER: usize = 16;
+/// Number of headers past the last connected block whose bodies can be downloaded ahead
+pub const DOWNLOAD_WINDOW: usize = 1024;
+/// Maximum number of bytes of blocks downloaded ahead of the connected blocks.
+/// Every block in flight counts for the maximum block size until it is received.
+pub const MAX_DOWNLOAD_BYTES: usize = 128 * 1024 * 1024;
+/// Maximum number of downloaded headers whose blocks are not connected yet
+pub const MAX_PENDING_HEADERS: usize = 4 * MAX_HEADERS_PER_MESSAGE;
+/// Time a peer has to deliver the headers or a block requested from it
+pub const SYNC_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// SyncEngine is the state of the headers-first initial block download.
+/// Headers are downloaded from a single peer and validated first, then the bodies of the blocks are
+/// downloaded in parallel from every peer that has them and handed back in chain order to be connected.
+pub struct SyncEngine {
+    /// Validated headers of the blocks to download, in chain order
+    pub headers: HeaderList,
+    /// Peer the headers are being downloaded from, and the time of the request
+    header_peer: Option<(PeerId, Instant)>,
+    /// Number of headers at the front of the list whose blocks were handed back to be connected
+    connected: usize,
+    /// Position of every header in the list, by hash
+    positions: HashMap<Hash, usize>,
+    /// Blocks requested from peers, with the peer and the time of the request
+    in_flight: HashMap<Hash, (PeerId, Instant)>,
+    /// Downloaded blocks waiting for the previous ones, with the peer that delivered them
+    downloaded: HashMap<Hash, (PeerId, proto::Block)>,
+    /// Encoded size of the downloaded blocks
+    downloaded_bytes: usize,
+    /// Blocks of a fork waiting for the fork to have more work than the current chain
+    fork_blocks: Vec<proto::Block>,
+    /// Cumulative proof of work of the blocks of the fork
+    fork_work: u128,
+}
+
+impl Default for SyncEngine {
+    fn default() -> Self {
+        SyncEngine::new()
+    }
+}
+
+impl SyncEngine {
+    pub fn new() -> Self {
+        SyncEngine {
+            headers: HeaderList::new(),
+            header_peer: None,
+            connected: 0,
+            positions: HashMap::new(),
+            in_flight: HashMap::new(),
+            downloaded: HashMap::new(),
+            downloaded_bytes: 0,
+            fork_blocks: Vec::new(),
+            fork_work: 0,
+        }
+    }
+
+    /// Returns the peer the headers are being downloaded from
+    pub fn header_peer(&self) -> Option<PeerId> {
+        self.header_peer.map(|(peer, _)| peer)
+    }
+
+    /// Record that headers were requested from the given peer
+    pub fn headers_requested(&mut self, peer: PeerId, now: Instant) {
+        self.header_peer = Some((peer, now));
+    }
+
+    /// Record that the header download from the current peer is over
+    pub fn headers_done(&mut self) {
+        self.header_peer = None;
+    }
+
+    /// Returns the number of downloaded headers whose blocks are not connected yet
+    pub fn pending_headers(&self) -> usize {
+        self.headers.len() as usize - self.connected
+    }
+
+    /// Check if a full Headers message fits under the limit of pending headers.
+    /// Once the limit is reached, more headers are only requested after the blocks of the pending ones are connected.
+    pub fn wants_headers(&self) -> bool {
+        self.pending_headers() + MAX_HEADERS_PER_MESSAGE <= MAX_PENDING_HEADERS
+    }
+
+    /// Returns the height of the last header to download, if there are headers left to download
+    pub fn target_height(&self) -> Option<u64> {
+        self.headers.last().map(|header| header.height)
+    }
+
+    /// Build the locator of a GetHeaders request, starting from the last downloaded header if there is one
+    pub fn locator(&self, blockchain: &Blockchain) -> Vec<Hash> {
+        let mut locator = Vec::new();
+        if let Some(last) = self.headers.last() {
+            locator.push(header_hash(last));
+        }
+        locator.extend(blockchain.block_locator());
+
+        locator
+    }
+
+    /// Validate a batch of headers and append them to the headers to download.
+    /// The first header must follow the last downloaded header, or a block of the current chain if there is none,
+    /// no more than MAX_REORG_DEPTH blocks below the tip.
+    /// Headers going over the limit of pending headers are rejected.
+    pub fn add_headers(&mut self, blockchain: &Blockchain, headers: Vec<proto::Header>) -> Result<()> {
+        let first = match headers.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        if self.pending_headers() + headers.len() > MAX_PENDING_HEADERS {
+            return Err(MarvinError::Network(format!(
+                "Headers go over the limit of {} headers ahead of the connected blocks", MAX_PENDING_HEADERS
+            )));
+        }
+
+        let mut prev = match self.headers.last() {
+            Some(last) => last.clone(),
+            None => {
+                let prev_hash = Hash::from_bytes(&first.prev_block_hash)?;
+                if !blockchain.contains_block(&prev_hash) {
+                    return Err(MarvinError::Network(String::from("Headers do not connect to the current chain")));
+                }
+                let prev = blockchain.get_block(&prev_hash)?.header.unwrap_or_default();
+                if blockchain.height() - prev.height as usize > MAX_REORG_DEPTH {
+                    return Err(MarvinError::Network(format!(
+                        "Headers fork from the current chain more than {} blocks below the tip", MAX_REORG_DEPTH
+                    )));
+                }
+                prev
+            }
+        };
+
+        for header in headers.iter() {
+            blockchain.validate_header(&prev, header)?;
+            prev = header.clone();
+        }
+
+        for header in headers {
+            self.positions.insert(header_hash(&header), self.headers.len() as usize);
+            self.headers.add(header);
+        }
+
+        Ok(())
+    }
+
+    /// Assign the blocks to download to the given peers, along with their best height.
+    /// Blocks are spread over all the peers that have them, with a limit of blocks in flight per peer,
+    /// and the blocks following the next one to connect stay under MAX_DOWNLOAD_BYTES.
+    /// Returns the hashes to request from each peer.
+    pub fn schedule(&mut self, peers: &[(PeerId, u64)], now: Instant) -> HashMap<PeerId, Vec<Hash>> {
+        let mut load: HashMap<PeerId, usize> = peers.iter().map(|(peer, _)| (*peer, 0)).collect();
+        for (peer, _) in self.in_flight.values() {
+            if let Some(count) = load.get_mut(peer) {
+                *count += 1;
+            }
+        }
+
+        let mut requests: HashMap<PeerId, Vec<Hash>> = HashMap::new();
+        let mut next_peer = 0;
+        let end = (self.connected + DOWNLOAD_WINDOW).min(self.headers.len() as usize);
+        let mut reserved = self.downloaded_bytes + self.in_flight.len() * DEFAULT_MAX_BLOCK_SIZE;
+
+        for (position, header) in self.headers.headers[self.connected..end].iter().enumerate() {
+            let hash = header_hash(header);
+            if self.in_flight.contains_key(&hash) || self.downloaded.contains_key(&hash) {
+                continue;
+            }
+            // The next block to connect is always downloaded, so the download cannot stall on a full budget
+            if position > 0 && reserved + DEFAULT_MAX_BLOCK_SIZE > MAX_DOWNLOAD_BYTES {
+                break;
+            }
+
+            // Round robin over the peers that have the block and are below the limit of blocks in flight
+            let candidate = (0..peers.len()).map(|i| peers[(next_peer + i) % peers.len()]).position(|(peer, best_height)| {
+                best_height >= header.height && load[&peer] < MAX_BLOCKS_IN_FLIGHT_PER_PEER
+            });
+            let peer = match candidate {
+                Some(offset) => {
+                    let index = (next_peer + offset) % peers.len();
+                    next_peer = index + 1;
+                    peers[index].0
+                }
+                None => continue,
+            };
+
+            *load.get_mut(&peer).unwrap() += 1;
+            reserved += DEFAULT_MAX_BLOCK_SIZE;
+            self.in_flight.insert(hash, (peer, now));
+            requests.entry(peer).or_default().push(hash);
+        }
+
+        requests
+    }
+
+    /// Check if a block is one of the blocks to download
+    pub fn is_pending(&self, hash: &Hash) -> bool {
+        match self.positions.get(hash) {
+            Some(position) => *position >= self.connected && !self.downloaded.contains_key(hash),
+            None => false,
+        }
+    }
+
+    /// Store a downloaded block until the previous blocks are connected.
+    /// Returns false if the block was not expected.
+    pub fn block_received(&mut self, peer: PeerId, block: proto::Block) -> bool {
+        let hash = crate::types::block::block_hash(&block);
+        if !self.is_pending(&hash) {
+            return false;
+        }
+
+        self.in_flight.remove(&hash);
+        self.downloaded_bytes += block.encoded_len();
+        self.downloaded.insert(hash, (peer, block));
+
+        true
+    }
+
+    /// Returns the next block to connect along with the peer that delivered it, if it was downloaded
+    pub fn next_block(&mut self) -> Option<(PeerId, proto::Block)> {
+        let header = self.headers.get(self.connected)?;
+        let downloaded = self.downloaded.remove(&header_hash(header))?;
+        self.downloaded_bytes -= downloaded.1.encoded_len();
+        self.connected += 1;
+
+        Some(downloaded)
+    }
+
+    /// Connect the downloaded blocks to the blockchain in chain order.
+    /// The blocks of a fork are kept until the fork has more cumulative work than the blocks of the current chain
+    /// above the fork, then the chain is reorganized.
+    /// Returns the number of blocks connected, or the peer that delivered an invalid block along with the error.
+    pub fn connect_blocks(&mut self, blockchain: &mut Blockchain) -> std::result::Result<usize, (PeerId, MarvinError)> {
+        let mut connected = 0;
+
+        while let Some((peer, block)) = self.next_block() {
+            let hash = crate::types::block::block_hash(&block);
+            let height = block.header.as_ref().unwrap().height as usize;
+
+            let result = if blockchain.contains_block(&hash) {
+                continue;
+            } else if self.fork_blocks.is_empty() && height == blockchain.height() + 1 {
+                blockchain.add_block(block)
+            } else {
+                self.fork_work = self.fork_work.saturating_add(Blockchain::block_work(block.header.as_ref().unwrap().difficulty));
+                self.fork_blocks.push(block);
+                let fork_height = height - self.fork_blocks.len();
+                if self.fork_work <= blockchain.work_above(fork_height) {
+                    continue;
+                }
+                self.fork_work = 0;
+                let blocks = std::mem::take(&mut self.fork_blocks);
+                blockchain.reorganize(fork_height, blocks)
+            };
+
+            match result {
+                Ok(()) => connected += 1,
+                Err(e) => {
+                    self.reset();
+                    return Err((peer, e));
+                }
+            }
+        }
+
+        // Start over with an empty list once every block was connected
+        if self.connected == self.headers.len() as usize && self.header_peer.is_none() {
+            self.reset();
+        }
+
+        Ok(connected)
+    }
+
+    /// Forget that a block was requested, so it can be requested from another peer
+    pub fn not_found(&mut self, hash: &Hash) {
+        self.in_flight.remove(hash);
+    }
+
+    /// Forget the requests that were not answered in time, returning the peers that did not answer
+    pub fn expire(&mut self, now: Instant) -> Vec<PeerId> {
+        let mut stalled = Vec::new();
+
+        if let Some((peer, requested_at)) = self.header_peer {
+            if now.duration_since(requested_at) > SYNC_REQUEST_TIMEOUT {
+                self.header_peer = None;
+                stalled.push(peer);
+            }
+        }
+
+        self.in_flight.retain(|_, (peer, requested_at)| {
+            let expired = now.duration_since(*requested_at) > SYNC_REQUEST_TIMEOUT;
+            if expired && !stalled.contains(peer) {
+                stalled.push(*peer);
+            }
+            !expired
+        });
+
+        stalled
+    }
+
+    /// Forget the requests sent to a disconnected peer
+    pub fn peer_disconnected(&mut self, peer: PeerId) {
+        if self.header_peer() == Some(peer) {
+            self.header_peer = None;
+        }
+        self.in_flight.retain(|_, (requested_from, _)| *requested_from != peer);
+    }
+
+    /// Drop all the downloaded headers and blocks
+    pub fn reset(&mut self) {
+        *self = SyncEngine::new();
+    }
+}
+
+fn header_hash(header: &proto::Header) -> Hash {
+    Hash::from_bytes(&crate::types::block::hash_header(header)).unwrap()
+}
+
+/// Create a GetHeaders message
+pub fn get_headers(locator: &[Hash], max_headers: usize) -> proto::Message {
+    proto::Message {
+        payload: Some(Payload::GetHeaders(proto::GetHeaders {
+            locator: locator.iter().map(|hash| hash.to_bytes().to_vec()).collect(),
+            max_headers: max_headers as u32,
+        })),
+    }
+}
+
+/// Create a Headers message
+pub fn headers_message(headers: Vec<proto::Header>) -> proto::Message {
+    proto::Message {
+        payload: Some(Payload::Headers(proto::Headers { headers })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::storage::MemoryStore;
+    use crate::crypto::keys;
+
+    /// Build a chain of signed and mined blocks on top of the tip of the given blockchain
+    fn build_chain(blockchain: &Blockchain, count: u64) -> Vec<proto::Block> {
+        let mut prev = blockchain.headers.last().unwrap().clone();
+        let mut blocks = Vec::new();
+
+        for _ in 0..count {
+            let mut header = proto::Header {
+                height: prev.height + 1,
+                version: 1,
+                timestamp: prev.timestamp.max(Blockchain::get_current_timestamp_as_unix_nano() as i64 - 60_000_000_000) + 1,
+                prev_block_hash: crate::types::block::hash_header(&prev),
+                tx_hash: crate::types::block::calculate_tx_hash(&[]),
+                difficulty: blockchain.difficulty,
+                ..Default::default()
+            };
+            crate::types::block::mine_header(&mut header);
+
+            let mut block = proto::Block {
+                header: Some(header.clone()),
+                ..Default::default()
+            };
+            crate::types::block::sign_block(&mut keys::generate_private_key(), &mut block).unwrap();
+
+            blocks.push(block);
+            prev = header;
+        }
+
+        blocks
+    }
+
+    fn headers_of(blocks: &[proto::Block]) -> Vec<proto::Header> {
+        blocks.iter().map(|block| block.header.clone().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_add_headers_validates_chain() {
+        let blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let blocks = build_chain(&blockchain, 4);
+        let mut sync = SyncEngine::new();
+
+        // Headers must connect to the current chain
+        assert!(sync.add_headers(&blockchain, headers_of(&blocks[1..])).is_err());
+
+        let mut broken = headers_of(&blocks[..2]);
+        broken[1].nonce += 1;
+        assert!(sync.add_headers(&blockchain, broken).is_err());
+        assert!(sync.headers.is_empty());
+
+        sync.add_headers(&blockchain, headers_of(&blocks[..2])).unwrap();
+        sync.add_headers(&blockchain, headers_of(&blocks[2..])).unwrap();
+        assert_eq!(sync.target_height(), Some(4));
+        assert_eq!(sync.locator(&blockchain)[0], crate::types::block::block_hash(&blocks[3]));
+    }
+
+    #[test]
+    fn test_download_from_multiple_peers_in_order() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let blocks = build_chain(&blockchain, 6);
+        let mut sync = SyncEngine::new();
+        sync.add_headers(&blockchain, headers_of(&blocks)).unwrap();
+
+        // Peer 2 only has the first blocks
+        let now = Instant::now();
+        let peers = [(1, 6), (2, 2), (3, 6)];
+        let requests = sync.schedule(&peers, now);
+        assert_eq!(requests.values().map(|hashes| hashes.len()).sum::<usize>(), 6);
+        assert_eq!(requests[&2], vec![crate::types::block::block_hash(&blocks[1])]);
+        assert_eq!(requests[&1].len(), 3);
+        assert!(sync.schedule(&peers, now).is_empty());
+
+        // Blocks received out of order are handed back in chain order
+        for block in blocks.iter().rev() {
+            let hash = crate::types::block::block_hash(block);
+            let peer = requests.iter().find(|(_, hashes)| hashes.contains(&hash)).map(|(peer, _)| *peer).unwrap();
+            assert!(sync.block_received(peer, block.clone()));
+        }
+        assert_eq!(sync.connect_blocks(&mut blockchain).unwrap(), 6);
+
+        assert_eq!(blockchain.height(), 6);
+        assert!(sync.headers.is_empty());
+    }
+
+    #[test]
+    fn test_connect_longer_fork() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let fork = build_chain(&blockchain, 3);
+        for block in build_chain(&blockchain, 2) {
+            blockchain.add_block(block).unwrap();
+        }
+
+        let mut sync = SyncEngine::new();
+        sync.add_headers(&blockchain, headers_of(&fork)).unwrap();
+        sync.schedule(&[(1, 3)], Instant::now());
+        for block in fork.iter() {
+            assert!(sync.block_received(1, block.clone()));
+        }
+
+        assert_eq!(sync.connect_blocks(&mut blockchain).unwrap(), 1);
+        assert_eq!(blockchain.height(), 3);
+        assert!(blockchain.contains_block(&crate::types::block::block_hash(&fork[0])));
+    }
+
+    #[test]
+    fn test_connect_fork_with_more_work() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        blockchain.difficulty += 2;
+        let heavy = build_chain(&blockchain, 1);
+        blockchain.difficulty -= 2;
+        let light = build_chain(&blockchain, 3);
+        for block in build_chain(&blockchain, 2) {
+            blockchain.add_block(block).unwrap();
+        }
+
+        // A longer fork with less work is not connected
+        let mut sync = SyncEngine::new();
+        sync.add_headers(&blockchain, headers_of(&light[..2])).unwrap();
+        sync.schedule(&[(1, 3)], Instant::now());
+        for block in light[..2].iter() {
+            assert!(sync.block_received(1, block.clone()));
+        }
+        assert_eq!(sync.connect_blocks(&mut blockchain).unwrap(), 0);
+        assert_eq!(blockchain.height(), 2);
+
+        // A shorter fork with more work replaces the chain
+        let mut sync = SyncEngine::new();
+        sync.add_headers(&blockchain, headers_of(&heavy)).unwrap();
+        sync.schedule(&[(1, 1)], Instant::now());
+        assert!(sync.block_received(1, heavy[0].clone()));
+        assert_eq!(sync.connect_blocks(&mut blockchain).unwrap(), 1);
+        assert_eq!(blockchain.height(), 1);
+        assert!(blockchain.contains_block(&crate::types::block::block_hash(&heavy[0])));
+    }
+
+    #[test]
+    fn test_headers_forking_too_deep_are_rejected() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let fork = build_chain(&blockchain, 1);
+        for block in build_chain(&blockchain, MAX_REORG_DEPTH as u64 + 1) {
+            blockchain.add_block(block).unwrap();
+        }
+
+        let mut sync = SyncEngine::new();
+        assert!(sync.add_headers(&blockchain, headers_of(&fork)).is_err());
+        assert!(sync.headers.is_empty());
+    }
+
+    #[test]
+    fn test_download_is_bounded_by_bytes() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let blocks = build_chain(&blockchain, 100);
+        let mut sync = SyncEngine::new();
+        sync.add_headers(&blockchain, headers_of(&blocks)).unwrap();
+
+        let peers: Vec<(PeerId, u64)> = (1..=8).map(|peer| (peer, 100)).collect();
+        let now = Instant::now();
+        let requests = sync.schedule(&peers, now);
+        let budget = MAX_DOWNLOAD_BYTES / DEFAULT_MAX_BLOCK_SIZE;
+        assert_eq!(requests.values().map(|hashes| hashes.len()).sum::<usize>(), budget);
+
+        // A received block only frees its space once it is connected
+        let hash = crate::types::block::block_hash(&blocks[0]);
+        let peer = requests.iter().find(|(_, hashes)| hashes.contains(&hash)).map(|(peer, _)| *peer).unwrap();
+        assert!(sync.block_received(peer, blocks[0].clone()));
+        assert!(sync.schedule(&peers, now).is_empty());
+        assert_eq!(sync.connect_blocks(&mut blockchain).unwrap(), 1);
+        let requests = sync.schedule(&peers, now);
+        assert_eq!(requests.values().flatten().collect::<Vec<_>>(), vec![&crate::types::block::block_hash(&blocks[budget])]);
+    }
+
+    #[test]
+    fn test_invalid_block_resets_sync() {
+        let mut blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let mut blocks = build_chain(&blockchain, 2);
+
+        let mut sync = SyncEngine::new();
+        sync.add_headers(&blockchain, headers_of(&blocks)).unwrap();
+        sync.schedule(&[(1, 2)], Instant::now());
+
+        // The body does not match the transactions the header commits to
+        blocks[0].transactions.push(proto::Transaction::default());
+        assert!(sync.block_received(1, blocks[0].clone()));
+        assert!(sync.block_received(1, blocks[1].clone()));
+
+        assert_eq!(sync.connect_blocks(&mut blockchain).unwrap_err().0, 1);
+        assert!(sync.headers.is_empty());
+        assert_eq!(blockchain.height(), 0);
+    }
+
+    #[test]
+    fn test_rescheduling() {
+        let blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let blocks = build_chain(&blockchain, 2);
+        let mut sync = SyncEngine::new();
+        sync.add_headers(&blockchain, headers_of(&blocks)).unwrap();
+
+        let now = Instant::now();
+        assert_eq!(sync.schedule(&[(1, 2)], now)[&1].len(), 2);
+
+        // A block with a body that does not match its header is not accepted
+        let mut tampered = blocks[0].clone();
+        tampered.header.as_mut().unwrap().version = 2;
+        assert!(!sync.block_received(1, tampered));
+
+        // Unanswered requests are sent to another peer
+        sync.not_found(&crate::types::block::block_hash(&blocks[0]));
+        assert_eq!(sync.schedule(&[(2, 2)], now)[&2].len(), 1);
+
+        sync.peer_disconnected(1);
+        assert_eq!(sync.schedule(&[(3, 2)], now)[&3].len(), 1);
+
+        let mut stalled = sync.expire(now + SYNC_REQUEST_TIMEOUT * 2);
+        stalled.sort();
+        assert_eq!(stalled, vec![2, 3]);
+        assert_eq!(sync.schedule(&[(4, 2)], now)[&4].len(), 2);
+    }
+
+    #[test]
+    fn test_pending_headers_limit() {
+        let blockchain = Blockchain::new(Box::new(MemoryStore::new()));
+        let blocks = build_chain(&blockchain, 2);
+        let mut sync = SyncEngine::new();
+        assert!(sync.wants_headers());
+
+        // Fill the list up to the limit, the headers of the list are not validated again
+        for _ in 0..MAX_PENDING_HEADERS - 1 {
+            sync.headers.add(proto::Header::default());
+        }
+        assert!(!sync.wants_headers());
+        assert!(sync.add_headers(&blockchain, headers_of(&blocks)).is_err());
+        assert_eq!(sync.pending_headers(), MAX_PENDING_HEADERS - 1);
+
+        // Connecting the blocks of the pending headers makes room for more headers
+        sync.connected = MAX_HEADERS_PER_MESSAGE;
+        assert!(sync.wants_headers());
+    }
+}
@@ -0,0 +1,407 @@
+use crate::crypto::keys::{PrivateKey, PublicKey};
+use crate::error::{Result, MarvinError};
+use crate::network::noise::{self, NoiseKeys, NoiseSession, NOISE_HANDSHAKE_TIMEOUT};
+use crate::network::transport::{report_disconnected, EventStream, PeerId, Transport, TransportEvent};
+use crate::network::transport::{EVENT_CHANNEL_CAPACITY, PEER_CHANNEL_CAPACITY};
+use crate::proto;
+use crate::types::limits::SizeLimits;
+use crate::utils::log::make_json_logger;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+/// Default maximum number of inbound connections, including the ones still in their Noise handshake
+pub const DEFAULT_MAX_INBOUND: usize = 64;
+/// Time to wait before accepting again after the first failure to accept a connection
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+/// Longest time to wait before accepting again after repeated failures to accept a connection
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// TcpTransport accepts and dials peers over TCP, exchanging length prefixed protobuf messages.
+/// Every connection starts with a Noise handshake: the messages are encrypted and the connection is
+/// authenticated with the identity key of the peer.
+pub struct TcpTransport {
+    local_addr: SocketAddr,
+    inner: Arc<Inner>,
+    listener: JoinHandle<()>,
+}
+
+/// Connection is the handle of an open connection with a peer
+struct Connection {
+    sender: mpsc::Sender<proto::Message>,
+    reader: JoinHandle<()>,
+    remote_public_key: PublicKey,
+    inbound: bool,
+}
+
+/// State shared between the transport and the tasks of its connections
+struct Inner {
+    keys: NoiseKeys,
+    limits: SizeLimits,
+    next_peer_id: AtomicU64,
+    connections: Mutex<HashMap<PeerId, Connection>>,
+    events: mpsc::Sender<TransportEvent>,
+    /// Number of inbound connections, open or in their Noise handshake
+    inbound: AtomicUsize,
+    max_inbound: AtomicUsize,
+    logger: slog::Logger,
+}
+
+impl TcpTransport {
+    /// Bind the transport to the given address and start accepting connections.
+    /// Connections are authenticated with the given identity key of the node,
+    /// peers sending messages that exceed the size limits are disconnected.
+    /// Returns the transport along with the stream of its events.
+    pub async fn bind(addr: &str, private_key: &mut PrivateKey, limits: SizeLimits) -> Result<(TcpTransport, EventStream)> {
+        limits.validate()?;
+        let keys = NoiseKeys::new(private_key)?;
+        let listener = TcpListener::bind(addr).await.map_err(|e| MarvinError::Network(e.to_string()))?;
+        let local_addr = listener.local_addr().map_err(|e| MarvinError::Network(e.to_string()))?;
+
+        let (events, event_stream) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let inner = Arc::new(Inner {
+            keys,
+            limits,
+            next_peer_id: AtomicU64::new(1),
+            connections: Mutex::new(HashMap::new()),
+            events,
+            inbound: AtomicUsize::new(0),
+            max_inbound: AtomicUsize::new(DEFAULT_MAX_INBOUND),
+            logger: make_json_logger(),
+        });
+
+        let listener = tokio::spawn(accept_loop(listener, inner.clone()));
+
+        Ok((TcpTransport { local_addr, inner, listener }, event_stream))
+    }
+
+    /// Set the maximum number of inbound connections, including the ones still in their Noise handshake.
+    /// Connections accepted over the limit are closed right away.
+    pub fn set_max_inbound(&self, max_inbound: usize) {
+        self.inner.max_inbound.store(max_inbound, Ordering::Relaxed);
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.listener.abort();
+        for (_, connection) in self.inner.connections.lock().unwrap().drain() {
+            connection.reader.abort();
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn local_addr(&self) -> String {
+        self.local_addr.to_string()
+    }
+
+    async fn dial(&self, addr: &str) -> Result<PeerId> {
+        let mut stream = TcpStream::connect(addr).await.map_err(|e| MarvinError::Network(e.to_string()))?;
+        let session = self.inner.handshake(&mut stream, true).await?;
+
+        self.inner.clone().register(stream, session, addr.to_string(), false).await
+    }
+
+    async fn send(&self, peer: PeerId, message: proto::Message) -> Result<()> {
+        self.inner
+            .sender(peer)?
+            .send(message)
+            .await
+            .map_err(|_| MarvinError::Network(format!("Connection with peer {} is closed", peer)))
+    }
+
+    fn try_send(&self, peer: PeerId, message: proto::Message) -> Result<()> {
+        self.inner.sender(peer)?.try_send(message).map_err(|e| match e {
+            TrySendError::Full(_) => MarvinError::Network(format!("Outgoing queue of peer {} is full", peer)),
+            TrySendError::Closed(_) => MarvinError::Network(format!("Connection with peer {} is closed", peer)),
+        })
+    }
+
+    fn disconnect(&self, peer: PeerId) {
+        self.inner.close(peer);
+    }
+
+    fn peers(&self) -> Vec<PeerId> {
+        self.inner.connections.lock().unwrap().keys().copied().collect()
+    }
+
+    fn remote_public_key(&self, peer: PeerId) -> Option<PublicKey> {
+        self.inner.connections.lock().unwrap().get(&peer).map(|connection| connection.remote_public_key)
+    }
+}
+
+impl Inner {
+    /// Returns the queue of the outgoing messages of a peer
+    fn sender(&self, peer: PeerId) -> Result<mpsc::Sender<proto::Message>> {
+        match self.connections.lock().unwrap().get(&peer) {
+            Some(connection) => Ok(connection.sender.clone()),
+            None => Err(MarvinError::Network(format!("Peer {} is not connected", peer))),
+        }
+    }
+
+    /// Perform the Noise handshake of a new connection, peers that do not complete it in time are dropped
+    async fn handshake(&self, stream: &mut TcpStream, initiator: bool) -> Result<NoiseSession> {
+        timeout(NOISE_HANDSHAKE_TIMEOUT, noise::handshake(stream, &self.keys, initiator))
+            .await
+            .map_err(|_| MarvinError::Network(String::from("Noise handshake timed out")))?
+    }
+
+    /// Start the reader and writer tasks of a new connection and report it on the event stream
+    async fn register(self: Arc<Self>, stream: TcpStream, session: NoiseSession, addr: String, inbound: bool) -> Result<PeerId> {
+        let _ = stream.set_nodelay(true);
+        let remote_public_key = session.remote_public_key;
+        let (read_half, write_half) = stream.into_split();
+        let (mut reader, mut writer) = session.split(read_half, write_half);
+        let peer = self.next_peer_id.fetch_add(1, Ordering::Relaxed);
+
+        // The connected event has to be on the stream before any message of the peer
+        self.events
+            .send(TransportEvent::Connected { peer, addr, inbound })
+            .await
+            .map_err(|_| MarvinError::Network(String::from("Transport event stream is closed")))?;
+
+        let (sender, mut outgoing) = mpsc::channel::<proto::Message>(PEER_CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                if writer.write_message(&message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Hold the lock while spawning the reader, so it cannot remove the connection before it is inserted
+        let mut connections = self.connections.lock().unwrap();
+        let inner = self.clone();
+        let reader = tokio::spawn(async move {
+            while let Ok(Some(message)) = reader.read_message(&inner.limits).await {
+                if inner.events.send(TransportEvent::Message { peer, message }).await.is_err() {
+                    break;
+                }
+            }
+            inner.close(peer);
+        });
+        connections.insert(peer, Connection { sender, reader, remote_public_key, inbound });
+
+        Ok(peer)
+    }
+
+    /// Close a connection, reporting it on the event stream if it was still open
+    fn close(&self, peer: PeerId) {
+        let connection = self.connections.lock().unwrap().remove(&peer);
+        if let Some(connection) = connection {
+            connection.reader.abort();
+            if connection.inbound {
+                self.inbound.fetch_sub(1, Ordering::Relaxed);
+            }
+            report_disconnected(&self.events, peer);
+        }
+    }
+}
+
+/// Accept incoming connections until the transport is dropped.
+/// Connections over the inbound limit are closed before their handshake starts.
+async fn accept_loop(listener: TcpListener, inner: Arc<Inner>) {
+    let mut backoff = ACCEPT_BACKOFF_MIN;
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                backoff = ACCEPT_BACKOFF_MIN;
+
+                let max_inbound = inner.max_inbound.load(Ordering::Relaxed);
+                if inner.inbound.fetch_add(1, Ordering::Relaxed) >= max_inbound {
+                    inner.inbound.fetch_sub(1, Ordering::Relaxed);
+                    debug!(inner.logger, "Inbound connection refused, too many inbound connections"; "addr" => addr.to_string());
+                    continue;
+                }
+
+                // The handshake runs on its own task, so a slow peer does not hold back the other connections
+                let inner = inner.clone();
+                tokio::spawn(async move {
+                    let mut stream = stream;
+                    let registered = match inner.handshake(&mut stream, false).await {
+                        Ok(session) => inner.clone().register(stream, session, addr.to_string(), true).await.is_ok(),
+                        Err(_) => false,
+                    };
+                    if !registered {
+                        inner.inbound.fetch_sub(1, Ordering::Relaxed);
+                    }
+                });
+            }
+            // Errors such as running out of file descriptors last a while, retrying right away would spin
+            Err(e) => {
+                warn!(inner.logger, "Failed to accept a connection"; "error" => e.to_string(), "retry_in" => format!("{:?}", backoff));
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys;
+    use crate::network::message::{ping, pong};
+
+    use std::time::Duration;
+
+    async fn next_event(events: &mut EventStream) -> TransportEvent {
+        timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_dial_and_exchange_messages() {
+        let mut server_key = keys::generate_private_key();
+        let mut client_key = keys::generate_private_key();
+        let (server, mut server_events) = TcpTransport::bind("127.0.0.1:0", &mut server_key, SizeLimits::default()).await.unwrap();
+        let (client, mut client_events) = TcpTransport::bind("127.0.0.1:0", &mut client_key, SizeLimits::default()).await.unwrap();
+
+        let server_peer = client.dial(&server.local_addr()).await.unwrap();
+        assert!(matches!(
+            next_event(&mut client_events).await,
+            TransportEvent::Connected { peer, inbound: false, .. } if peer == server_peer
+        ));
+
+        let client_peer = match next_event(&mut server_events).await {
+            TransportEvent::Connected { peer, inbound: true, .. } => peer,
+            event => panic!("unexpected event {:?}", event),
+        };
+
+        // Both ends of the connection are authenticated with the identity of the other node
+        assert_eq!(client.remote_public_key(server_peer), Some(server_key.public_key()));
+        assert_eq!(server.remote_public_key(client_peer), Some(client_key.public_key()));
+
+        client.send(server_peer, ping(7)).await.unwrap();
+        assert_eq!(
+            next_event(&mut server_events).await,
+            TransportEvent::Message { peer: client_peer, message: ping(7) }
+        );
+
+        server.try_send(client_peer, pong(7)).unwrap();
+        assert_eq!(
+            next_event(&mut client_events).await,
+            TransportEvent::Message { peer: server_peer, message: pong(7) }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let (server, mut server_events) = TcpTransport::bind("127.0.0.1:0", &mut keys::generate_private_key(), SizeLimits::default()).await.unwrap();
+        let (client, mut client_events) = TcpTransport::bind("127.0.0.1:0", &mut keys::generate_private_key(), SizeLimits::default()).await.unwrap();
+
+        let server_peer = client.dial(&server.local_addr()).await.unwrap();
+        next_event(&mut client_events).await;
+        let client_peer = match next_event(&mut server_events).await {
+            TransportEvent::Connected { peer, .. } => peer,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(client.peers(), vec![server_peer]);
+
+        client.disconnect(server_peer);
+        assert_eq!(next_event(&mut client_events).await, TransportEvent::Disconnected { peer: server_peer });
+        assert!(client.peers().is_empty());
+        assert!(client.send(server_peer, ping(1)).await.is_err());
+        assert!(client.try_send(server_peer, ping(1)).is_err());
+
+        // The remote side notices the connection was closed
+        assert_eq!(next_event(&mut server_events).await, TransportEvent::Disconnected { peer: client_peer });
+        assert!(server.peers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_with_full_event_stream() {
+        let (server, mut server_events) = TcpTransport::bind("127.0.0.1:0", &mut keys::generate_private_key(), SizeLimits::default()).await.unwrap();
+        let (client, mut client_events) = TcpTransport::bind("127.0.0.1:0", &mut keys::generate_private_key(), SizeLimits::default()).await.unwrap();
+
+        let server_peer = client.dial(&server.local_addr()).await.unwrap();
+        next_event(&mut client_events).await;
+        let client_peer = match next_event(&mut server_events).await {
+            TransportEvent::Connected { peer, .. } => peer,
+            event => panic!("unexpected event {:?}", event),
+        };
+
+        // Fill the event stream of the client with messages it does not read
+        for nonce in 0..EVENT_CHANNEL_CAPACITY as u64 {
+            server.send(client_peer, ping(nonce)).await.unwrap();
+        }
+        while client_events.len() < EVENT_CHANNEL_CAPACITY {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // The disconnection is reported once there is room on the stream
+        client.disconnect(server_peer);
+        for _ in 0..EVENT_CHANNEL_CAPACITY {
+            assert!(matches!(next_event(&mut client_events).await, TransportEvent::Message { .. }));
+        }
+        assert_eq!(next_event(&mut client_events).await, TransportEvent::Disconnected { peer: server_peer });
+    }
+
+    #[tokio::test]
+    async fn test_inbound_limit() {
+        let (server, mut server_events) = TcpTransport::bind("127.0.0.1:0", &mut keys::generate_private_key(), SizeLimits::default()).await.unwrap();
+        let (client, mut client_events) = TcpTransport::bind("127.0.0.1:0", &mut keys::generate_private_key(), SizeLimits::default()).await.unwrap();
+        server.set_max_inbound(1);
+
+        let server_peer = client.dial(&server.local_addr()).await.unwrap();
+        next_event(&mut client_events).await;
+        next_event(&mut server_events).await;
+
+        // Connections over the limit are closed before the handshake
+        assert!(client.dial(&server.local_addr()).await.is_err());
+        assert_eq!(server.peers().len(), 1);
+
+        // Closing a connection makes room for another one
+        client.disconnect(server_peer);
+        next_event(&mut client_events).await;
+        assert!(matches!(next_event(&mut server_events).await, TransportEvent::Disconnected { .. }));
+        client.dial(&server.local_addr()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dial_unreachable_peer() {
+        let (client, _events) = TcpTransport::bind("127.0.0.1:0", &mut keys::generate_private_key(), SizeLimits::default()).await.unwrap();
+        let (server, _server_events) = TcpTransport::bind("127.0.0.1:0", &mut keys::generate_private_key(), SizeLimits::default()).await.unwrap();
+        let addr = server.local_addr();
+        drop(server);
+
+        assert!(client.dial(&addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message_disconnects_peer() {
+        let limits = SizeLimits {
+            max_message_size: 64 * 1024,
+            max_block_size: 32 * 1024,
+            max_transaction_size: 1024,
+        };
+        let (server, mut server_events) = TcpTransport::bind("127.0.0.1:0", &mut keys::generate_private_key(), limits).await.unwrap();
+        let (client, mut client_events) = TcpTransport::bind("127.0.0.1:0", &mut keys::generate_private_key(), SizeLimits::default()).await.unwrap();
+
+        let server_peer = client.dial(&server.local_addr()).await.unwrap();
+        next_event(&mut client_events).await;
+        let client_peer = match next_event(&mut server_events).await {
+            TransportEvent::Connected { peer, .. } => peer,
+            event => panic!("unexpected event {:?}", event),
+        };
+
+        let tx = proto::Transaction { data: vec![1; 2048], ..Default::default() };
+        let message = proto::Message {
+            payload: Some(proto::message::Payload::Transaction(tx)),
+        };
+        client.send(server_peer, message).await.unwrap();
+
+        assert_eq!(next_event(&mut server_events).await, TransportEvent::Disconnected { peer: client_peer });
+        assert_eq!(next_event(&mut client_events).await, TransportEvent::Disconnected { peer: server_peer });
+    }
+}
@@ -0,0 +1,72 @@
+use crate::crypto::keys::PublicKey;
+use crate::error::Result;
+use crate::proto;
+
+use std::future::Future;
+
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+/// PeerId identifies a connection with a peer for the lifetime of a transport
+pub type PeerId = u64;
+
+/// Number of events buffered by a transport before the connections stop reading from their peers
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+/// Number of outgoing messages buffered for every peer
+pub const PEER_CHANNEL_CAPACITY: usize = 256;
+
+/// TransportEvent is emitted by a transport on its event stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportEvent {
+    /// A connection with a peer was established, `inbound` is true when the peer dialed us
+    Connected { peer: PeerId, addr: String, inbound: bool },
+    /// A message was received from a peer
+    Message { peer: PeerId, message: proto::Message },
+    /// The connection with a peer was closed
+    Disconnected { peer: PeerId },
+}
+
+/// EventStream is the stream of events of a transport, consumed by the node
+pub type EventStream = mpsc::Receiver<TransportEvent>;
+
+/// Report that the connection with a peer was closed.
+/// The node drops the state of a peer on this event, so it is queued on a task of its own when the stream is full
+/// instead of being lost.
+pub(crate) fn report_disconnected(events: &mpsc::Sender<TransportEvent>, peer: PeerId) {
+    if let Err(TrySendError::Full(event)) = events.try_send(TransportEvent::Disconnected { peer }) {
+        let events = events.clone();
+        // Without a runtime the transport is being torn down and nothing reads the stream anymore
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let _ = events.send(event).await;
+            });
+        }
+    }
+}
+
+/// Transport is a trait that defines how the node connects and exchanges messages with its peers.
+/// A transport is created together with its `EventStream`, every connection, message and disconnection
+/// is reported on the stream in the order it happened for a given peer.
+pub trait Transport: Send + Sync + 'static {
+    /// Returns the address the transport accepts connections on
+    fn local_addr(&self) -> String;
+
+    /// Dial a peer, the connection is reported on the event stream before the future completes
+    fn dial(&self, addr: &str) -> impl Future<Output = Result<PeerId>> + Send;
+
+    /// Send a message to a connected peer
+    fn send(&self, peer: PeerId, message: proto::Message) -> impl Future<Output = Result<()>> + Send;
+
+    /// Send a message to a connected peer without waiting, fails if the outgoing queue of the peer is full
+    fn try_send(&self, peer: PeerId, message: proto::Message) -> Result<()>;
+
+    /// Close the connection with a peer
+    fn disconnect(&self, peer: PeerId);
+
+    /// Returns the peers currently connected
+    fn peers(&self) -> Vec<PeerId>;
+
+    /// Returns the identity key the connection with a peer is authenticated with,
+    /// `None` if the peer is not connected or the transport does not authenticate its connections
+    fn remote_public_key(&self, peer: PeerId) -> Option<PublicKey>;
+}
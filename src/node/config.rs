@@ -0,0 +1,449 @@
+use crate::core::blockchain::{DEFAULT_DIFFICULTY, MIN_DIFFICULTY};
+use crate::core::mempool::{DEFAULT_MAX_SIZE, DEFAULT_MAX_TRANSACTIONS, DEFAULT_TRANSACTION_TTL};
+use crate::core::miner::DEFAULT_BLOCK_INTERVAL;
+use crate::error::{Result, MarvinError};
+use crate::network::address_book::is_host_port;
+use crate::network::node::DEFAULT_CHAIN_ID;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Default directory the node keeps its data in
+pub const DEFAULT_DATA_DIR: &str = "data";
+/// Default minimum level of the log records written by the node
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+/// Default address the node accepts peer connections on
+pub const DEFAULT_P2P_LISTEN_ADDR: &str = "0.0.0.0:7878";
+/// Default address the JSON-RPC API is served on
+pub const DEFAULT_RPC_LISTEN_ADDR: &str = "127.0.0.1:8545";
+/// Prefix of the environment variables overriding the settings of the config file
+pub const ENV_PREFIX: &str = "MARVIN_";
+/// Keys of the settings that can be overridden by environment variables and command line flags.
+/// The environment variable of a setting is its key in upper case with dots replaced by underscores,
+/// prefixed with `ENV_PREFIX`, e.g. `MARVIN_RPC_LISTEN_ADDR`. Lists are comma separated.
+pub const SETTINGS: &[&str] = &[
+    "data_dir",
+    "log_level",
+    "chain.id",
+    "chain.difficulty",
+    "network.listen_addr",
+    "network.bootnodes",
+    "network.external_addr",
+    "rpc.listen_addr",
+    "rpc.grpc_addr",
+    "mining.enabled",
+    "mining.mnemonic",
+    "mining.interval_secs",
+    "mempool.ttl_secs",
+    "mempool.max_transactions",
+    "mempool.max_size",
+];
+
+/// NodeConfig holds the settings of a full node. Settings are layered: the defaults are overridden by the
+/// TOML config file, which is overridden by the environment variables, which are overridden by the command line.
+/// Every setting of the config file is optional and falls back to its default value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// Directory holding the blocks, the mempool, the address book and the identity key of the node
+    pub data_dir: PathBuf,
+    /// Minimum level of the log records: critical, error, warning, info, debug or trace
+    pub log_level: String,
+    pub chain: ChainSettings,
+    pub network: NetworkSettings,
+    pub rpc: RpcSettings,
+    pub mining: MiningSettings,
+    pub mempool: MempoolSettings,
+}
+
+/// ChainSettings are the parameters every node of a network must agree on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainSettings {
+    pub id: String,
+    /// Minimum number of leading zero bits of the hash of a block header.
+    /// Peers requiring another minimum are refused at the handshake.
+    pub difficulty: u32,
+}
+
+/// NetworkSettings are the peer-to-peer settings of the node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSettings {
+    pub listen_addr: String,
+    /// Addresses of the nodes to connect to on startup
+    pub bootnodes: Vec<String>,
+    /// Address advertised to peers, defaults to the listen address
+    pub external_addr: Option<String>,
+}
+
+/// RpcSettings are the settings of the APIs served to clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcSettings {
+    /// Address of the JSON-RPC API, over HTTP and WebSocket
+    pub listen_addr: String,
+    /// Address of the gRPC API, the gRPC API is disabled if not set
+    pub grpc_addr: Option<String>,
+}
+
+/// MiningSettings are the settings of the miner of the node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiningSettings {
+    pub enabled: bool,
+    /// Mnemonic of the key signing the mined blocks, the identity key of the node is used if not set
+    pub mnemonic: Option<String>,
+    /// Seconds between two mined blocks
+    pub interval_secs: u64,
+}
+
+/// MempoolSettings are the settings of the pool of pending transactions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MempoolSettings {
+    /// Seconds a transaction stays in the mempool before it is dropped, 0 keeps transactions until they are mined
+    pub ttl_secs: u64,
+    /// Maximum number of pending transactions
+    pub max_transactions: usize,
+    /// Maximum total size in bytes of the pending transactions
+    pub max_size: usize,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            data_dir: PathBuf::from(DEFAULT_DATA_DIR),
+            log_level: DEFAULT_LOG_LEVEL.to_string(),
+            chain: ChainSettings::default(),
+            network: NetworkSettings::default(),
+            rpc: RpcSettings::default(),
+            mining: MiningSettings::default(),
+            mempool: MempoolSettings::default(),
+        }
+    }
+}
+
+impl Default for ChainSettings {
+    fn default() -> Self {
+        ChainSettings {
+            id: DEFAULT_CHAIN_ID.to_string(),
+            difficulty: DEFAULT_DIFFICULTY,
+        }
+    }
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        NetworkSettings {
+            listen_addr: DEFAULT_P2P_LISTEN_ADDR.to_string(),
+            bootnodes: Vec::new(),
+            external_addr: None,
+        }
+    }
+}
+
+impl Default for RpcSettings {
+    fn default() -> Self {
+        RpcSettings {
+            listen_addr: DEFAULT_RPC_LISTEN_ADDR.to_string(),
+            grpc_addr: None,
+        }
+    }
+}
+
+impl Default for MiningSettings {
+    fn default() -> Self {
+        MiningSettings {
+            enabled: false,
+            mnemonic: None,
+            interval_secs: DEFAULT_BLOCK_INTERVAL.as_secs(),
+        }
+    }
+}
+
+impl Default for MempoolSettings {
+    fn default() -> Self {
+        MempoolSettings {
+            ttl_secs: DEFAULT_TRANSACTION_TTL.as_secs(),
+            max_transactions: DEFAULT_MAX_TRANSACTIONS,
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+}
+
+impl MempoolSettings {
+    /// Returns the maximum age of the transactions in the mempool, `None` if they never expire
+    pub fn ttl(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.ttl_secs)).filter(|ttl| !ttl.is_zero())
+    }
+}
+
+impl NodeConfig {
+    /// Build the configuration of the node from its layers: the config file if any, the environment variables
+    /// and the command line overrides, given as setting keys and values. The result is validated.
+    pub fn resolve<I>(path: Option<&Path>, env: I, overrides: &[(&str, String)]) -> Result<NodeConfig>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut config = match path {
+            Some(path) => NodeConfig::load(path)?,
+            None => NodeConfig::default(),
+        };
+
+        config.apply_env(env)?;
+        for (key, value) in overrides {
+            config.set(key, value)?;
+        }
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Load the configuration from a TOML file
+    pub fn load(path: &Path) -> Result<NodeConfig> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| MarvinError::Storage(format!("Failed to read the config file {}: {}", path.display(), e)))?;
+
+        NodeConfig::parse(&data)
+    }
+
+    /// Parse the configuration from a TOML document
+    pub fn parse(data: &str) -> Result<NodeConfig> {
+        toml::from_str(data).map_err(|e| MarvinError::Validation(format!("Invalid config: {}", e)))
+    }
+
+    /// Override the settings with the `MARVIN_*` variables found in the given environment
+    pub fn apply_env<I>(&mut self, env: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, value) in env {
+            if let Some(key) = SETTINGS.iter().find(|key| env_var(key) == name) {
+                self.set(key, &value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set a setting given its key, see `SETTINGS`. An empty value unsets the optional settings.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "data_dir" => self.data_dir = PathBuf::from(value),
+            "log_level" => self.log_level = value.to_string(),
+            "chain.id" => self.chain.id = value.to_string(),
+            "chain.difficulty" => self.chain.difficulty = parse_value(key, value)?,
+            "network.listen_addr" => self.network.listen_addr = value.to_string(),
+            "network.bootnodes" => self.network.bootnodes = parse_list(value),
+            "network.external_addr" => self.network.external_addr = optional(value),
+            "rpc.listen_addr" => self.rpc.listen_addr = value.to_string(),
+            "rpc.grpc_addr" => self.rpc.grpc_addr = optional(value),
+            "mining.enabled" => self.mining.enabled = parse_value(key, value)?,
+            "mining.mnemonic" => self.mining.mnemonic = optional(value),
+            "mining.interval_secs" => self.mining.interval_secs = parse_value(key, value)?,
+            "mempool.ttl_secs" => self.mempool.ttl_secs = parse_value(key, value)?,
+            "mempool.max_transactions" => self.mempool.max_transactions = parse_value(key, value)?,
+            "mempool.max_size" => self.mempool.max_size = parse_value(key, value)?,
+            _ => return Err(MarvinError::Validation(format!("Unknown setting {}", key))),
+        }
+
+        Ok(())
+    }
+
+    /// Check that every setting has a usable value
+    pub fn validate(&self) -> Result<()> {
+        if self.data_dir.as_os_str().is_empty() {
+            return Err(MarvinError::Validation(String::from("data_dir must not be empty")));
+        }
+        self.log_level()?;
+
+        if self.chain.id.trim().is_empty() {
+            return Err(MarvinError::Validation(String::from("chain.id must not be empty")));
+        }
+        if self.chain.difficulty < MIN_DIFFICULTY || self.chain.difficulty > 256 {
+            return Err(MarvinError::Validation(format!(
+                "chain.difficulty must be between {} and 256 bits, got {}", MIN_DIFFICULTY, self.chain.difficulty
+            )));
+        }
+
+        validate_socket_addr("network.listen_addr", &self.network.listen_addr)?;
+        for bootnode in self.network.bootnodes.iter() {
+            validate_host_port("network.bootnodes", bootnode)?;
+        }
+        if let Some(addr) = &self.network.external_addr {
+            validate_host_port("network.external_addr", addr)?;
+        }
+        validate_socket_addr("rpc.listen_addr", &self.rpc.listen_addr)?;
+        if let Some(addr) = &self.rpc.grpc_addr {
+            validate_socket_addr("rpc.grpc_addr", addr)?;
+        }
+
+        if let Some(mnemonic) = &self.mining.mnemonic {
+            bip39::Mnemonic::parse_normalized(mnemonic)
+                .map_err(|e| MarvinError::Validation(format!("Invalid mining.mnemonic: {}", e)))?;
+        }
+        if self.mining.enabled && self.mining.interval_secs == 0 {
+            return Err(MarvinError::Validation(String::from("mining.interval_secs must be greater than 0")));
+        }
+        if self.mempool.max_transactions == 0 || self.mempool.max_size == 0 {
+            return Err(MarvinError::Validation(String::from("mempool.max_transactions and mempool.max_size must be greater than 0")));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the minimum level of the log records
+    pub fn log_level(&self) -> Result<slog::Level> {
+        let name = match self.log_level.trim().to_lowercase().as_str() {
+            "warning" => String::from("warn"),
+            name => name.to_string(),
+        };
+
+        slog::Level::from_str(&name)
+            .map_err(|_| MarvinError::Validation(format!("Invalid log_level {}", self.log_level)))
+    }
+}
+
+/// Returns the environment variable overriding a setting
+pub fn env_var(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| MarvinError::Validation(format!("Invalid value {} for {}: {}", value, key, e)))
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
+}
+
+fn optional(value: &str) -> Option<String> {
+    Some(value.trim()).filter(|value| !value.is_empty()).map(String::from)
+}
+
+fn validate_socket_addr(key: &str, addr: &str) -> Result<()> {
+    addr.parse::<SocketAddr>()
+        .map(|_| ())
+        .map_err(|e| MarvinError::Validation(format!("Invalid {} {}: {}", key, addr, e)))
+}
+
+/// Check that an address is a host, name or IP, followed by a port, the same rule as the address book
+fn validate_host_port(key: &str, addr: &str) -> Result<()> {
+    if !is_host_port(addr) {
+        return Err(MarvinError::Validation(format!("Invalid {} {}, expected host:port", key, addr)));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = NodeConfig::parse(r#"
+            data_dir = "/var/lib/marvin"
+
+            [chain]
+            difficulty = 12
+
+            [network]
+            listen_addr = "0.0.0.0:9000"
+            bootnodes = ["10.0.0.1:7878"]
+
+            [mining]
+            enabled = true
+        "#).unwrap();
+
+        assert_eq!(config.data_dir, PathBuf::from("/var/lib/marvin"));
+        assert_eq!(config.chain, ChainSettings { id: DEFAULT_CHAIN_ID.to_string(), difficulty: 12 });
+        assert_eq!(config.network.bootnodes, vec![String::from("10.0.0.1:7878")]);
+        assert_eq!(config.rpc, RpcSettings::default());
+        assert!(config.mining.enabled);
+        assert_eq!(config.mining.interval_secs, DEFAULT_BLOCK_INTERVAL.as_secs());
+        assert_eq!(config.mempool.ttl(), Some(DEFAULT_TRANSACTION_TTL));
+        assert!(config.validate().is_ok());
+
+        let config = NodeConfig::parse("[mempool]\nttl_secs = 0\n").unwrap();
+        assert_eq!(config.mempool.ttl(), None);
+
+        assert_eq!(NodeConfig::parse("").unwrap(), NodeConfig::default());
+        assert!(matches!(NodeConfig::parse("unknown = 1"), Err(MarvinError::Validation(_))));
+        assert!(matches!(NodeConfig::load(Path::new("/nonexistent/marvin.toml")), Err(MarvinError::Storage(_))));
+    }
+
+    #[test]
+    fn test_layers() {
+        let path = std::env::temp_dir().join(format!("marvin-config-{}.toml", hex::encode(crate::crypto::keys::new_entropy())));
+        fs::write(&path, "log_level = \"debug\"\n[rpc]\nlisten_addr = \"127.0.0.1:9000\"\n[mining]\ninterval_secs = 5\n").unwrap();
+
+        let env = env(&[
+            ("MARVIN_RPC_LISTEN_ADDR", "127.0.0.1:9001"),
+            ("MARVIN_NETWORK_BOOTNODES", "seed.marvin.dev:7878, 10.0.0.2:7878"),
+            ("MARVIN_MINING_INTERVAL_SECS", "3"),
+            ("MARVIN_MEMPOOL_TTL_SECS", "600"),
+            ("HOME", "/root"),
+        ]);
+        let overrides = [("rpc.listen_addr", String::from("127.0.0.1:9002")), ("mining.enabled", String::from("true"))];
+        let config = NodeConfig::resolve(Some(&path), env, &overrides).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.log_level().unwrap(), slog::Level::Debug);
+        assert_eq!(NodeConfig { log_level: String::from("Warning"), ..Default::default() }.log_level().unwrap(), slog::Level::Warning);
+        assert_eq!(config.rpc.listen_addr, "127.0.0.1:9002");
+        assert_eq!(config.network.bootnodes, vec![String::from("seed.marvin.dev:7878"), String::from("10.0.0.2:7878")]);
+        assert_eq!(config.mining.interval_secs, 3);
+        assert_eq!(config.mempool.ttl(), Some(Duration::from_secs(600)));
+        assert!(config.mining.enabled);
+        assert_eq!(env_var("network.external_addr"), "MARVIN_NETWORK_EXTERNAL_ADDR");
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let invalid = [
+            ("log_level", "verbose"),
+            ("chain.id", " "),
+            ("chain.difficulty", "0"),
+            ("chain.difficulty", "300"),
+            ("network.listen_addr", "localhost"),
+            ("network.bootnodes", "10.0.0.1"),
+            ("rpc.grpc_addr", "127.0.0.1:99999"),
+            ("mining.mnemonic", "not a mnemonic"),
+            ("mempool.max_transactions", "0"),
+            ("mempool.max_size", "0"),
+        ];
+        for (key, value) in invalid {
+            let mut config = NodeConfig::default();
+            config.set(key, value).unwrap();
+            assert!(matches!(config.validate(), Err(MarvinError::Validation(_))), "{} = {}", key, value);
+        }
+
+        let mut config = NodeConfig::default();
+        assert!(config.set("chain.difficulty", "-1").is_err());
+        assert!(config.set("mining.enabled", "maybe").is_err());
+        assert!(config.set("mempool.ttl_secs", "-5").is_err());
+        assert!(config.set("unknown", "1").is_err());
+        assert!(NodeConfig::resolve(None, env(&[("MARVIN_MINING_INTERVAL_SECS", "soon")]), &[]).is_err());
+
+        config.set("mining.enabled", "true").unwrap();
+        config.set("mining.interval_secs", "0").unwrap();
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use crate::core::blockchain::Blockchain;
+use crate::core::miner::Miner;
+use crate::core::storage::FileStore;
+use crate::crypto::keys::{self, PrivateKey, SEED_SIZE};
+use crate::error::{Result, MarvinError};
+use crate::network::node::{NetworkConfig, Node};
+use crate::network::tcp::TcpTransport;
+use crate::network::transport::Transport;
+use crate::node::config::NodeConfig;
+use crate::rpc::grpc::GrpcServer;
+use crate::rpc::methods::RpcContext;
+use crate::rpc::server::RpcServer;
+use crate::types::limits::SizeLimits;
+use crate::utils::log::make_json_logger;
+
+use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// Files of the data directory of a node
+const NODE_KEY_FILE: &str = "node.key";
+const BLOCKS_FILE: &str = "blocks.dat";
+const MEMPOOL_FILE: &str = "mempool.dat";
+const ADDRESS_BOOK_FILE: &str = "address_book.json";
+/// Longest time between two sweeps of the expired transactions of the mempool
+const MEMPOOL_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// RunningNode is a full node started from a `NodeConfig`: the blockchain and its storage,
+/// the peer-to-peer node, the RPC servers and the miner, if mining is enabled.
+pub struct RunningNode {
+    blockchain: Arc<Mutex<Blockchain>>,
+    p2p_addr: String,
+    rpc: RpcServer,
+    grpc: Option<GrpcServer>,
+    node: JoinHandle<()>,
+    stop: oneshot::Sender<()>,
+    miner: Option<JoinHandle<()>>,
+    mempool_sweep: Option<JoinHandle<()>>,
+    data_dir: PathBuf,
+    logger: slog::Logger,
+}
+
+impl RunningNode {
+    /// Start a node from its configuration
+    pub async fn start(config: &NodeConfig) -> Result<RunningNode> {
+        let logger = make_json_logger();
+        fs::create_dir_all(&config.data_dir).map_err(|e| MarvinError::Storage(e.to_string()))?;
+
+        let mut node_key = load_or_create_node_key(&config.data_dir.join(NODE_KEY_FILE))?;
+        let miner_key = match (&config.mining.mnemonic, config.mining.enabled) {
+            (Some(mnemonic), true) => Some(keys::get_private_key_from_mnemonic(mnemonic)?),
+            (None, true) => Some(keys::new_private_key_from_seed(&node_key.to_bytes())?),
+            (_, false) => None,
+        };
+
+        let store = FileStore::open(&config.data_dir.join(BLOCKS_FILE))?;
+        let mut blockchain = Blockchain::open(Box::new(store), config.chain.difficulty)?;
+        blockchain.mempool.ttl = config.mempool.ttl();
+        blockchain.mempool.max_transactions = config.mempool.max_transactions;
+        blockchain.mempool.max_size = config.mempool.max_size;
+        let mempool_path = config.data_dir.join(MEMPOOL_FILE);
+        match blockchain.load_mempool(&mempool_path) {
+            Ok(loaded) => info!(logger, "Mempool loaded"; "transactions" => loaded),
+            Err(e) => warn!(logger, "Failed to load the mempool"; "error" => e.to_string()),
+        }
+        info!(logger, "Blockchain opened"; "height" => blockchain.height(), "data_dir" => config.data_dir.display().to_string());
+        let blockchain = Arc::new(Mutex::new(blockchain));
+
+        let (transport, events) = TcpTransport::bind(&config.network.listen_addr, &mut node_key, SizeLimits::default()).await?;
+        let p2p_addr = transport.local_addr();
+        let network_config = NetworkConfig {
+            chain_id: config.chain.id.clone(),
+            bootnodes: config.network.bootnodes.clone(),
+            external_addr: config.network.external_addr.clone(),
+            address_book_path: Some(config.data_dir.join(ADDRESS_BOOK_FILE)),
+            ..Default::default()
+        };
+        let node = Node::new(network_config, Arc::new(transport), blockchain.clone(), node_key);
+        let (stop, stopped) = oneshot::channel::<()>();
+        let node = tokio::spawn(node.run_until(events, async move {
+            let _ = stopped.await;
+        }));
+        info!(logger, "Peer-to-peer node started"; "addr" => &p2p_addr);
+
+        let context = RpcContext::new(blockchain.clone(), &config.chain.id);
+        let rpc = RpcServer::bind(&config.rpc.listen_addr, context.clone()).await?;
+        info!(logger, "JSON-RPC server started"; "addr" => rpc.local_addr().to_string());
+        let grpc = match &config.rpc.grpc_addr {
+            Some(addr) => {
+                let grpc = GrpcServer::bind(addr, context).await?;
+                info!(logger, "gRPC server started"; "addr" => grpc.local_addr().to_string());
+                Some(grpc)
+            }
+            None => None,
+        };
+
+        let miner = miner_key.map(|key| {
+            let interval = Duration::from_secs(config.mining.interval_secs);
+            info!(logger, "Miner started"; "address" => key.public_key().address().to_string(), "interval_secs" => interval.as_secs());
+            tokio::spawn(Miner::new(blockchain.clone(), key, interval).run())
+        });
+        // Blocks sweep the mempool as they are connected, the timer also drops expired transactions when no block comes
+        let mempool_sweep = config.mempool.ttl().map(|ttl| {
+            tokio::spawn(sweep_mempool(blockchain.clone(), ttl.min(MEMPOOL_SWEEP_INTERVAL), logger.clone()))
+        });
+
+        Ok(RunningNode {
+            blockchain,
+            p2p_addr,
+            rpc,
+            grpc,
+            node,
+            stop,
+            miner,
+            mempool_sweep,
+            data_dir: config.data_dir.clone(),
+            logger,
+        })
+    }
+
+    /// Returns the blockchain of the node
+    pub fn blockchain(&self) -> Arc<Mutex<Blockchain>> {
+        self.blockchain.clone()
+    }
+
+    /// Returns the address the node accepts peer connections on
+    pub fn p2p_addr(&self) -> &str {
+        &self.p2p_addr
+    }
+
+    /// Returns the address the JSON-RPC API is served on
+    pub fn rpc_addr(&self) -> SocketAddr {
+        self.rpc.local_addr()
+    }
+
+    /// Returns the address the gRPC API is served on, if it is enabled
+    pub fn grpc_addr(&self) -> Option<SocketAddr> {
+        self.grpc.as_ref().map(|grpc| grpc.local_addr())
+    }
+
+    /// Stop the node: the miner and the RPC servers are stopped first so the state no longer changes,
+    /// then the peer-to-peer node saves its address book and the mempool is saved to the data directory.
+    /// Blocks are written to the store as they are added, so they need no saving.
+    pub async fn shutdown(self) -> Result<()> {
+        info!(self.logger, "Shutting down the node");
+        for task in self.miner.into_iter().chain(self.mempool_sweep) {
+            task.abort();
+            let _ = task.await;
+        }
+        drop(self.rpc);
+        drop(self.grpc);
+
+        let _ = self.stop.send(());
+        let _ = self.node.await;
+
+        let saved = self.blockchain.lock().unwrap().mempool.save(&self.data_dir.join(MEMPOOL_FILE))?;
+        info!(self.logger, "Node stopped"; "mempool_transactions" => saved);
+
+        Ok(())
+    }
+}
+
+/// Start a node and run it until the process receives SIGINT or SIGTERM
+pub async fn run(config: NodeConfig) -> Result<()> {
+    crate::utils::log::set_log_level(config.log_level()?);
+    let node = RunningNode::start(&config).await?;
+    shutdown_signal().await?;
+
+    node.shutdown().await
+}
+
+/// Drop the transactions that outlived the TTL of the mempool at a regular interval
+async fn sweep_mempool(blockchain: Arc<Mutex<Blockchain>>, interval: Duration, logger: slog::Logger) {
+    let mut tick = tokio::time::interval(interval);
+    tick.tick().await;
+
+    loop {
+        tick.tick().await;
+        let dropped = blockchain.lock().unwrap().mempool.sweep_expired();
+        if dropped > 0 {
+            info!(logger, "Expired transactions dropped from the mempool"; "count" => dropped);
+        }
+    }
+}
+
+/// Wait for SIGINT, or SIGTERM on unix platforms
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).map_err(|e| MarvinError::Internal(e.to_string()))?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map_err(|e| MarvinError::Internal(e.to_string())),
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.map_err(|e| MarvinError::Internal(e.to_string()))
+}
+
+/// Load the identity key of the node, the key is generated and saved on the first start.
+/// The key file is only readable by the user running the node.
+fn load_or_create_node_key(path: &Path) -> Result<PrivateKey> {
+    match fs::read_to_string(path) {
+        Ok(data) => {
+            let seed: [u8; SEED_SIZE] = hex::decode(data.trim())
+                .ok()
+                .and_then(|seed| seed.try_into().ok())
+                .ok_or_else(|| MarvinError::Storage(format!("Invalid node key in {}", path.display())))?;
+            keys::new_private_key_from_seed(&seed)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let key = keys::generate_private_key();
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+            let mut file = options
+                .open(path)
+                .map_err(|e| MarvinError::Storage(format!("Failed to create the node key {}: {}", path.display(), e)))?;
+            file.write_all(key.to_string().as_bytes()).map_err(|e| MarvinError::Storage(e.to_string()))?;
+            Ok(key)
+        }
+        Err(e) => Err(MarvinError::Storage(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::config::{MiningSettings, NetworkSettings, RpcSettings};
+    use crate::proto;
+
+    fn config(data_dir: &Path, mining: bool) -> NodeConfig {
+        NodeConfig {
+            data_dir: data_dir.to_path_buf(),
+            network: NetworkSettings { listen_addr: String::from("127.0.0.1:0"), ..Default::default() },
+            rpc: RpcSettings { listen_addr: String::from("127.0.0.1:0"), grpc_addr: Some(String::from("127.0.0.1:0")) },
+            mining: MiningSettings { enabled: mining, mnemonic: None, interval_secs: 1 },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_start_and_restart() {
+        let data_dir = std::env::temp_dir().join(format!("marvin-node-{}", hex::encode(keys::new_entropy())));
+
+        let node = RunningNode::start(&config(&data_dir, true)).await.unwrap();
+        assert!(node.grpc_addr().is_some());
+        let mut blocks = node.blockchain().lock().unwrap().subscribe();
+        tokio::time::timeout(Duration::from_secs(10), blocks.recv()).await.unwrap().unwrap();
+        let tip = node.blockchain().lock().unwrap().headers.last().unwrap().clone();
+        let node_key = fs::read_to_string(data_dir.join(NODE_KEY_FILE)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(data_dir.join(NODE_KEY_FILE)).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        node.shutdown().await.unwrap();
+        assert!(data_dir.join(MEMPOOL_FILE).exists());
+        assert!(data_dir.join(ADDRESS_BOOK_FILE).exists());
+
+        // The mined blocks and the identity of the node survive a restart
+        let node = RunningNode::start(&config(&data_dir, false)).await.unwrap();
+        let height = node.blockchain().lock().unwrap().height();
+        assert!(height >= 1);
+        assert_eq!(node.blockchain().lock().unwrap().get_block_by_height(tip.height as usize).unwrap().header, Some(tip));
+        assert_eq!(fs::read_to_string(data_dir.join(NODE_KEY_FILE)).unwrap(), node_key);
+        node.shutdown().await.unwrap();
+
+        fs::write(data_dir.join(NODE_KEY_FILE), "not a key").unwrap();
+        assert!(RunningNode::start(&config(&data_dir, false)).await.is_err());
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mempool_sweep() {
+        let data_dir = std::env::temp_dir().join(format!("marvin-node-{}", hex::encode(keys::new_entropy())));
+        let mut config = config(&data_dir, false);
+        config.mempool.ttl_secs = 1;
+
+        let node = RunningNode::start(&config).await.unwrap();
+        assert_eq!(node.blockchain().lock().unwrap().mempool.ttl, Some(Duration::from_secs(1)));
+
+        let mut tx = proto::Transaction {
+            to: keys::generate_private_key().public_key().to_bytes().to_vec(),
+            ..Default::default()
+        };
+        crate::types::transaction::sign_transaction(&mut keys::generate_private_key(), &mut tx).unwrap();
+        node.blockchain().lock().unwrap().add_transaction(&tx).unwrap();
+
+        // No block is mined, the timer drops the transaction once it is older than the TTL
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !node.blockchain().lock().unwrap().mempool.is_empty() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        node.shutdown().await.unwrap();
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
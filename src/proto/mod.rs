@@ -1,4 +1 @@
-use prost;
-use prost::{Enumeration, Message};
-
 include!(concat!(env!("OUT_DIR"), "/proto.rs"));
\ No newline at end of file
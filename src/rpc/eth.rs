@@ -0,0 +1,254 @@
+use crate::core::blockchain::Blockchain;
+use crate::core::state::TransactionLocation;
+use crate::crypto::keys::Address;
+use crate::error::MarvinError;
+use crate::proto;
+use crate::rpc::jsonrpc::{RpcError, METHOD_NOT_FOUND};
+use crate::rpc::methods::{next_nonce, param, parse_address, parse_hex};
+use crate::types;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serde_json::{json, Value};
+
+/// Number of nanoseconds in a second, Marvin timestamps are in nanoseconds and Ethereum timestamps in seconds
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+/// Returns the numeric chain id reported to Ethereum tooling, the first 4 bytes of sha256(chain id).
+/// Every node of a network derives the same value, and it stays small enough for JavaScript clients.
+pub fn eth_chain_id(chain_id: &str) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.input_str(chain_id);
+
+    let mut hash = [0; 32];
+    hasher.result(&mut hash);
+
+    u32::from_be_bytes(hash[..4].try_into().unwrap()) as u64
+}
+
+/// Call a method of the Ethereum compatibility layer.
+/// Marvin keeps the state of the latest block only, so account queries accept the `latest` and `pending` tags
+/// or the current block number. Raw transactions are protobuf encoded Marvin transactions, not RLP, since Marvin
+/// transactions are signed with ed25519.
+pub fn call(blockchain: &mut Blockchain, chain_id: &str, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "eth_chainId" => Ok(json!(quantity(eth_chain_id(chain_id)))),
+        "eth_blockNumber" => Ok(json!(quantity(blockchain.height() as u64))),
+        "eth_getBlockByNumber" => {
+            let number = block_number(blockchain, &param::<String>(params, 0)?)?;
+            let full_transactions = optional_param(params, 1)?.unwrap_or(false);
+            get_block_by_number(blockchain, number, full_transactions)
+        }
+        "eth_getBalance" => {
+            let address = parse_address(&param::<String>(params, 0)?)?;
+            latest_state(blockchain, optional_param(params, 1)?)?;
+            Ok(json!(quantity(blockchain.state.account(&address).balance())))
+        }
+        "eth_getTransactionCount" => {
+            let address = parse_address(&param::<String>(params, 0)?)?;
+            match latest_state(blockchain, optional_param(params, 1)?)? {
+                true => Ok(json!(quantity(next_nonce(blockchain, &address)))),
+                false => Ok(json!(quantity(blockchain.state.account(&address).nonce))),
+            }
+        }
+        "eth_sendRawTransaction" => {
+            let tx = types::transaction::deserialize_transaction(&parse_hex(&param::<String>(params, 0)?)?)?;
+            let hash = blockchain.add_transaction(&tx)?;
+            Ok(json!(data(hash.as_bytes())))
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Method {} not found", method))),
+    }
+}
+
+/// Returns the positional parameter at the given index, `None` if it is missing or null
+fn optional_param<T: serde::de::DeserializeOwned>(params: &Value, index: usize) -> Result<Option<T>, RpcError> {
+    match params.as_array().and_then(|params| params.get(index)) {
+        None | Some(Value::Null) => Ok(None),
+        Some(_) => param(params, index).map(Some),
+    }
+}
+
+/// Returns the height of the block designated by a block tag or a hex block number
+fn block_number(blockchain: &Blockchain, tag: &str) -> Result<u64, RpcError> {
+    match tag {
+        "latest" | "pending" | "safe" | "finalized" => Ok(blockchain.height() as u64),
+        "earliest" => Ok(0),
+        number => {
+            let digits = number
+                .strip_prefix("0x")
+                .ok_or_else(|| RpcError::invalid_params(format!("Invalid block number {}", number)))?;
+            u64::from_str_radix(digits, 16).map_err(|e| RpcError::invalid_params(format!("Invalid block number {}: {}", number, e)))
+        }
+    }
+}
+
+/// Check that an account query targets the latest state, returns whether pending transactions should be included
+fn latest_state(blockchain: &Blockchain, tag: Option<String>) -> Result<bool, RpcError> {
+    let tag = tag.unwrap_or_else(|| String::from("latest"));
+    if tag == "pending" {
+        return Ok(true);
+    }
+
+    if block_number(blockchain, &tag)? != blockchain.height() as u64 {
+        return Err(RpcError::invalid_params("Only the state of the latest block is available"));
+    }
+
+    Ok(false)
+}
+
+fn get_block_by_number(blockchain: &Blockchain, number: u64, full_transactions: bool) -> Result<Value, RpcError> {
+    let block = match blockchain.get_block_by_height(number as usize) {
+        Ok(block) => block,
+        Err(MarvinError::NotFound(_)) => return Ok(Value::Null),
+        Err(e) => return Err(e.into()),
+    };
+
+    let header = block.header.clone().unwrap_or_default();
+    let hash = types::block::block_hash(&block);
+    let transactions: Vec<Value> = block
+        .transactions
+        .iter()
+        .enumerate()
+        .map(|(index, tx)| match full_transactions {
+            true => transaction(tx, TransactionLocation { block_hash: hash, height: header.height, index }),
+            false => json!(data(types::transaction::transaction_hash(tx).as_bytes())),
+        })
+        .collect();
+
+    Ok(json!({
+        "number": quantity(header.height),
+        "hash": data(hash.as_bytes()),
+        "parentHash": data(&header.prev_block_hash),
+        "nonce": format!("0x{:016x}", header.nonce),
+        "transactionsRoot": data(&header.tx_hash),
+        "miner": address(Address::from_public_key_bytes(&block.public_key).ok()),
+        "difficulty": quantity(header.difficulty as u64),
+        "timestamp": quantity((header.timestamp / NANOS_PER_SECOND).max(0) as u64),
+        "size": quantity(prost::Message::encoded_len(&block) as u64),
+        "extraData": "0x",
+        "gasLimit": quantity(0),
+        "gasUsed": quantity(0),
+        "uncles": [],
+        "transactions": transactions,
+    }))
+}
+
+fn transaction(tx: &proto::Transaction, location: TransactionLocation) -> Value {
+    json!({
+        "hash": data(types::transaction::transaction_hash(tx).as_bytes()),
+        "nonce": quantity(tx.nonce.max(0) as u64),
+        "blockHash": data(location.block_hash.as_bytes()),
+        "blockNumber": quantity(location.height),
+        "transactionIndex": quantity(location.index as u64),
+        "from": address(types::transaction::sender_address(tx)),
+        "to": address(types::transaction::recipient_address(tx)),
+        "value": quantity(tx.value),
+        "input": data(&tx.data),
+        "gas": quantity(0),
+        "gasPrice": quantity(0),
+    })
+}
+
+/// Encode a number as an Ethereum quantity, hex without leading zeros
+fn quantity(value: u64) -> String {
+    format!("0x{:x}", value)
+}
+
+/// Encode bytes as Ethereum data, 0x prefixed hex
+fn data(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn address(address: Option<Address>) -> Value {
+    match address {
+        Some(address) => json!(data(&address.to_bytes())),
+        None => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys;
+    use crate::rpc::jsonrpc::INVALID_PARAMS;
+    use crate::rpc::methods::tests::{context, mine_block, transfer};
+
+    use prost::Message;
+
+    fn call_eth(blockchain: &mut Blockchain, method: &str, params: Value) -> Result<Value, RpcError> {
+        call(blockchain, "marvin-test", method, &params)
+    }
+
+    #[test]
+    fn test_chain_id() {
+        assert_eq!(eth_chain_id("marvin-test"), eth_chain_id("marvin-test"));
+        assert_ne!(eth_chain_id("marvin-test"), eth_chain_id("marvin-devnet"));
+
+        let context = context();
+        let mut blockchain = context.blockchain.lock().unwrap();
+        assert_eq!(
+            call_eth(&mut blockchain, "eth_chainId", Value::Null).unwrap(),
+            json!(quantity(eth_chain_id("marvin-test")))
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        let mut alice = keys::generate_private_key();
+        let bob = keys::generate_private_key();
+        let context = context();
+        let mut blockchain = context.blockchain.lock().unwrap();
+
+        let tx = transfer(&mut alice, &bob, 0, 0);
+        let block = mine_block(&blockchain, vec![tx.clone()]);
+        blockchain.add_block(block.clone()).unwrap();
+
+        assert_eq!(call_eth(&mut blockchain, "eth_blockNumber", Value::Null).unwrap(), json!("0x1"));
+
+        let latest = call_eth(&mut blockchain, "eth_getBlockByNumber", json!(["latest", false])).unwrap();
+        assert_eq!(latest, call_eth(&mut blockchain, "eth_getBlockByNumber", json!(["0x1"])).unwrap());
+        assert_eq!(latest["hash"], json!(data(types::block::block_hash(&block).as_bytes())));
+        assert_eq!(latest["parentHash"], json!(format!("0x{}", hex::encode(blockchain.genesis_hash()))));
+        assert_eq!(latest["transactions"], json!([data(types::transaction::transaction_hash(&tx).as_bytes())]));
+
+        let full = call_eth(&mut blockchain, "eth_getBlockByNumber", json!(["0x1", true])).unwrap();
+        assert_eq!(full["transactions"][0]["from"], json!(data(&alice.public_key().address().to_bytes())));
+        assert_eq!(full["transactions"][0]["value"], json!("0x0"));
+        assert_eq!(full["transactions"][0]["blockNumber"], json!("0x1"));
+
+        let earliest = call_eth(&mut blockchain, "eth_getBlockByNumber", json!(["earliest", false])).unwrap();
+        assert_eq!(earliest["number"], json!("0x0"));
+        assert_eq!(call_eth(&mut blockchain, "eth_getBlockByNumber", json!(["0x2", false])).unwrap(), Value::Null);
+
+        let error = call_eth(&mut blockchain, "eth_getBlockByNumber", json!(["12", false])).unwrap_err();
+        assert_eq!(error.code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_accounts_and_transactions() {
+        let mut alice = keys::generate_private_key();
+        let bob = keys::generate_private_key();
+        let context = context();
+        let mut blockchain = context.blockchain.lock().unwrap();
+        let alice_address = data(&alice.public_key().address().to_bytes());
+        let bob_address = data(&bob.public_key().address().to_bytes());
+
+        let block = mine_block(&blockchain, vec![transfer(&mut alice, &bob, 0, 0)]);
+        blockchain.add_block(block).unwrap();
+
+        let tx = transfer(&mut alice, &bob, 0, 1);
+        let hash = call_eth(&mut blockchain, "eth_sendRawTransaction", json!([data(&tx.encode_to_vec())])).unwrap();
+        assert_eq!(hash, json!(data(types::transaction::transaction_hash(&tx).as_bytes())));
+
+        assert_eq!(call_eth(&mut blockchain, "eth_getBalance", json!([bob_address, "latest"])).unwrap(), json!("0x0"));
+        assert_eq!(call_eth(&mut blockchain, "eth_getBalance", json!([bob_address, "0x1"])).unwrap(), json!("0x0"));
+        assert_eq!(call_eth(&mut blockchain, "eth_getTransactionCount", json!([alice_address, "latest"])).unwrap(), json!("0x1"));
+        assert_eq!(call_eth(&mut blockchain, "eth_getTransactionCount", json!([alice_address, "pending"])).unwrap(), json!("0x2"));
+
+        // Historical state is not kept
+        let error = call_eth(&mut blockchain, "eth_getBalance", json!([bob_address, "earliest"])).unwrap_err();
+        assert_eq!(error.code, INVALID_PARAMS);
+
+        assert_eq!(call_eth(&mut blockchain, "eth_call", json!([])).unwrap_err().code, METHOD_NOT_FOUND);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::core::blockchain::Blockchain;
+use crate::crypto::keys::{Address, ADDRESS_SIZE};
+use crate::error::{Result, MarvinError};
+use crate::proto;
+use crate::proto::node_service_server::{NodeService, NodeServiceServer};
+use crate::rpc::methods::{next_nonce, RpcContext};
+use crate::types::hash::Hash;
+use crate::types::limits::SizeLimits;
+
+use prost::Message;
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Code, Request, Response, Status};
+
+use std::net::SocketAddr;
+use std::sync::MutexGuard;
+
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// Metadata key carrying the stable code of the error of a failed call, see `MarvinError::code`
+pub const ERROR_CODE_METADATA_KEY: &str = "marvin-error-code";
+
+/// GrpcServer serves the `NodeService` gRPC API of the node, the server stops when it is dropped
+pub struct GrpcServer {
+    local_addr: SocketAddr,
+    server: JoinHandle<()>,
+}
+
+impl GrpcServer {
+    /// Bind the server to the given address and start serving requests against the state of the node
+    pub async fn bind(addr: &str, context: RpcContext) -> Result<GrpcServer> {
+        let listener = TcpListener::bind(addr).await.map_err(|e| MarvinError::Network(e.to_string()))?;
+        let local_addr = listener.local_addr().map_err(|e| MarvinError::Network(e.to_string()))?;
+        let incoming = TcpIncoming::from_listener(listener, true, None).map_err(|e| MarvinError::Network(e.to_string()))?;
+
+        let service = NodeServiceServer::new(GrpcService { context });
+        let server = tokio::spawn(async move {
+            let _ = tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(incoming)
+                .await;
+        });
+
+        Ok(GrpcServer { local_addr, server })
+    }
+
+    /// Returns the address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for GrpcServer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// GrpcService implements the `NodeService` gRPC API on top of the blockchain
+pub struct GrpcService {
+    context: RpcContext,
+}
+
+impl GrpcService {
+    fn blockchain(&self) -> MutexGuard<'_, Blockchain> {
+        self.context.blockchain.lock().unwrap()
+    }
+}
+
+#[tonic::async_trait]
+impl NodeService for GrpcService {
+    async fn get_height(&self, _: Request<proto::GetHeightRequest>) -> std::result::Result<Response<proto::GetHeightResponse>, Status> {
+        let height = self.blockchain().height() as u64;
+
+        Ok(Response::new(proto::GetHeightResponse { height }))
+    }
+
+    async fn get_block_by_height(&self, request: Request<proto::GetBlockByHeightRequest>) -> std::result::Result<Response<proto::Block>, Status> {
+        let height = request.into_inner().height;
+
+        self.blockchain().get_block_by_height(height as usize).map(Response::new).map_err(status)
+    }
+
+    async fn get_block_by_hash(&self, request: Request<proto::GetBlockByHashRequest>) -> std::result::Result<Response<proto::Block>, Status> {
+        let hash = Hash::from_bytes(&request.into_inner().hash).map_err(status)?;
+
+        self.blockchain().get_block(&hash).map(Response::new).map_err(status)
+    }
+
+    async fn get_transaction(&self, request: Request<proto::GetTransactionRequest>) -> std::result::Result<Response<proto::GetTransactionResponse>, Status> {
+        let hash = Hash::from_bytes(&request.into_inner().hash).map_err(status)?;
+        let blockchain = self.blockchain();
+
+        if let Some(tx) = blockchain.mempool.get(&hash) {
+            return Ok(Response::new(proto::GetTransactionResponse {
+                transaction: Some(tx.clone()),
+                pending: true,
+                ..Default::default()
+            }));
+        }
+
+        let not_found = || status(MarvinError::NotFound(format!("Transaction {} not found", hash)));
+        let location = blockchain.state.transaction(&hash).ok_or_else(not_found)?;
+        let block = blockchain.get_block(&location.block_hash).map_err(status)?;
+        let tx = block.transactions.get(location.index).cloned().ok_or_else(not_found)?;
+
+        Ok(Response::new(proto::GetTransactionResponse {
+            transaction: Some(tx),
+            pending: false,
+            block_hash: location.block_hash.to_bytes().to_vec(),
+            block_height: location.height,
+            index: location.index as u32,
+        }))
+    }
+
+    async fn get_account(&self, request: Request<proto::GetAccountRequest>) -> std::result::Result<Response<proto::GetAccountResponse>, Status> {
+        let value = request
+            .into_inner()
+            .address
+            .try_into()
+            .map_err(|_| status(MarvinError::Validation(format!("Invalid address, expected {} bytes", ADDRESS_SIZE))))?;
+        let address = Address { value };
+        let blockchain = self.blockchain();
+        let account = blockchain.state.account(&address);
+
+        Ok(Response::new(proto::GetAccountResponse {
+            balance: account.balance(),
+            nonce: account.nonce,
+            pending_nonce: next_nonce(&blockchain, &address),
+        }))
+    }
+
+    async fn send_transaction(&self, request: Request<proto::Transaction>) -> std::result::Result<Response<proto::SendTransactionResponse>, Status> {
+        let tx = request.into_inner();
+        SizeLimits::default()
+            .check_transaction(&tx.encode_to_vec())
+            .map_err(status)?;
+
+        let hash = self.blockchain().add_transaction(&tx).map_err(status)?;
+
+        Ok(Response::new(proto::SendTransactionResponse { hash: hash.to_bytes().to_vec() }))
+    }
+}
+
+/// Convert an error of the node to a gRPC status, the stable code of the error is set in the metadata
+fn status(error: MarvinError) -> Status {
+    let code = match error {
+        MarvinError::Validation(_) | MarvinError::Crypto(_) => Code::InvalidArgument,
+        MarvinError::NotFound(_) => Code::NotFound,
+        MarvinError::MempoolRejected(_) => Code::FailedPrecondition,
+        MarvinError::Network(_) => Code::Unavailable,
+        MarvinError::NotImplemented(_) => Code::Unimplemented,
+        MarvinError::General(_) | MarvinError::Internal(_) | MarvinError::Storage(_) => Code::Internal,
+    };
+
+    let mut metadata = MetadataMap::new();
+    metadata.insert(ERROR_CODE_METADATA_KEY, error.code().into());
+
+    Status::with_metadata(code, error.to_string(), metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys;
+    use crate::proto::node_service_client::NodeServiceClient;
+    use crate::rpc::methods::tests::{context, mine_block, transfer};
+    use crate::types;
+
+    #[tokio::test]
+    async fn test_node_service() {
+        let context = context();
+        let server = GrpcServer::bind("127.0.0.1:0", context.clone()).await.unwrap();
+        let mut client = NodeServiceClient::connect(format!("http://{}", server.local_addr())).await.unwrap();
+
+        let mut alice = keys::generate_private_key();
+        let bob = keys::generate_private_key();
+        let block = {
+            let mut blockchain = context.blockchain.lock().unwrap();
+            let block = mine_block(&blockchain, vec![transfer(&mut alice, &bob, 0, 0)]);
+            blockchain.add_block(block.clone()).unwrap();
+            block
+        };
+
+        let height = client.get_height(proto::GetHeightRequest {}).await.unwrap().into_inner().height;
+        assert_eq!(height, 1);
+
+        let by_height = client.get_block_by_height(proto::GetBlockByHeightRequest { height: 1 }).await.unwrap().into_inner();
+        assert_eq!(by_height, block);
+        let hash = types::block::block_hash(&block).to_bytes().to_vec();
+        let by_hash = client.get_block_by_hash(proto::GetBlockByHashRequest { hash }).await.unwrap().into_inner();
+        assert_eq!(by_hash, block);
+        let missing = client.get_block_by_height(proto::GetBlockByHeightRequest { height: 2 }).await.unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+        assert_eq!(missing.metadata().get(ERROR_CODE_METADATA_KEY).unwrap(), "-32011");
+
+        let tx = transfer(&mut alice, &bob, 0, 1);
+        let hash = client.send_transaction(tx.clone()).await.unwrap().into_inner().hash;
+        assert_eq!(hash, types::transaction::transaction_hash(&tx).to_bytes().to_vec());
+
+        let pending = client.get_transaction(proto::GetTransactionRequest { hash }).await.unwrap().into_inner();
+        assert!(pending.pending);
+        assert_eq!(pending.transaction, Some(tx));
+
+        let mined_hash = types::transaction::transaction_hash(&block.transactions[0]).to_bytes().to_vec();
+        let mined = client.get_transaction(proto::GetTransactionRequest { hash: mined_hash }).await.unwrap().into_inner();
+        assert!(!mined.pending);
+        assert_eq!(mined.block_height, 1);
+
+        let address = alice.public_key().address().to_bytes().to_vec();
+        let account = client.get_account(proto::GetAccountRequest { address }).await.unwrap().into_inner();
+        assert_eq!((account.nonce, account.pending_nonce), (1, 2));
+        let address = bob.public_key().address().to_bytes().to_vec();
+        assert_eq!(client.get_account(proto::GetAccountRequest { address }).await.unwrap().into_inner().balance, 0);
+
+        // Transactions with an invalid signature are rejected
+        let mut forged = transfer(&mut alice, &bob, 0, 2);
+        forged.value = 1000;
+        assert_eq!(client.send_transaction(forged).await.unwrap_err().code(), Code::InvalidArgument);
+        let stale = client.send_transaction(transfer(&mut alice, &bob, 0, 0)).await.unwrap_err();
+        assert_eq!(stale.code(), Code::FailedPrecondition);
+        let invalid = client.get_account(proto::GetAccountRequest { address: vec![1; 4] }).await.unwrap_err();
+        assert_eq!(invalid.code(), Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::error::MarvinError;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Version of the JSON-RPC protocol served by the node
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// Standard JSON-RPC 2.0 error codes
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// RpcError is the error object of a JSON-RPC response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        RpcError::new(INVALID_PARAMS, message)
+    }
+}
+
+/// Errors of the node are returned with their stable code, see `MarvinError::code`
+impl From<MarvinError> for RpcError {
+    fn from(error: MarvinError) -> Self {
+        RpcError::new(error.code(), error.to_string())
+    }
+}
+
+/// Handle the body of a JSON-RPC request, either a single request or a batch, calling the methods with `call`.
+/// Returns `None` when there is nothing to answer, which is the case for notifications.
+pub fn handle_message<F>(body: &[u8], call: &mut F) -> Option<Value>
+where
+    F: FnMut(&str, &Value) -> Result<Value, RpcError>,
+{
+    let message: Value = match serde_json::from_slice(body) {
+        Ok(message) => message,
+        Err(e) => return Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))),
+    };
+
+    match message {
+        Value::Array(requests) if requests.is_empty() => {
+            Some(error_response(Value::Null, RpcError::new(INVALID_REQUEST, "Empty batch")))
+        }
+        Value::Array(requests) => {
+            let responses: Vec<Value> = requests
+                .into_iter()
+                .filter_map(|request| handle_request(request, call))
+                .collect();
+
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        request => handle_request(request, call),
+    }
+}
+
+/// Handle a single request, returning its response unless the request is a notification
+fn handle_request<F>(request: Value, call: &mut F) -> Option<Value>
+where
+    F: FnMut(&str, &Value) -> Result<Value, RpcError>,
+{
+    let id = request.get("id").cloned();
+
+    let (method, params) = match parse_request(&request) {
+        Ok(call) => call,
+        // Invalid requests are always answered, with a null id when it could not be read
+        Err(error) => return Some(error_response(id.unwrap_or(Value::Null), error)),
+    };
+
+    let result = call(method, params);
+
+    // Notifications have no id and get no response
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": JSONRPC_VERSION, "result": result, "id": id }),
+        Err(error) => error_response(id, error),
+    })
+}
+
+/// Returns the method and the parameters of a request
+fn parse_request(request: &Value) -> Result<(&str, &Value), RpcError> {
+    let invalid = |message: &str| RpcError::new(INVALID_REQUEST, message);
+
+    let request = request.as_object().ok_or_else(|| invalid("Request must be an object"))?;
+    if request.get("jsonrpc").and_then(Value::as_str) != Some(JSONRPC_VERSION) {
+        return Err(invalid("Unsupported JSON-RPC version"));
+    }
+    if !matches!(request.get("id"), None | Some(Value::Null | Value::Number(_) | Value::String(_))) {
+        return Err(invalid("Request id must be a number or a string"));
+    }
+
+    let method = request
+        .get("method")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("Request method must be a string"))?;
+    let params = match request.get("params") {
+        None => &Value::Null,
+        Some(params @ (Value::Array(_) | Value::Object(_))) => params,
+        Some(_) => return Err(invalid("Request params must be an array or an object")),
+    };
+
+    Ok((method, params))
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": JSONRPC_VERSION, "error": error, "id": id })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::methods::{self, tests::context, RpcContext};
+
+    fn handle(context: &RpcContext, body: &[u8]) -> Option<Value> {
+        handle_message(body, &mut |method, params| methods::call(context, method, params))
+    }
+
+    fn error_code(response: &Value) -> i64 {
+        response["error"]["code"].as_i64().unwrap()
+    }
+
+    #[test]
+    fn test_handle_request() {
+        let context = context();
+
+        let response = handle(&context, br#"{"jsonrpc":"2.0","method":"marvin_getHeight","id":7}"#).unwrap();
+        assert_eq!(response, json!({ "jsonrpc": "2.0", "result": 0, "id": 7 }));
+
+        // Notifications are executed without a response
+        assert_eq!(handle(&context, br#"{"jsonrpc":"2.0","method":"marvin_getHeight"}"#), None);
+    }
+
+    #[test]
+    fn test_handle_invalid_requests() {
+        let context = context();
+
+        let response = handle(&context, b"{not json").unwrap();
+        assert_eq!(error_code(&response), PARSE_ERROR);
+        assert_eq!(response["id"], Value::Null);
+
+        let response = handle(&context, br#"{"jsonrpc":"1.0","method":"marvin_getHeight","id":1}"#).unwrap();
+        assert_eq!(error_code(&response), INVALID_REQUEST);
+        assert_eq!(response["id"], json!(1));
+
+        let response = handle(&context, br#"{"jsonrpc":"2.0","method":"marvin_getHeight","params":3,"id":1}"#).unwrap();
+        assert_eq!(error_code(&response), INVALID_REQUEST);
+
+        let response = handle(&context, br#"{"jsonrpc":"2.0","method":"marvin_unknown","id":"a"}"#).unwrap();
+        assert_eq!(error_code(&response), METHOD_NOT_FOUND);
+        assert_eq!(response["id"], json!("a"));
+
+        let response = handle(&context, b"[]").unwrap();
+        assert_eq!(error_code(&response), INVALID_REQUEST);
+    }
+
+    #[test]
+    fn test_handle_batch() {
+        let context = context();
+        let batch = br#"[
+            {"jsonrpc":"2.0","method":"marvin_getHeight","id":1},
+            {"jsonrpc":"2.0","method":"marvin_getHeight"},
+            {"jsonrpc":"2.0","method":"marvin_getBlockByHeight","params":["zero"],"id":2},
+            5
+        ]"#;
+
+        let responses = handle(&context, batch).unwrap();
+        let responses = responses.as_array().unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["result"], json!(0));
+        assert_eq!(error_code(&responses[1]), INVALID_PARAMS);
+        assert_eq!(error_code(&responses[2]), INVALID_REQUEST);
+
+        // A batch made only of notifications gets no response
+        let batch = br#"[{"jsonrpc":"2.0","method":"marvin_getHeight"}]"#;
+        assert_eq!(handle(&context, batch), None);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::core::blockchain::Blockchain;
+use crate::crypto::keys::{Address, ADDRESS_SIZE};
+use crate::error::MarvinError;
+use crate::rpc::eth;
+use crate::rpc::jsonrpc::{RpcError, METHOD_NOT_FOUND};
+use crate::rpc::types::{RpcBlock, RpcTransaction};
+use crate::types;
+use crate::types::hash::Hash;
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use std::sync::{Arc, Mutex};
+
+/// RpcContext is the state of the node the RPC methods are served from
+#[derive(Clone)]
+pub struct RpcContext {
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    /// Identifier of the network the node belongs to
+    pub chain_id: String,
+}
+
+impl RpcContext {
+    pub fn new(blockchain: Arc<Mutex<Blockchain>>, chain_id: &str) -> Self {
+        RpcContext { blockchain, chain_id: chain_id.to_string() }
+    }
+}
+
+/// Call a method of the node API with its parameters.
+/// Parameters are positional, hashes, addresses and encoded transactions are hex strings with an optional `0x` prefix.
+/// Methods prefixed with `eth_` are served by the Ethereum compatibility layer.
+pub fn call(context: &RpcContext, method: &str, params: &Value) -> Result<Value, RpcError> {
+    let mut blockchain = context.blockchain.lock().unwrap();
+
+    if method.starts_with("eth_") {
+        return eth::call(&mut blockchain, &context.chain_id, method, params);
+    }
+
+    match method {
+        "marvin_getHeight" => Ok(json!(blockchain.height())),
+        "marvin_getBlockByHeight" => get_block_by_height(&blockchain, param(params, 0)?),
+        "marvin_getBlockByHash" => get_block_by_hash(&blockchain, parse_hash(&param::<String>(params, 0)?)?),
+        "marvin_getTransactionByHash" => get_transaction_by_hash(&blockchain, parse_hash(&param::<String>(params, 0)?)?),
+        "marvin_getBalance" => Ok(json!(blockchain.state.account(&parse_address(&param::<String>(params, 0)?)?).balance())),
+        "marvin_getNonce" => Ok(json!(next_nonce(&blockchain, &parse_address(&param::<String>(params, 0)?)?))),
+        "marvin_sendTransaction" => send_transaction(&mut blockchain, &param::<String>(params, 0)?),
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Method {} not found", method))),
+    }
+}
+
+fn get_block_by_height(blockchain: &Blockchain, height: usize) -> Result<Value, RpcError> {
+    match blockchain.get_block_by_height(height) {
+        Ok(block) => Ok(json!(RpcBlock::from(&block))),
+        Err(MarvinError::NotFound(_)) => Ok(Value::Null),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn get_block_by_hash(blockchain: &Blockchain, hash: Hash) -> Result<Value, RpcError> {
+    match blockchain.get_block(&hash) {
+        Ok(block) => Ok(json!(RpcBlock::from(&block))),
+        Err(MarvinError::NotFound(_)) => Ok(Value::Null),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns a pending transaction of the mempool or a transaction mined in the main chain
+fn get_transaction_by_hash(blockchain: &Blockchain, hash: Hash) -> Result<Value, RpcError> {
+    if let Some(tx) = blockchain.mempool.get(&hash) {
+        return Ok(json!(RpcTransaction::new(tx, None)));
+    }
+
+    let Some(location) = blockchain.state.transaction(&hash) else {
+        return Ok(Value::Null);
+    };
+    let block = blockchain.get_block(&location.block_hash)?;
+    match block.transactions.get(location.index) {
+        Some(tx) => Ok(json!(RpcTransaction::new(tx, Some(location)))),
+        None => Ok(Value::Null),
+    }
+}
+
+/// Returns the nonce of the next transaction of an address, accounting for its pending transactions
+pub fn next_nonce(blockchain: &Blockchain, address: &Address) -> u64 {
+    blockchain
+        .mempool
+        .transactions_for_address(address)
+        .into_iter()
+        .filter(|tx| types::transaction::sender_address(tx).as_ref() == Some(address))
+        .map(|tx| tx.nonce.max(0) as u64 + 1)
+        .fold(blockchain.state.account(address).nonce, u64::max)
+}
+
+/// Add a hex encoded transaction to the mempool, returning its hash
+fn send_transaction(blockchain: &mut Blockchain, data: &str) -> Result<Value, RpcError> {
+    let tx = types::transaction::deserialize_transaction(&parse_hex(data)?)?;
+    let hash = blockchain.add_transaction(&tx)?;
+
+    Ok(json!(hash.to_string()))
+}
+
+/// Returns the positional parameter at the given index
+pub fn param<T: DeserializeOwned>(params: &Value, index: usize) -> Result<T, RpcError> {
+    let value = params
+        .as_array()
+        .and_then(|params| params.get(index))
+        .ok_or_else(|| RpcError::invalid_params(format!("Missing parameter {}", index)))?;
+
+    serde_json::from_value(value.clone()).map_err(|e| RpcError::invalid_params(format!("Invalid parameter {}: {}", index, e)))
+}
+
+pub fn parse_hex(value: &str) -> Result<Vec<u8>, RpcError> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    hex::decode(value).map_err(|e| RpcError::invalid_params(format!("Invalid hex string: {}", e)))
+}
+
+pub fn parse_hash(value: &str) -> Result<Hash, RpcError> {
+    Hash::from_bytes(&parse_hex(value)?).map_err(|e| RpcError::invalid_params(e.to_string()))
+}
+
+pub fn parse_address(value: &str) -> Result<Address, RpcError> {
+    let bytes = parse_hex(value)?;
+    let value = bytes
+        .try_into()
+        .map_err(|_| RpcError::invalid_params(format!("Invalid address, expected {} bytes", ADDRESS_SIZE)))?;
+
+    Ok(Address { value })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::core::blockchain::DEFAULT_DIFFICULTY;
+    use crate::core::storage::MemoryStore;
+    use crate::crypto::keys;
+    use crate::proto;
+
+    use prost::Message;
+
+    pub fn context() -> RpcContext {
+        RpcContext::new(Arc::new(Mutex::new(Blockchain::new(Box::new(MemoryStore::new())))), "marvin-test")
+    }
+
+    pub fn transfer(from: &mut keys::PrivateKey, to: &keys::PrivateKey, value: u64, nonce: i64) -> proto::Transaction {
+        let mut tx = proto::Transaction {
+            from: from.public_key().to_bytes().to_vec(),
+            to: to.public_key().to_bytes().to_vec(),
+            value,
+            nonce,
+            ..Default::default()
+        };
+        types::transaction::sign_transaction(from, &mut tx).unwrap();
+
+        tx
+    }
+
+    pub fn mine_block(blockchain: &Blockchain, transactions: Vec<proto::Transaction>) -> proto::Block {
+        let mut block = proto::Block {
+            header: Some(proto::Header {
+                height: blockchain.height() as u64 + 1,
+                version: 1,
+                timestamp: Blockchain::get_current_timestamp_as_unix_nano() as i64,
+                prev_block_hash: types::block::hash_header(blockchain.headers.last().unwrap()),
+                difficulty: DEFAULT_DIFFICULTY,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        for tx in transactions {
+            types::block::add_transaction(&mut block, tx);
+        }
+        block.header.as_mut().unwrap().tx_hash = types::block::calculate_tx_hash(&block.transactions);
+        types::block::mine_header(block.header.as_mut().unwrap());
+        types::block::sign_block(&mut keys::generate_private_key(), &mut block).unwrap();
+
+        block
+    }
+
+    fn address_param(key: &keys::PrivateKey) -> Value {
+        json!([key.public_key().address().to_string()])
+    }
+
+    #[test]
+    fn test_blocks_and_transactions() {
+        let mut alice = keys::generate_private_key();
+        let bob = keys::generate_private_key();
+        let context = context();
+
+        let tx = transfer(&mut alice, &bob, 0, 0);
+        let tx_hash = types::transaction::transaction_hash(&tx).to_string();
+        let block = {
+            let mut blockchain = context.blockchain.lock().unwrap();
+            let block = mine_block(&blockchain, vec![tx]);
+            blockchain.add_block(block.clone()).unwrap();
+            block
+        };
+        let block_hash = types::block::block_hash(&block).to_string();
+
+        assert_eq!(call(&context, "marvin_getHeight", &Value::Null).unwrap(), json!(1));
+
+        let by_height = call(&context, "marvin_getBlockByHeight", &json!([1])).unwrap();
+        let by_hash = call(&context, "marvin_getBlockByHash", &json!([format!("0x{}", block_hash)])).unwrap();
+        assert_eq!(by_height, by_hash);
+        assert_eq!(by_height["hash"], json!(block_hash));
+        assert_eq!(by_height["transactions"][0]["hash"], json!(tx_hash));
+        assert_eq!(call(&context, "marvin_getBlockByHeight", &json!([2])).unwrap(), Value::Null);
+
+        let mined = call(&context, "marvin_getTransactionByHash", &json!([tx_hash])).unwrap();
+        assert_eq!(mined["blockHash"], json!(block_hash));
+        assert_eq!(mined["blockHeight"], json!(1));
+        assert_eq!(mined["index"], json!(0));
+
+        assert_eq!(call(&context, "marvin_getBalance", &address_param(&bob)).unwrap(), json!(0));
+        assert_eq!(call(&context, "marvin_getNonce", &address_param(&alice)).unwrap(), json!(1));
+
+        let error = call(&context, "marvin_getBlockByHash", &json!(["zz"])).unwrap_err();
+        assert_eq!(error.code, crate::rpc::jsonrpc::INVALID_PARAMS);
+        let error = call(&context, "marvin_getBalance", &json!(["abcd"])).unwrap_err();
+        assert_eq!(error.code, crate::rpc::jsonrpc::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_send_transaction() {
+        let mut alice = keys::generate_private_key();
+        let bob = keys::generate_private_key();
+        let context = context();
+
+        let tx = transfer(&mut alice, &bob, 0, 0);
+        let hash = call(&context, "marvin_sendTransaction", &json!([hex::encode(tx.encode_to_vec())])).unwrap();
+        assert_eq!(hash, json!(types::transaction::transaction_hash(&tx).to_string()));
+
+        let pending = call(&context, "marvin_getTransactionByHash", &json!([hash])).unwrap();
+        assert_eq!(pending["value"], json!(0));
+        assert_eq!(pending["blockHash"], Value::Null);
+
+        // The nonce accounts for the pending transactions of the sender
+        assert_eq!(call(&context, "marvin_getNonce", &address_param(&alice)).unwrap(), json!(1));
+        assert_eq!(call(&context, "marvin_getNonce", &address_param(&bob)).unwrap(), json!(0));
+
+        // Transactions with an invalid signature are rejected
+        let mut forged = transfer(&mut alice, &bob, 0, 1);
+        forged.value = 1000;
+        let error = call(&context, "marvin_sendTransaction", &json!([hex::encode(forged.encode_to_vec())])).unwrap_err();
+        assert_eq!(error.code, crate::error::CRYPTO_ERROR_CODE);
+
+        // Transactions sending more than the sender holds are rejected
+        let unfunded = transfer(&mut alice, &bob, 10, 1);
+        let error = call(&context, "marvin_sendTransaction", &json!([hex::encode(unfunded.encode_to_vec())])).unwrap_err();
+        assert_eq!(error.code, crate::error::MEMPOOL_REJECTED_ERROR_CODE);
+    }
+}
@@ -0,0 +1,7 @@
+pub mod eth;
+pub mod grpc;
+pub mod jsonrpc;
+pub mod methods;
+pub mod server;
+pub mod subscriptions;
+pub mod types;
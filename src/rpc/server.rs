@@ -0,0 +1,208 @@
+use crate::error::{Result, MarvinError};
+use crate::rpc::jsonrpc;
+use crate::rpc::methods::{self, RpcContext};
+use crate::rpc::subscriptions::Subscriptions;
+
+use axum::body::Bytes;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::Value;
+
+use std::net::SocketAddr;
+
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+/// RpcServer serves the JSON-RPC 2.0 API of the node over HTTP and WebSocket.
+/// Requests are POSTed to the root path, WebSocket connections are upgraded from a GET on the root path and can
+/// subscribe to new heads, pending transactions and address activity on top of the regular methods.
+/// The server stops when it is dropped.
+pub struct RpcServer {
+    local_addr: SocketAddr,
+    server: JoinHandle<()>,
+}
+
+impl RpcServer {
+    /// Bind the server to the given address and start serving requests against the state of the node
+    pub async fn bind(addr: &str, context: RpcContext) -> Result<RpcServer> {
+        let listener = TcpListener::bind(addr).await.map_err(|e| MarvinError::Network(e.to_string()))?;
+        let local_addr = listener.local_addr().map_err(|e| MarvinError::Network(e.to_string()))?;
+
+        let router = Router::new().route("/", get(upgrade).post(handle)).with_state(context);
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(listener, router).await;
+        });
+
+        Ok(RpcServer { local_addr, server })
+    }
+
+    /// Returns the address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn handle(State(context): State<RpcContext>, body: Bytes) -> Response {
+    match jsonrpc::handle_message(&body, &mut |method, params| methods::call(&context, method, params)) {
+        Some(response) => Json(response).into_response(),
+        // Notifications are acknowledged without a body
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+async fn upgrade(State(context): State<RpcContext>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| serve_socket(socket, context))
+}
+
+/// Serve the requests of a WebSocket connection and push the notifications of its subscriptions.
+/// A connection falling too far behind the events skips the ones it missed.
+async fn serve_socket(mut socket: WebSocket, context: RpcContext) {
+    let (mut chain_events, mut mempool_events) = {
+        let blockchain = context.blockchain.lock().unwrap();
+        (blockchain.subscribe(), blockchain.mempool.subscribe())
+    };
+    let mut subscriptions = Subscriptions::new();
+
+    loop {
+        let messages: Vec<Value> = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => jsonrpc::handle_message(text.as_bytes(), &mut |method, params| {
+                    subscriptions
+                        .call(method, params)
+                        .unwrap_or_else(|| methods::call(&context, method, params))
+                })
+                .into_iter()
+                .collect(),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = chain_events.recv() => match event {
+                Ok(event) => subscriptions.chain_notifications(&event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            event = mempool_events.recv() => match event {
+                Ok(event) => subscriptions.mempool_notifications(&event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        for message in messages {
+            if socket.send(Message::Text(message.to_string())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys;
+    use crate::rpc::methods::tests::{context, mine_block, transfer};
+    use crate::rpc::subscriptions::SUBSCRIPTION_METHOD;
+    use crate::types;
+
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite;
+
+    /// Send a JSON-RPC request over a plain HTTP/1.1 connection and return the status line and the body
+    async fn post(addr: SocketAddr, body: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            addr, body.len(), body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_serve_requests() {
+        let context = context();
+        let genesis_hash = hex::encode(context.blockchain.lock().unwrap().genesis_hash());
+        let server = RpcServer::bind("127.0.0.1:0", context).await.unwrap();
+
+        let request = json!({ "jsonrpc": "2.0", "method": "marvin_getBlockByHeight", "params": [0], "id": 1 });
+        let (status, body) = post(server.local_addr(), &request.to_string()).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+
+        let response: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(response["id"], json!(1));
+        assert_eq!(response["result"]["hash"], json!(genesis_hash));
+        assert_eq!(response["result"]["height"], json!(0));
+
+        let (status, body) = post(server.local_addr(), r#"{"jsonrpc":"2.0","method":"marvin_getHeight"}"#).await;
+        assert_eq!(status, "HTTP/1.1 204 No Content");
+        assert!(body.is_empty());
+    }
+
+    fn request(id: u64, method: &str, params: Value) -> tungstenite::Message {
+        let request = json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": id });
+        tungstenite::Message::Text(request.to_string())
+    }
+
+    /// Returns the next JSON message received on a WebSocket
+    async fn next<S>(socket: &mut S) -> Value
+    where
+        S: StreamExt<Item = tungstenite::Result<tungstenite::Message>> + Unpin,
+    {
+        let message = socket.next().await.unwrap().unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_websocket_subscriptions() {
+        let context = context();
+        let server = RpcServer::bind("127.0.0.1:0", context.clone()).await.unwrap();
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/", server.local_addr())).await.unwrap();
+
+        socket.send(request(1, "marvin_subscribe", json!(["newHeads"]))).await.unwrap();
+        socket.send(request(2, "marvin_subscribe", json!(["pendingTransactions"]))).await.unwrap();
+        socket.send(request(3, "marvin_getHeight", json!([]))).await.unwrap();
+
+        let heads = next(&mut socket).await["result"].clone();
+        let pending = next(&mut socket).await["result"].clone();
+        assert_eq!(next(&mut socket).await["result"], json!(0));
+
+        let mut alice = keys::generate_private_key();
+        let tx = transfer(&mut alice, &keys::generate_private_key(), 0, 0);
+        context.blockchain.lock().unwrap().add_transaction(&tx).unwrap();
+
+        let notification = next(&mut socket).await;
+        assert_eq!(notification["method"], json!(SUBSCRIPTION_METHOD));
+        assert_eq!(notification["params"]["subscription"], pending);
+        assert_eq!(notification["params"]["result"]["hash"], json!(types::transaction::transaction_hash(&tx).to_string()));
+
+        let block = {
+            let mut blockchain = context.blockchain.lock().unwrap();
+            let block = mine_block(&blockchain, vec![tx.clone()]);
+            blockchain.add_block(block.clone()).unwrap();
+            block
+        };
+
+        let notification = next(&mut socket).await;
+        assert_eq!(notification["params"]["subscription"], heads);
+        assert_eq!(notification["params"]["result"]["hash"], json!(types::block::block_hash(&block).to_string()));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::core::blockchain::ChainEvent;
+use crate::core::mempool::MempoolEvent;
+use crate::core::state::TransactionLocation;
+use crate::crypto::keys::Address;
+use crate::proto;
+use crate::rpc::jsonrpc::{RpcError, JSONRPC_VERSION};
+use crate::rpc::methods::{param, parse_address};
+use crate::rpc::types::{RpcHeader, RpcTransaction};
+use crate::types;
+
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+
+/// Method of the notifications sent to the subscribers
+pub const SUBSCRIPTION_METHOD: &str = "marvin_subscription";
+/// Maximum number of subscriptions of a single connection
+pub const MAX_SUBSCRIPTIONS: usize = 64;
+
+/// Subscription is a stream of events a WebSocket client asked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subscription {
+    /// Headers of the blocks connected at the tip of the chain
+    NewHeads,
+    /// Transactions added to the mempool
+    PendingTransactions,
+    /// Pending and mined transactions sent from or to an address
+    AddressActivity(Address),
+}
+
+impl Subscription {
+    /// Parse the parameters of a `marvin_subscribe` call: the kind of subscription followed by its arguments
+    pub fn from_params(params: &Value) -> Result<Subscription, RpcError> {
+        match param::<String>(params, 0)?.as_str() {
+            "newHeads" => Ok(Subscription::NewHeads),
+            "pendingTransactions" => Ok(Subscription::PendingTransactions),
+            "addressActivity" => Ok(Subscription::AddressActivity(parse_address(&param::<String>(params, 1)?)?)),
+            kind => Err(RpcError::invalid_params(format!("Unknown subscription {}", kind))),
+        }
+    }
+
+    fn involves(&self, tx: &proto::Transaction) -> bool {
+        match self {
+            Subscription::AddressActivity(address) => {
+                types::transaction::sender_address(tx).as_ref() == Some(address)
+                    || types::transaction::recipient_address(tx).as_ref() == Some(address)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Subscriptions are the active subscriptions of a WebSocket connection
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    next_id: u64,
+    active: BTreeMap<String, Subscription>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Subscriptions::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.active.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active.is_empty()
+    }
+
+    /// Handle `marvin_subscribe` and `marvin_unsubscribe`, returns `None` for the other methods
+    pub fn call(&mut self, method: &str, params: &Value) -> Option<Result<Value, RpcError>> {
+        match method {
+            "marvin_subscribe" => Some(Subscription::from_params(params).and_then(|subscription| self.subscribe(subscription))),
+            "marvin_unsubscribe" => Some(param::<String>(params, 0).map(|id| json!(self.unsubscribe(&id)))),
+            _ => None,
+        }
+    }
+
+    /// Add a subscription, returning its id
+    pub fn subscribe(&mut self, subscription: Subscription) -> Result<Value, RpcError> {
+        if self.active.len() >= MAX_SUBSCRIPTIONS {
+            return Err(RpcError::invalid_params(format!("Too many subscriptions, the maximum is {}", MAX_SUBSCRIPTIONS)));
+        }
+
+        self.next_id += 1;
+        let id = format!("0x{:x}", self.next_id);
+        self.active.insert(id.clone(), subscription);
+
+        Ok(json!(id))
+    }
+
+    /// Remove a subscription, returns false if there is no subscription with the given id
+    pub fn unsubscribe(&mut self, id: &str) -> bool {
+        self.active.remove(id).is_some()
+    }
+
+    /// Returns the notifications of the subscriptions interested in a chain event
+    pub fn chain_notifications(&self, event: &ChainEvent) -> Vec<Value> {
+        let ChainEvent::BlockConnected { hash, height, block } = event else {
+            return Vec::new();
+        };
+
+        let mut notifications = Vec::new();
+        for (id, subscription) in self.active.iter() {
+            match subscription {
+                Subscription::NewHeads => {
+                    if let Some(header) = block.header.as_ref() {
+                        notifications.push(notification(id, json!(RpcHeader::from(header))));
+                    }
+                }
+                Subscription::AddressActivity(_) => {
+                    for (index, tx) in block.transactions.iter().enumerate().filter(|(_, tx)| subscription.involves(tx)) {
+                        let location = TransactionLocation { block_hash: *hash, height: *height, index };
+                        notifications.push(notification(id, json!(RpcTransaction::new(tx, Some(location)))));
+                    }
+                }
+                Subscription::PendingTransactions => {}
+            }
+        }
+
+        notifications
+    }
+
+    /// Returns the notifications of the subscriptions interested in a mempool event
+    pub fn mempool_notifications(&self, event: &MempoolEvent) -> Vec<Value> {
+        let tx = match event {
+            MempoolEvent::Added { tx, .. } | MempoolEvent::Replaced { tx, .. } => tx,
+            _ => return Vec::new(),
+        };
+
+        self.active
+            .iter()
+            .filter(|(_, subscription)| **subscription == Subscription::PendingTransactions || subscription.involves(tx))
+            .map(|(id, _)| notification(id, json!(RpcTransaction::new(tx, None))))
+            .collect()
+    }
+}
+
+fn notification(id: &str, result: Value) -> Value {
+    json!({
+        "jsonrpc": JSONRPC_VERSION,
+        "method": SUBSCRIPTION_METHOD,
+        "params": { "subscription": id, "result": result },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys;
+    use crate::rpc::methods::tests::transfer;
+
+    #[test]
+    fn test_subscribe_and_unsubscribe() {
+        let mut subscriptions = Subscriptions::new();
+
+        let id = subscriptions.call("marvin_subscribe", &json!(["newHeads"])).unwrap().unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert!(subscriptions.call("marvin_subscribe", &json!(["addressActivity"])).unwrap().is_err());
+        assert!(subscriptions.call("marvin_subscribe", &json!(["logs"])).unwrap().is_err());
+        assert!(subscriptions.call("marvin_getHeight", &Value::Null).is_none());
+
+        assert_eq!(subscriptions.call("marvin_unsubscribe", &json!([id])).unwrap(), Ok(json!(true)));
+        assert_eq!(subscriptions.call("marvin_unsubscribe", &json!([id])).unwrap(), Ok(json!(false)));
+        assert!(subscriptions.is_empty());
+
+        for _ in 0..MAX_SUBSCRIPTIONS {
+            subscriptions.subscribe(Subscription::PendingTransactions).unwrap();
+        }
+        assert!(subscriptions.subscribe(Subscription::NewHeads).is_err());
+    }
+
+    #[test]
+    fn test_notifications() {
+        let mut alice = keys::generate_private_key();
+        let bob = keys::generate_private_key();
+        let carol = keys::generate_private_key();
+
+        let mut subscriptions = Subscriptions::new();
+        let heads = subscriptions.subscribe(Subscription::NewHeads).unwrap();
+        let pending = subscriptions.subscribe(Subscription::PendingTransactions).unwrap();
+        let bob_activity = subscriptions.subscribe(Subscription::AddressActivity(bob.public_key().address())).unwrap();
+        subscriptions.subscribe(Subscription::AddressActivity(carol.public_key().address())).unwrap();
+
+        let tx = transfer(&mut alice, &bob, 5, 0);
+        let hash = types::transaction::transaction_hash(&tx);
+
+        let notifications = subscriptions.mempool_notifications(&MempoolEvent::Added { hash, tx: tx.clone() });
+        let ids: Vec<&Value> = notifications.iter().map(|n| &n["params"]["subscription"]).collect();
+        assert_eq!(ids, vec![&pending, &bob_activity]);
+        assert_eq!(notifications[0]["method"], json!(SUBSCRIPTION_METHOD));
+        assert_eq!(notifications[0]["params"]["result"]["hash"], json!(hash.to_string()));
+
+        let block = proto::Block {
+            header: Some(proto::Header { height: 3, ..Default::default() }),
+            transactions: vec![tx],
+            ..Default::default()
+        };
+        let event = ChainEvent::BlockConnected { hash: types::block::block_hash(&block), height: 3, block };
+        let notifications = subscriptions.chain_notifications(&event);
+        let ids: Vec<&Value> = notifications.iter().map(|n| &n["params"]["subscription"]).collect();
+        assert_eq!(ids, vec![&heads, &bob_activity]);
+        assert_eq!(notifications[0]["params"]["result"]["height"], json!(3));
+        assert_eq!(notifications[1]["params"]["result"]["blockHeight"], json!(3));
+
+        assert!(subscriptions.mempool_notifications(&MempoolEvent::Mined { hash, height: 3 }).is_empty());
+    }
+}
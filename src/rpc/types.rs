@@ -0,0 +1,105 @@
+use crate::core::state::TransactionLocation;
+use crate::proto;
+use crate::types;
+
+use serde::{Deserialize, Serialize};
+
+/// RpcHeader is the JSON representation of a block header, bytes are hex encoded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcHeader {
+    pub hash: String,
+    pub height: u64,
+    pub prev_block_hash: String,
+    pub tx_hash: String,
+    pub version: u32,
+    pub timestamp: i64,
+    pub nonce: u64,
+    pub difficulty: u32,
+}
+
+/// RpcBlock is the JSON representation of a block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBlock {
+    #[serde(flatten)]
+    pub header: RpcHeader,
+    pub public_key: String,
+    pub signature: String,
+    pub transactions: Vec<RpcTransaction>,
+}
+
+/// RpcTransaction is the JSON representation of a transaction. The block fields are only set once the transaction is mined.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcTransaction {
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub value: u64,
+    pub data: String,
+    pub nonce: i64,
+    pub signature: String,
+    pub valid_until_height: u64,
+    pub valid_until_timestamp: i64,
+    pub block_hash: Option<String>,
+    pub block_height: Option<u64>,
+    pub index: Option<usize>,
+}
+
+impl From<&proto::Header> for RpcHeader {
+    fn from(header: &proto::Header) -> Self {
+        RpcHeader {
+            hash: hex::encode(types::block::hash_header(header)),
+            height: header.height,
+            prev_block_hash: hex::encode(&header.prev_block_hash),
+            tx_hash: hex::encode(&header.tx_hash),
+            version: header.version,
+            timestamp: header.timestamp,
+            nonce: header.nonce,
+            difficulty: header.difficulty,
+        }
+    }
+}
+
+impl From<&proto::Block> for RpcBlock {
+    fn from(block: &proto::Block) -> Self {
+        let header = block.header.clone().unwrap_or_default();
+        let block_hash = types::block::block_hash(block);
+
+        RpcBlock {
+            header: RpcHeader::from(&header),
+            public_key: hex::encode(&block.public_key),
+            signature: hex::encode(&block.signature),
+            transactions: block
+                .transactions
+                .iter()
+                .enumerate()
+                .map(|(index, tx)| {
+                    let location = TransactionLocation { block_hash, height: header.height, index };
+                    RpcTransaction::new(tx, Some(location))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl RpcTransaction {
+    /// Create the JSON representation of a transaction, along with its location if it is mined
+    pub fn new(tx: &proto::Transaction, location: Option<TransactionLocation>) -> Self {
+        RpcTransaction {
+            hash: types::transaction::transaction_hash(tx).to_string(),
+            from: hex::encode(&tx.from),
+            to: hex::encode(&tx.to),
+            value: tx.value,
+            data: hex::encode(&tx.data),
+            nonce: tx.nonce,
+            signature: hex::encode(&tx.signature),
+            valid_until_height: tx.valid_until_height,
+            valid_until_timestamp: tx.valid_until_timestamp,
+            block_hash: location.map(|location| location.block_hash.to_string()),
+            block_height: location.map(|location| location.height),
+            index: location.map(|location| location.index),
+        }
+    }
+}
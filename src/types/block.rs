@@ -1,7 +1,7 @@
 
 use crate::crypto::keys::{PrivateKey, PublicKey, SignatureWrapper};
 use crate::crypto::keys::{SIGNATURE_SIZE, PUBLIC_KEY_SIZE};
-use crate::proto::{self, Transaction};
+use crate::proto;
 
 use crate::error::{Result, MarvinError};
 
@@ -11,37 +11,45 @@ use prost::Message;
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
 
-use super::transaction::hash_transaction;
+use super::hash::Hash;
+use super::limits::SizeLimits;
+use super::transaction::calculate_transaction_hash;
 
 
 /// Serialize a header
 pub fn serialize_header(h : proto::Header) -> Result<Vec<u8>> {
     let mut buf = Vec::new();
-    h.encode(&mut buf).map_err(|e| MarvinError::General(e.to_string()))?;
+    h.encode(&mut buf).map_err(|e| MarvinError::Internal(e.to_string()))?;
     Ok(buf)
 }
 
 /// Deserialize a header
 pub fn deserialize_header(data: &[u8]) -> Result<proto::Header> {
-    proto::Header::decode(data).map_err(|e| MarvinError::General(e.to_string()))
+    proto::Header::decode(data).map_err(|e| MarvinError::Validation(e.to_string()))
 }
 
 /// Serialize a block
 pub fn serialize_block(b : proto::Block) -> Result<Vec<u8>> {
     let mut buf = Vec::new();
-    b.encode(&mut buf).map_err(|e| MarvinError::General(e.to_string()))?;
+    b.encode(&mut buf).map_err(|e| MarvinError::Internal(e.to_string()))?;
     Ok(buf)
 }
 
-/// Deserialize a block
+/// Deserialize a block, rejecting blocks and transactions larger than the default size limits
 pub fn deserialize_block(data: &[u8]) -> Result<proto::Block> {
-    proto::Block::decode(data).map_err(|e| MarvinError::General(e.to_string()))
+    deserialize_block_with_limits(data, &SizeLimits::default())
+}
+
+/// Deserialize a block, the sizes of the block and of its transactions are checked before decoding
+pub fn deserialize_block_with_limits(data: &[u8], limits: &SizeLimits) -> Result<proto::Block> {
+    limits.check_block(data)?;
+    proto::Block::decode(data).map_err(|e| MarvinError::Validation(e.to_string()))
 }
 
 /// Sign a block
-pub fn sign_block(private_key: &mut PrivateKey, b: &mut proto::Block) -> Result<(SignatureWrapper)> {
+pub fn sign_block(private_key: &mut PrivateKey, b: &mut proto::Block) -> Result<SignatureWrapper> {
     let hash = hash_block(b);
-    let signature = private_key.sign(&hash).map_err(|e| MarvinError::General(e.to_string()))?;
+    let signature = private_key.sign(&hash).map_err(|e| MarvinError::Crypto(e.to_string()))?;
 
     b.signature = signature.to_bytes().to_vec();
     b.public_key = private_key.public_key().to_bytes().to_vec();
@@ -56,19 +64,19 @@ pub fn verify_block(b: &proto::Block) -> Result<bool> {
     // TODO: Validate Transactions
 
     if b.signature.is_empty() || b.public_key.is_empty() {
-        return Err(MarvinError::General(String::from("Block is not signed".to_string())));
+        return Err(MarvinError::Crypto(String::from("Block is not signed")));
     }
 
     if b.signature.len() != SIGNATURE_SIZE {
-        return Err(MarvinError::General(String::from("Invalid signature size".to_string())));
+        return Err(MarvinError::Crypto(String::from("Invalid signature size")));
     }
 
     if b.public_key.len() != PUBLIC_KEY_SIZE {
-        return Err(MarvinError::General(String::from("Invalid public key size".to_string())));
+        return Err(MarvinError::Crypto(String::from("Invalid public key size")));
     }
 
-    let signature = SignatureWrapper::from_bytes(&b.signature).unwrap();
-    let public_key = PublicKey::from_bytes(&b.public_key).unwrap();
+    let signature = SignatureWrapper::from_bytes(&b.signature)?;
+    let public_key = PublicKey::from_bytes(&b.public_key)?;
     let hash = hash_block(b);
     let is_valid = signature.verify(&hash, &public_key);
 
@@ -92,20 +100,25 @@ pub fn hash_block(b: &proto::Block) -> Vec<u8> {
     hash_header(b.header.as_ref().unwrap())
 }
 
+/// Returns the hash of a block as a typed `Hash`
+pub fn block_hash(b: &proto::Block) -> Hash {
+    Hash::from_bytes(&hash_block(b)).unwrap()
+}
+
 /// Add a transaction to a block
 pub fn add_transaction(b: &mut proto::Block, tx: proto::Transaction) {
     b.transactions.push(tx);
-    let hash = calculate_tx_hash(&mut b.transactions);
+    let hash = calculate_tx_hash(&b.transactions);
     
     b.header.as_mut().unwrap().tx_hash = hash;
 }
 
-/// Calculate the hash of a list of transactions
-pub fn calculate_tx_hash(txs : &mut Vec<proto::Transaction>) -> Vec<u8> {
+/// Calculate the hash of a list of transactions, committed to by the `tx_hash` of the block header
+pub fn calculate_tx_hash(txs : &[proto::Transaction]) -> Vec<u8> {
     let mut hasher = Sha256::new();
     
-    for tx in txs.iter_mut() {
-        let data = hash_transaction(tx);
+    for tx in txs.iter() {
+        let data = calculate_transaction_hash(tx);
         hasher.input(&data);
     }
 
@@ -115,6 +128,31 @@ pub fn calculate_tx_hash(txs : &mut Vec<proto::Transaction>) -> Vec<u8> {
     hash.to_vec()
 }
 
+/// Returns the number of leading zero bits of a hash
+pub fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+
+    bits
+}
+
+/// Check if the hash of a header has at least as many leading zero bits as its difficulty
+pub fn meets_difficulty(h: &proto::Header) -> bool {
+    leading_zero_bits(&hash_header(h)) >= h.difficulty
+}
+
+/// Mine a header, incrementing its nonce until its hash meets its difficulty
+pub fn mine_header(h: &mut proto::Header) {
+    while !meets_difficulty(h) {
+        h.nonce = h.nonce.wrapping_add(1);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,6 +216,8 @@ mod tests {
             signature: [0; 64].to_vec(),
             nonce: 123,
             hash: [0; 32].to_vec(),
+            valid_until_height: 0,
+            valid_until_timestamp: 0,
         };
 
         let block = proto::Block {
@@ -234,6 +274,8 @@ mod tests {
             signature: [0; 64].to_vec(),
             nonce: 123,
             hash: [0; 32].to_vec(),
+            valid_until_height: 0,
+            valid_until_timestamp: 0,
         };
 
         add_transaction(&mut block, tx);
@@ -248,5 +290,32 @@ mod tests {
         assert!(is_valid);
 
     }
-    
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tx_hash_is_deterministic() {
+        let mut block = proto::Block {
+            header: Some(proto::Header::default()),
+            ..Default::default()
+        };
+        add_transaction(&mut block, proto::Transaction { nonce: 1, ..Default::default() });
+        add_transaction(&mut block, proto::Transaction { nonce: 2, ..Default::default() });
+
+        assert_eq!(block.header.as_ref().unwrap().tx_hash, calculate_tx_hash(&block.transactions));
+    }
+
+    #[test]
+    fn test_mine_header() {
+        assert_eq!(leading_zero_bits(&[0, 0, 0b0010_0000, 0xff]), 18);
+        assert_eq!(leading_zero_bits(&[0; 4]), 32);
+
+        let mut header = proto::Header {
+            height: 1,
+            difficulty: 8,
+            ..Default::default()
+        };
+        mine_header(&mut header);
+
+        assert!(meets_difficulty(&header));
+        assert_eq!(hash_header(&header)[0], 0);
+    }
+}
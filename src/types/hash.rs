@@ -0,0 +1,74 @@
+use crate::error::{Result, MarvinError};
+
+use std::fmt;
+use std::str::FromStr;
+
+pub const HASH_SIZE: usize = 32;
+
+/// Hash is a 32 byte SHA-256 digest identifying a block or a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Hash(pub [u8; HASH_SIZE]);
+
+impl Hash {
+    /// Create a hash from a slice, which must be exactly 32 bytes long
+    pub fn from_bytes(bytes: &[u8]) -> Result<Hash> {
+        let value: [u8; HASH_SIZE] = bytes
+            .try_into()
+            .map_err(|_| MarvinError::Validation(format!("Invalid hash size, expected {} bytes.", HASH_SIZE)))?;
+
+        Ok(Hash(value))
+    }
+
+    /// Convert the hash to bytes
+    pub fn to_bytes(self) -> [u8; HASH_SIZE] {
+        self.0
+    }
+
+    /// Returns the hash as a byte slice
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Hash {
+    /// Formats the hash as a string in hex format
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for Hash {
+    type Err = MarvinError;
+
+    /// Parses a hash from a string in hex format, with or without a `0x` prefix
+    fn from_str(s: &str) -> Result<Hash> {
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        let bytes = hex::decode(s).map_err(|e| MarvinError::Validation(e.to_string()))?;
+
+        Hash::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_from_bytes() {
+        let hash = Hash::from_bytes(&[7; HASH_SIZE]).unwrap();
+        assert_eq!(hash.to_bytes(), [7; HASH_SIZE]);
+
+        assert!(Hash::from_bytes(&[7; 31]).is_err());
+    }
+
+    #[test]
+    fn test_hash_hex_roundtrip() {
+        let hash = Hash([0xab; HASH_SIZE]);
+        let hex_string = hash.to_string();
+
+        assert_eq!(hex_string, "ab".repeat(HASH_SIZE));
+        assert_eq!(hex_string.parse::<Hash>().unwrap(), hash);
+        assert_eq!(format!("0x{}", hex_string).parse::<Hash>().unwrap(), hash);
+        assert!("zz".parse::<Hash>().is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use crate::error::{Result, MarvinError};
+
+/// Default maximum size of an encoded network message
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+/// Default maximum size of an encoded block
+pub const DEFAULT_MAX_BLOCK_SIZE: usize = 2 * 1024 * 1024;
+/// Default maximum size of an encoded transaction
+pub const DEFAULT_MAX_TRANSACTION_SIZE: usize = 64 * 1024;
+
+/// Field numbers of the messages checked by the limits, see the proto files
+const MESSAGE_BLOCK_FIELD: u32 = 7;
+const MESSAGE_TRANSACTION_FIELD: u32 = 8;
+const MESSAGE_COMPACT_BLOCK_FIELD: u32 = 13;
+const MESSAGE_BLOCK_TRANSACTIONS_FIELD: u32 = 15;
+const BLOCK_TRANSACTIONS_FIELD: u32 = 2;
+const BLOCK_TRANSACTIONS_TRANSACTIONS_FIELD: u32 = 2;
+
+/// SizeLimits bounds the encoded size of the messages, blocks and transactions a node accepts.
+/// The limits are checked on the encoded bytes before decoding them, so an oversized payload is rejected
+/// without allocating anything for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeLimits {
+    pub max_message_size: usize,
+    pub max_block_size: usize,
+    pub max_transaction_size: usize,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        SizeLimits {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            max_transaction_size: DEFAULT_MAX_TRANSACTION_SIZE,
+        }
+    }
+}
+
+impl SizeLimits {
+    /// Check that a transaction fits in a block and a block fits in a message
+    pub fn validate(&self) -> Result<()> {
+        if self.max_transaction_size == 0 {
+            return Err(MarvinError::Validation(String::from("Maximum transaction size must be greater than zero")));
+        }
+        if self.max_transaction_size > self.max_block_size {
+            return Err(MarvinError::Validation(String::from("Maximum transaction size exceeds the maximum block size")));
+        }
+        if self.max_block_size > self.max_message_size {
+            return Err(MarvinError::Validation(String::from("Maximum block size exceeds the maximum message size")));
+        }
+
+        Ok(())
+    }
+
+    /// Check the size of an encoded transaction
+    pub fn check_transaction(&self, data: &[u8]) -> Result<()> {
+        check_size("Transaction", data.len(), self.max_transaction_size)
+    }
+
+    /// Check the size of an encoded block and of every transaction it carries
+    pub fn check_block(&self, data: &[u8]) -> Result<()> {
+        check_size("Block", data.len(), self.max_block_size)?;
+
+        for field in Fields::new(data) {
+            if let (BLOCK_TRANSACTIONS_FIELD, Some(tx)) = field? {
+                self.check_transaction(tx)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the size of an encoded network message and of the blocks and transactions it carries
+    pub fn check_message(&self, data: &[u8]) -> Result<()> {
+        check_size("Message", data.len(), self.max_message_size)?;
+
+        for field in Fields::new(data) {
+            match field? {
+                (MESSAGE_BLOCK_FIELD, Some(block)) => self.check_block(block)?,
+                (MESSAGE_TRANSACTION_FIELD, Some(tx)) => self.check_transaction(tx)?,
+                (MESSAGE_COMPACT_BLOCK_FIELD, Some(compact)) => check_size("Compact block", compact.len(), self.max_block_size)?,
+                (MESSAGE_BLOCK_TRANSACTIONS_FIELD, Some(response)) => {
+                    check_size("Block transactions", response.len(), self.max_block_size)?;
+                    for field in Fields::new(response) {
+                        if let (BLOCK_TRANSACTIONS_TRANSACTIONS_FIELD, Some(tx)) = field? {
+                            self.check_transaction(tx)?;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn check_size(kind: &str, size: usize, max: usize) -> Result<()> {
+    if size > max {
+        return Err(MarvinError::Validation(format!("{} of {} bytes exceeds the maximum size of {} bytes", kind, size, max)));
+    }
+
+    Ok(())
+}
+
+/// Fields iterates over the top level fields of an encoded protobuf message without decoding them.
+/// Yields the field number along with the bytes of length delimited fields.
+struct Fields<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Fields { data, position: 0 }
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or_else(|| MarvinError::Validation(String::from("Truncated varint")))?;
+            self.position += 1;
+
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(MarvinError::Validation(String::from("Varint is too long")))
+    }
+
+    fn skip(&mut self, length: u64) -> Result<&'a [u8]> {
+        let remaining = (self.data.len() - self.position) as u64;
+        if length > remaining {
+            return Err(MarvinError::Validation(String::from("Field length exceeds the size of the message")));
+        }
+
+        let start = self.position;
+        self.position += length as usize;
+
+        Ok(&self.data[start..self.position])
+    }
+
+    fn read_field(&mut self) -> Result<(u32, Option<&'a [u8]>)> {
+        let key = self.read_varint()?;
+        let field = (key >> 3) as u32;
+
+        match key & 0x7 {
+            0 => self.read_varint().map(|_| (field, None)),
+            1 => self.skip(8).map(|_| (field, None)),
+            2 => {
+                let length = self.read_varint()?;
+                self.skip(length).map(|value| (field, Some(value)))
+            }
+            5 => self.skip(4).map(|_| (field, None)),
+            wire_type => Err(MarvinError::Validation(format!("Unsupported wire type {}", wire_type))),
+        }
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u32, Option<&'a [u8]>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.data.len() {
+            return None;
+        }
+
+        let field = self.read_field();
+        if field.is_err() {
+            // Stop at the first malformed field
+            self.position = self.data.len();
+        }
+
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto;
+
+    use prost::Message;
+
+    const LIMITS: SizeLimits = SizeLimits {
+        max_message_size: 4096,
+        max_block_size: 2048,
+        max_transaction_size: 512,
+    };
+
+    fn transaction(data_size: usize) -> proto::Transaction {
+        proto::Transaction {
+            data: vec![1; data_size],
+            value: 10,
+            nonce: 1,
+            ..Default::default()
+        }
+    }
+
+    fn block_message(transactions: Vec<proto::Transaction>) -> proto::Message {
+        proto::Message {
+            payload: Some(proto::message::Payload::Block(proto::Block {
+                header: Some(proto::Header { height: 1, ..Default::default() }),
+                transactions,
+                ..Default::default()
+            })),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(SizeLimits::default().validate().is_ok());
+        assert!(LIMITS.validate().is_ok());
+        assert!(SizeLimits { max_transaction_size: 4096, ..LIMITS }.validate().is_err());
+        assert!(SizeLimits { max_block_size: 8192, ..LIMITS }.validate().is_err());
+    }
+
+    #[test]
+    fn test_check_transaction_and_block() {
+        assert!(LIMITS.check_transaction(&transaction(100).encode_to_vec()).is_ok());
+        assert!(LIMITS.check_transaction(&transaction(600).encode_to_vec()).is_err());
+
+        let block = proto::Block {
+            transactions: vec![transaction(100), transaction(100)],
+            ..Default::default()
+        };
+        assert!(LIMITS.check_block(&block.encode_to_vec()).is_ok());
+
+        // Every transaction of the block is checked, not only the size of the whole block
+        let block = proto::Block {
+            transactions: vec![transaction(100), transaction(600)],
+            ..Default::default()
+        };
+        assert!(LIMITS.check_block(&block.encode_to_vec()).is_err());
+
+        let block = proto::Block {
+            transactions: (0..6).map(|_| transaction(400)).collect(),
+            ..Default::default()
+        };
+        assert!(LIMITS.check_block(&block.encode_to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_check_message() {
+        assert!(LIMITS.check_message(&block_message(vec![transaction(100)]).encode_to_vec()).is_ok());
+        assert!(LIMITS.check_message(&block_message(vec![transaction(600)]).encode_to_vec()).is_err());
+
+        let message = proto::Message {
+            payload: Some(proto::message::Payload::Transaction(transaction(600))),
+        };
+        assert!(LIMITS.check_message(&message.encode_to_vec()).is_err());
+
+        let message = proto::Message {
+            payload: Some(proto::message::Payload::BlockTransactions(proto::BlockTransactions {
+                block_hash: vec![0; 32],
+                transactions: vec![transaction(600)],
+            })),
+        };
+        assert!(LIMITS.check_message(&message.encode_to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_check_malformed_message() {
+        let mut data = block_message(vec![transaction(100)]).encode_to_vec();
+
+        // A length prefix pointing past the end of the message
+        data.truncate(data.len() - 1);
+        assert!(LIMITS.check_message(&data).is_err());
+
+        // A huge length prefix is rejected without allocating
+        let data = [(MESSAGE_BLOCK_FIELD << 3 | 2) as u8, 0xff, 0xff, 0xff, 0xff, 0x0f];
+        assert!(LIMITS.check_message(&data).is_err());
+    }
+}
@@ -1,2 +1,4 @@
 pub mod block;
-pub mod transaction;
\ No newline at end of file
+pub mod hash;
+pub mod limits;
+pub mod transaction;
@@ -1,6 +1,8 @@
-use crate::crypto::keys::{PrivateKey, PublicKey, SignatureWrapper};
-use crate::crypto::keys::{SIGNATURE_SIZE, PUBLIC_KEY_SIZE};
+use crate::crypto::keys::{Address, PrivateKey, PublicKey, SignatureWrapper};
+use crate::crypto::keys::SIGNATURE_SIZE;
 use crate::proto;
+use crate::types::hash::Hash;
+use crate::types::limits::SizeLimits;
 
 use crate::error::{Result, MarvinError};
 
@@ -13,13 +15,19 @@ use crypto::sha2::Sha256;
 /// Serialize a transaction
 pub fn serialize_transaction(t : proto::Transaction) -> Result<Vec<u8>> {
     let mut buf = Vec::new();
-    t.encode(&mut buf).map_err(|e| MarvinError::General(e.to_string()))?;
+    t.encode(&mut buf).map_err(|e| MarvinError::Internal(e.to_string()))?;
     Ok(buf)
 }
 
-/// Deserialize a transaction
+/// Deserialize a transaction, rejecting transactions larger than the default size limits
 pub fn deserialize_transaction(data: &[u8]) -> Result<proto::Transaction> {
-    proto::Transaction::decode(data).map_err(|e| MarvinError::General(e.to_string()))
+    deserialize_transaction_with_limits(data, &SizeLimits::default())
+}
+
+/// Deserialize a transaction, its size is checked before decoding
+pub fn deserialize_transaction_with_limits(data: &[u8], limits: &SizeLimits) -> Result<proto::Transaction> {
+    limits.check_transaction(data)?;
+    proto::Transaction::decode(data).map_err(|e| MarvinError::Validation(e.to_string()))
 }
 
 /// Hash a transaction
@@ -36,14 +44,48 @@ pub fn hash_transaction(t: &mut proto::Transaction) -> Vec<u8> {
     hash.to_vec()
 }
 
-/// Sign a transaction
+/// Calculate the signing hash of a transaction without mutating it.
+/// The signature and hash fields are zeroed before hashing, the same way `verify_transaction` does,
+/// so the result identifies the transaction regardless of whether it has been signed or not.
+pub fn calculate_transaction_hash(t: &proto::Transaction) -> Vec<u8> {
+    let mut unsigned = t.clone();
+    unsigned.signature = [0; SIGNATURE_SIZE].to_vec();
+    unsigned.hash = [0; 32].to_vec();
+
+    hash_transaction(&mut unsigned)
+}
+
+/// Returns the signing hash of a transaction as a typed `Hash`, see `calculate_transaction_hash`
+pub fn transaction_hash(t: &proto::Transaction) -> Hash {
+    Hash::from_bytes(&calculate_transaction_hash(t)).unwrap()
+}
+
+/// Returns the address of the sender of a transaction, if the sender public key is well formed
+pub fn sender_address(t: &proto::Transaction) -> Option<Address> {
+    Address::from_public_key_bytes(&t.from).ok()
+}
+
+/// Returns the address of the recipient of a transaction, if the recipient public key is well formed
+pub fn recipient_address(t: &proto::Transaction) -> Option<Address> {
+    Address::from_public_key_bytes(&t.to).ok()
+}
+
+/// Check if a transaction has expired for a block at the given height and timestamp (unix nanoseconds).
+/// A zero `valid_until_height` or `valid_until_timestamp` means the transaction does not expire on that criteria.
+pub fn is_expired(t: &proto::Transaction, height: u64, timestamp: i64) -> bool {
+    (t.valid_until_height != 0 && height > t.valid_until_height)
+        || (t.valid_until_timestamp != 0 && timestamp > t.valid_until_timestamp)
+}
+
+/// Sign a transaction, replacing any previous signature and hash so a modified transaction can be signed again
 pub fn sign_transaction(private_key: &mut PrivateKey, t: &mut proto::Transaction) -> Result<SignatureWrapper> {
-    let hash = hash_transaction(t);
-    let signature = private_key.sign(&hash).map_err(|e| MarvinError::General(e.to_string()))?;
+    t.from = private_key.public_key().to_bytes().to_vec();
+
+    let hash = calculate_transaction_hash(t);
+    let signature = private_key.sign(&hash).map_err(|e| MarvinError::Crypto(e.to_string()))?;
 
     t.signature = signature.to_bytes().to_vec();
     t.hash = hash;
-    t.from = private_key.public_key().to_bytes().to_vec();
 
     Ok(signature)
 }
@@ -51,11 +93,11 @@ pub fn sign_transaction(private_key: &mut PrivateKey, t: &mut proto::Transaction
 /// Verify a transaction
 pub fn verify_transaction(t: &mut proto::Transaction) -> Result<bool> {
     if t.signature.is_empty() {
-        return Err(MarvinError::General(String::from("Transaction is not signed".to_string())));
+        return Err(MarvinError::Crypto(String::from("Transaction is not signed")));
     }
 
     if t.signature.len() != SIGNATURE_SIZE {
-        return Err(MarvinError::Internal(String::from("Invalid signature size".to_string())));
+        return Err(MarvinError::Crypto(String::from("Invalid signature size")));
     }
 
     let temp_sig = t.signature.clone();
@@ -69,12 +111,12 @@ pub fn verify_transaction(t: &mut proto::Transaction) -> Result<bool> {
     t.signature = temp_sig;
     t.hash = temp_hash;
 
-    let signature = SignatureWrapper::from_bytes(&t.signature).unwrap();
-    let public_key = PublicKey::from_bytes(&t.from).unwrap();
+    let signature = SignatureWrapper::from_bytes(&t.signature)?;
+    let public_key = PublicKey::from_bytes(&t.from)?;
     
     let is_valid = signature.verify(&hash, &public_key);
     if !is_valid {
-        return Err(MarvinError::General(String::from("Invalid signature".to_string())));
+        return Err(MarvinError::Crypto(String::from("Invalid signature")));
     }
 
     Ok(is_valid)
@@ -88,12 +130,12 @@ mod tests {
     #[test]
     fn test_serialize_transaction() {
         let mnemonic_to = "all wild paddle pride wheat menu task funny sign profit blouse hockey";
-        let private_key_to = keys::get_private_key_from_mnemonic(&mnemonic_to).unwrap();
+        let private_key_to = keys::get_private_key_from_mnemonic(mnemonic_to).unwrap();
         let public_key_to = private_key_to.public_key();
         // let address_to = public_key_to.address();
 
         let mnemonic_from = "hello wild paddle pride wheat menu task funny sign profit blouse hockey";
-        let private_key_from = keys::get_private_key_from_mnemonic(&mnemonic_from).unwrap();
+        let private_key_from = keys::get_private_key_from_mnemonic(mnemonic_from).unwrap();
         let public_key_from = private_key_from.public_key();
         // let address_from = public_key_from.address();
 
@@ -105,6 +147,8 @@ mod tests {
             signature: [0; 64].to_vec(),
             nonce: 123,
             hash: [0; 32].to_vec(),
+            valid_until_height: 0,
+            valid_until_timestamp: 0,
         };
 
         let data = serialize_transaction(tx.clone()).unwrap();
@@ -117,7 +161,7 @@ mod tests {
     fn test_sign_transaction() {
         let mut private_key_from = keys::generate_private_key();
         let public_key_from = private_key_from.public_key();
-        let mut private_key_to = keys::generate_private_key();
+        let private_key_to = keys::generate_private_key();
         let public_key_to = private_key_to.public_key();
 
         let mut tx = proto::Transaction {
@@ -128,6 +172,8 @@ mod tests {
             signature: [0; 64].to_vec(),
             nonce: 123,
             hash: [0; 32].to_vec(),
+            valid_until_height: 0,
+            valid_until_timestamp: 0,
         }; 
 
         let signature = sign_transaction(&mut private_key_from, &mut tx).unwrap();
@@ -136,4 +182,52 @@ mod tests {
         let is_valid = verify_transaction(&mut tx).unwrap();
         assert!(is_valid);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_calculate_transaction_hash() {
+        let mut private_key = keys::generate_private_key();
+        let public_key = private_key.public_key();
+
+        let mut tx = proto::Transaction {
+            from: public_key.to_bytes().to_vec(),
+            to: public_key.to_bytes().to_vec(),
+            value: 1000,
+            data: b"Transaction data".to_vec(),
+            signature: [0; 64].to_vec(),
+            nonce: 123,
+            hash: [0; 32].to_vec(),
+            valid_until_height: 0,
+            valid_until_timestamp: 0,
+        };
+        let unsigned_hash = calculate_transaction_hash(&tx);
+
+        sign_transaction(&mut private_key, &mut tx).unwrap();
+        let signed = tx.clone();
+
+        // The hash is stable across signing and does not touch the transaction
+        assert_eq!(calculate_transaction_hash(&tx), unsigned_hash);
+        assert_eq!(tx.hash, unsigned_hash);
+        assert_eq!(tx, signed);
+
+        // Signing again after a change produces a valid transaction
+        tx.valid_until_height = 10;
+        sign_transaction(&mut private_key, &mut tx).unwrap();
+        assert!(verify_transaction(&mut tx).unwrap());
+        assert_ne!(tx.hash, unsigned_hash);
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let mut tx = proto::Transaction::default();
+        assert!(!is_expired(&tx, u64::MAX, i64::MAX));
+
+        tx.valid_until_height = 10;
+        assert!(!is_expired(&tx, 10, 0));
+        assert!(is_expired(&tx, 11, 0));
+
+        tx.valid_until_height = 0;
+        tx.valid_until_timestamp = 1627483623;
+        assert!(!is_expired(&tx, 100, 1627483623));
+        assert!(is_expired(&tx, 100, 1627483624));
+    }
+}
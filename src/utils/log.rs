@@ -1,5 +1,13 @@
 use slog::*;
-use std::sync::Mutex;
+use std::sync::{Mutex, OnceLock};
+
+// Minimum level of the records written by the loggers, every record is written if it is not set
+static LOG_LEVEL: OnceLock<Level> = OnceLock::new();
+
+// Sets the minimum level of the records written by the loggers created afterwards, it can only be set once
+pub fn set_log_level(level: Level) -> bool {
+    LOG_LEVEL.set(level).is_ok()
+}
 
 pub fn make_json_logger() -> Logger {
     // let term_decorator = slog_term::TermDecorator::new().build();
@@ -8,6 +16,7 @@ pub fn make_json_logger() -> Logger {
 
     let json_drain = slog_json::Json::default(std::io::stdout()).fuse();
     let json_drain = Mutex::new(json_drain).fuse();
+    let json_drain = LevelFilter::new(json_drain, LOG_LEVEL.get().copied().unwrap_or(Level::Trace));
 
     // let logger = Logger::root(slog::Duplicate(term_drain, json_drain).fuse(), o!());
     let logger = Logger::root(json_drain.fuse(), o!());